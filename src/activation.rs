@@ -0,0 +1,150 @@
+use config::ActivationConfig;
+use errors::*;
+use logging;
+use proxy;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use supervisor::{ChildState, Supervisor};
+
+const INITIATOR: &str = "socket activation";
+
+// how often the target is tried while the child starts up, and how often the idle timeout is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// open connections, and when the last one closed
+struct Activity {
+    connections: usize,
+    last_closed: Instant,
+}
+
+struct Activation {
+    name: String,
+    config: ActivationConfig,
+    supervisor: Arc<Supervisor>,
+    activity: Mutex<Activity>,
+}
+
+impl Activation {
+    // starts the child unless it already runs, then waits for it to accept on the target
+    fn connect_target(&self) -> Result<TcpStream> {
+        if self.supervisor.state_of(&self.name) == Some(ChildState::Stopped) {
+            // a concurrent connection may have started it in the meantime
+            if let Err(e) = self.supervisor.start_command(&self.name, INITIATOR) {
+                debug!(target: &logging::target(&self.name), "Not starting [{}] for a connection: {}", self.name, e);
+            }
+        }
+
+        let deadline = Instant::now() + self.config.ready_timeout;
+
+        loop {
+            let addrs = self.config.target.to_socket_addrs()
+                .chain_err(|| format!("Unable to resolve activation target {}", self.config.target))?;
+
+            let mut last_error = None;
+
+            for addr in addrs {
+                match TcpStream::connect_timeout(&addr, POLL_INTERVAL) {
+                    Ok(stream) => return Ok(stream),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+
+            if Instant::now() >= deadline {
+                bail!("[{}] did not accept on {} within {:?}, last error: {:?}",
+                    self.name, self.config.target, self.config.ready_timeout, last_error);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn serve_client(&self, client: TcpStream) -> Result<()> {
+        let upstream = self.connect_target()?;
+        let _ = client.set_nodelay(true);
+        let _ = upstream.set_nodelay(true);
+
+        proxy::pipe(client, upstream)
+            .chain_err(|| format!("Connection to [{}] failed", self.name))?;
+
+        Ok(())
+    }
+
+    fn stop_when_idle(&self, idle_timeout: Duration) {
+        loop {
+            thread::sleep(IDLE_CHECK_INTERVAL);
+
+            let idle = {
+                let activity = self.activity.lock().unwrap();
+                activity.connections == 0 && activity.last_closed.elapsed() >= idle_timeout
+            };
+
+            let running = match self.supervisor.state_of(&self.name) {
+                Some(ChildState::Stopped) => false,
+                Some(_) => true,
+                None => break,
+            };
+
+            if idle && running {
+                info!(target: &logging::target(&self.name),
+                    "No connections to [{}] for {:?}, stopping it until the next one", self.name, idle_timeout);
+
+                if let Err(e) = self.supervisor.stop_command(&self.name, INITIATOR) {
+                    warn!(target: &logging::target(&self.name), "Unable to stop idle [{}]: {}", self.name, e);
+                }
+            }
+        }
+    }
+}
+
+// listens in place of the manual command, starting it on the first connection and proxying every connection to it
+pub fn serve(name: String, config: ActivationConfig, supervisor: Arc<Supervisor>) -> Result<()> {
+    let listener = TcpListener::bind(&config.listen)
+        .chain_err(|| format!("Unable to listen on {} for the activation of [{}]", config.listen, name))?;
+
+    info!(target: &logging::target(&name), "Listening on {} to start [{}] on the first connection",
+        config.listen, name);
+
+    let activation = Arc::new(Activation {
+        name: name,
+        config: config,
+        supervisor: supervisor,
+        activity: Mutex::new(Activity { connections: 0, last_closed: Instant::now() }),
+    });
+
+    if let Some(idle_timeout) = activation.config.idle_timeout {
+        let activation = activation.clone();
+        let _ = thread::spawn(move || activation.stop_when_idle(idle_timeout));
+    }
+
+    let _ = thread::spawn(move || {
+        for client in listener.incoming() {
+            let client = match client {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!(target: &logging::target(&activation.name), "Unable to accept a connection for [{}]: {}",
+                        activation.name, e);
+                    continue;
+                },
+            };
+
+            let activation = activation.clone();
+
+            let _ = thread::spawn(move || {
+                activation.activity.lock().unwrap().connections += 1;
+
+                if let Err(e) = activation.serve_client(client) {
+                    warn!("{}", e);
+                }
+
+                let mut activity = activation.activity.lock().unwrap();
+                activity.connections -= 1;
+                activity.last_closed = Instant::now();
+            });
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// the crash alerts of a command are held back for the window after one went out, the first one after the window
+// then stands for every crash since, so that a crash loop escalates with a growing count instead of flooding
+pub struct CrashAlerts {
+    window: Option<Duration>,
+    commands: Mutex<HashMap<String, Held>>,
+}
+
+struct Held {
+    alerted: Instant,
+
+    // since the last alert, which does not count itself
+    crashes: u32,
+}
+
+impl CrashAlerts {
+    pub fn new(window: Option<Duration>) -> CrashAlerts {
+        CrashAlerts {
+            window: window,
+            commands: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // how many crashes the alert stands for, none if it is held back
+    pub fn crashed(&self, command: &str) -> Option<u32> {
+        let window = match self.window {
+            Some(window) => window,
+            None => return Some(1),
+        };
+
+        let mut commands = self.commands.lock().unwrap();
+
+        if let Some(held) = commands.get_mut(command) {
+            if held.alerted.elapsed() < window {
+                held.crashes += 1;
+                return None;
+            }
+        }
+
+        let crashes = commands.get(command).map(|held| held.crashes).unwrap_or(0) + 1;
+
+        commands.insert(command.to_owned(), Held {
+            alerted: Instant::now(),
+            crashes: 0,
+        });
+
+        Some(crashes)
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::Local;
+use errors::*;
+use serde_json;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// who caused an audited action
+pub const SUPERVISOR: &str = "supervisor";
+pub const SCM: &str = "scm";
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    action: &'a str,
+    initiator: &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<&'a str>,
+
+    detail: &'a str,
+}
+
+// append only json lines of every privileged action, separate from the service log
+pub struct Audit {
+    file: Option<Mutex<File>>,
+}
+
+impl Audit {
+    pub fn disabled() -> Audit {
+        Audit { file: None }
+    }
+
+    pub fn open(path: &Path) -> Result<Audit> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .chain_err(|| format!("Unable to open audit log {:?}", path))?;
+
+        Ok(Audit { file: Some(Mutex::new(file)) })
+    }
+
+    pub fn record(&self, action: &str, initiator: &str, command: Option<&str>, detail: &str) {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return,
+        };
+
+        let record = Record {
+            time: Local::now().to_rfc3339(),
+            action: action,
+            initiator: initiator,
+            command: command,
+            detail: detail,
+        };
+
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Unable to serialize audit record: {}", e);
+                return;
+            },
+        };
+
+        line.push('\n');
+
+        // a single write per record keeps the lines whole
+        if let Err(e) = file.lock().unwrap().write_all(line.as_bytes()) {
+            error!("Unable to write audit record [{}]: {}", line.trim(), e);
+        }
+    }
+}
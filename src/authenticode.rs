@@ -0,0 +1,108 @@
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::ptr;
+use win::to_wide;
+use winapi::shared::minwindef::DWORD;
+use winapi::um::softpub::WINTRUST_ACTION_GENERIC_VERIFY_V2;
+use winapi::um::wincrypt::{CertCloseStore, CertFindCertificateInStore, CertFreeCertificateContext, CertGetNameStringW,
+    CryptMsgClose, CryptMsgGetParam, CryptQueryObject, CERT_FIND_SUBJECT_CERT, CERT_INFO, CERT_NAME_SIMPLE_DISPLAY_TYPE,
+    CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED, CERT_QUERY_FORMAT_FLAG_BINARY, CERT_QUERY_OBJECT_FILE, CMSG_SIGNER_INFO,
+    CMSG_SIGNER_INFO_PARAM, HCERTSTORE, HCRYPTMSG, PKCS_7_ASN_ENCODING, X509_ASN_ENCODING};
+use winapi::um::wintrust::{WinVerifyTrust, WINTRUST_DATA, WINTRUST_FILE_INFO, WTD_CHOICE_FILE, WTD_REVOKE_NONE,
+    WTD_STATEACTION_CLOSE, WTD_STATEACTION_VERIFY, WTD_UI_NONE};
+
+// checks the embedded signature chains up to a trusted root and matches the file,
+// revocation is not checked so that machines without internet access still verify
+fn verify_trust(wide_path: &[u16]) -> io::Result<()> {
+    unsafe {
+        let mut file_info: WINTRUST_FILE_INFO = mem::zeroed();
+        file_info.cbStruct = mem::size_of::<WINTRUST_FILE_INFO>() as DWORD;
+        file_info.pcwszFilePath = wide_path.as_ptr();
+
+        let mut data: WINTRUST_DATA = mem::zeroed();
+        data.cbStruct = mem::size_of::<WINTRUST_DATA>() as DWORD;
+        data.dwUIChoice = WTD_UI_NONE;
+        data.fdwRevocationChecks = WTD_REVOKE_NONE;
+        data.dwUnionChoice = WTD_CHOICE_FILE;
+        *data.u.pFile_mut() = &mut file_info;
+        data.dwStateAction = WTD_STATEACTION_VERIFY;
+
+        let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+        let status = WinVerifyTrust(ptr::null_mut(), &mut action, &mut data as *mut WINTRUST_DATA as *mut _);
+
+        data.dwStateAction = WTD_STATEACTION_CLOSE;
+        WinVerifyTrust(ptr::null_mut(), &mut action, &mut data as *mut WINTRUST_DATA as *mut _);
+
+        if status != 0 {
+            return Err(io::Error::from_raw_os_error(status));
+        }
+    }
+
+    Ok(())
+}
+
+// display name of the certificate that signed the file, normally its common name
+fn signer_name(wide_path: &[u16]) -> io::Result<String> {
+    let encoding = X509_ASN_ENCODING | PKCS_7_ASN_ENCODING;
+
+    unsafe {
+        let mut store: HCERTSTORE = ptr::null_mut();
+        let mut msg: HCRYPTMSG = ptr::null_mut();
+
+        if CryptQueryObject(CERT_QUERY_OBJECT_FILE, wide_path.as_ptr() as *const _,
+            CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED, CERT_QUERY_FORMAT_FLAG_BINARY, 0,
+            ptr::null_mut(), ptr::null_mut(), ptr::null_mut(), &mut store, &mut msg, ptr::null_mut()) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let name = (|| {
+            let mut len: DWORD = 0;
+
+            if CryptMsgGetParam(msg, CMSG_SIGNER_INFO_PARAM, 0, ptr::null_mut(), &mut len) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // u64 elements keep the signer info aligned
+            let mut buf = vec![0u64; (len as usize + 7) / 8];
+
+            if CryptMsgGetParam(msg, CMSG_SIGNER_INFO_PARAM, 0, buf.as_mut_ptr() as *mut _, &mut len) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let signer_info = &*(buf.as_ptr() as *const CMSG_SIGNER_INFO);
+
+            let mut cert_info: CERT_INFO = mem::zeroed();
+            cert_info.Issuer = signer_info.Issuer;
+            cert_info.SerialNumber = signer_info.SerialNumber;
+
+            let cert = CertFindCertificateInStore(store, encoding, 0, CERT_FIND_SUBJECT_CERT,
+                &cert_info as *const CERT_INFO as *const _, ptr::null());
+
+            if cert.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut name = [0u16; 256];
+            let len = CertGetNameStringW(cert, CERT_NAME_SIMPLE_DISPLAY_TYPE, 0, ptr::null_mut(),
+                name.as_mut_ptr(), name.len() as DWORD);
+
+            CertFreeCertificateContext(cert);
+
+            // the length includes the terminating null
+            Ok(String::from_utf16_lossy(&name[..(len as usize).saturating_sub(1)]))
+        })();
+
+        CryptMsgClose(msg);
+        CertCloseStore(store, 0);
+        name
+    }
+}
+
+// the publisher of a validly signed file, or an error if it is unsigned, tampered with or untrusted
+pub fn publisher(path: &Path) -> io::Result<String> {
+    let wide_path = to_wide(path);
+
+    verify_trust(&wide_path)?;
+    signer_name(&wide_path)
+}
@@ -0,0 +1,40 @@
+use session::SessionChild;
+use shared_child::SharedChild;
+use std::io;
+use std::process::ExitStatus;
+
+// a running child, however it was spawned
+pub enum Child {
+    Local(SharedChild),
+    Session(SessionChild),
+}
+
+impl Child {
+    pub fn id(&self) -> u32 {
+        match *self {
+            Child::Local(ref child) => child.id(),
+            Child::Session(ref child) => child.id(),
+        }
+    }
+
+    pub fn wait(&self) -> io::Result<ExitStatus> {
+        match *self {
+            Child::Local(ref child) => child.wait(),
+            Child::Session(ref child) => child.wait(),
+        }
+    }
+
+    pub fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        match *self {
+            Child::Local(ref child) => child.try_wait(),
+            Child::Session(ref child) => child.try_wait(),
+        }
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        match *self {
+            Child::Local(ref child) => child.kill(),
+            Child::Session(ref child) => child.kill(),
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use config::{EffectiveConfig, FileConfig};
+use control;
+use errors::*;
+use lint;
+use logging;
+use migrate;
+use paths::ServicePaths;
+use profile;
+use schema;
+use selftest;
+use serde_json;
+use update;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use vars::Variables;
+use version;
+use win;
+use winapi::shared::winerror::ERROR_LOGON_TYPE_NOT_GRANTED;
+use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+// verbs are given as the first executable argument, the service itself never gets any
+pub fn run(args: &[String]) -> Option<i32> {
+    let verb = args.first()?;
+
+    // the executable has no console of its own
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+
+    let res = match verb.as_str() {
+        "status" | "drain" | "maintenance" | "reload" | "history" | "log-level" | "restart" | "start" => send(&args.join(" ")),
+        "print-config" if args.len() == 1 => send("print-config"),
+        "print-config" => print_config(&args[1..]),
+        "tail" => tail(&args.join(" ")),
+        "--check-config" => check_config(&args[1..]),
+        "--version" => print_version(),
+        "schema" => print_schema(),
+        "init" => init_config(),
+        "migrate-config" => migrate_config(),
+        "update" => update_now(),
+        "powershell-module" => write_powershell_module(),
+        "set-credentials" => set_credentials(&args[1..]),
+        "self-test" => selftest::run(&args[1..]),
+        _ => Err(format!("Unknown verb: {}", verb).into()),
+    };
+
+    match res {
+        Ok(_) => Some(0),
+        Err(ref e) => {
+            eprintln!("Error: {}", e);
+
+            for e in e.iter().skip(1) {
+                eprintln!("- Caused by: {}", e);
+            }
+
+            Some(1)
+        },
+    }
+}
+
+fn send(request: &str) -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+    let response = control::request(&paths.pipe_name(), request)?;
+
+    if let Some(error) = response.error {
+        bail!(error);
+    }
+
+    if let Some(result) = response.result.filter(|result| !result.is_null()) {
+        let pretty = serde_json::to_string_pretty(&result)
+            .chain_err(|| "Unable to format control response")?;
+
+        println!("{}", pretty);
+    }
+
+    Ok(())
+}
+
+// prints the lines as they are, following the log until interrupted if asked to
+fn tail(request: &str) -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+    let mut error = None;
+
+    control::request_stream(&paths.pipe_name(), request, |response| {
+        if let Some(e) = response.error {
+            error = Some(e);
+            return false;
+        }
+
+        let lines = response.result.as_ref()
+            .and_then(|result| result["lines"].as_array())
+            .map(|lines| lines.iter().filter_map(|line| line.as_str()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        for line in lines {
+            println!("{}", line);
+        }
+
+        true
+    })?;
+
+    match error {
+        Some(error) => bail!(error),
+        None => Ok(()),
+    }
+}
+
+// the variables with the profile the service would use, or the one given like a start argument
+fn variables(paths: &ServicePaths, args: &[String]) -> Result<Variables> {
+    Ok(Variables::new(paths).with_profile(profile::selected(args, &paths.name)?))
+}
+
+// the config file resolved like the service would, with `--file` instead of asking the running service,
+// e.g. to see what a profile changes before starting with it
+fn print_config(args: &[String]) -> Result<()> {
+    let profile_args = match args.split_first() {
+        Some((flag, rest)) if flag == "--file" => rest,
+        _ => bail!("Expected print-config for the running service, or print-config --file [--profile <name>]"),
+    };
+
+    let paths = ServicePaths::from_current_exe()?;
+    let variables = variables(&paths, profile_args)?;
+    let (service_config, cmds) = FileConfig::load(&paths.config_file, &variables)?.into_parts();
+
+    let effective = EffectiveConfig::new(&paths.config_file, &variables, &service_config, &cmds)?;
+    let pretty = serde_json::to_string_pretty(&effective)
+        .chain_err(|| "Unable to format the effective config")?;
+
+    println!("{}", pretty);
+    Ok(())
+}
+
+// loads the config exactly as the service would, without starting anything
+fn check_config(args: &[String]) -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+    let variables = variables(&paths, args)?;
+    let (_, cmds) = FileConfig::load(&paths.config_file, &variables)?.into_parts();
+    let warnings = lint::lint(&cmds);
+
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+
+    let profile = variables.profile().map(|profile| format!(" in profile {}", profile)).unwrap_or_default();
+
+    println!("Config {:?}{} is valid with {} command(s) and {} warning(s)",
+        paths.config_file, profile, cmds.len(), warnings.len());
+    Ok(())
+}
+
+fn print_version() -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+    println!("{} {}", paths.name, version::describe());
+    Ok(())
+}
+
+fn print_schema() -> Result<()> {
+    let pretty = serde_json::to_string_pretty(&schema::schema())
+        .chain_err(|| "Unable to format config schema")?;
+
+    println!("{}", pretty);
+    Ok(())
+}
+
+const SAMPLE_CONFIG: &str = include_str!("../config/sample.toml");
+
+// writes the commented sample config next to the executable, never overwriting an existing one
+fn init_config() -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&paths.config_file)
+        .chain_err(|| format!("Unable to create config file {:?}, it may already exist", paths.config_file))?;
+
+    file.write_all(SAMPLE_CONFIG.replace("<exe>", &format!("{}.exe", paths.name)).as_bytes())
+        .chain_err(|| format!("Unable to write config file {:?}", paths.config_file))?;
+
+    println!("Wrote sample config to {:?}", paths.config_file);
+    Ok(())
+}
+
+const POWERSHELL_MODULE: &str = include_str!("../powershell/WindowsService.psm1");
+
+// writes the powershell module talking to the control pipe of this service next to the executable,
+// replacing the one of an older version
+fn write_powershell_module() -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+    let module = paths.exe_dir.join(format!("{}.psm1", paths.name));
+
+    fs::write(&module, POWERSHELL_MODULE.replace("<service>", &paths.name))
+        .chain_err(|| format!("Unable to write PowerShell module {:?}", module))?;
+
+    println!("Wrote PowerShell module to {:?}, load it with Import-Module", module);
+    Ok(())
+}
+
+// rewrites the legacy cmds list in place, keeping the original as <exe>.toml.bak
+fn migrate_config() -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+
+    let text = fs::read_to_string(&paths.config_file)
+        .chain_err(|| format!("Unable to read config file {:?}", paths.config_file))?;
+
+    let migrated = match migrate::migrate(&text)? {
+        Some(migrated) => migrated,
+        None => {
+            println!("Config {:?} has no legacy cmds to migrate", paths.config_file);
+            return Ok(());
+        },
+    };
+
+    let backup = paths.config_file.with_extension("toml.bak");
+
+    fs::copy(&paths.config_file, &backup)
+        .chain_err(|| format!("Unable to back up config file to {:?}", backup))?;
+
+    fs::write(&paths.config_file, migrated)
+        .chain_err(|| format!("Unable to write config file {:?}", paths.config_file))?;
+
+    println!("Migrated config {:?}, the original is kept as {:?}", paths.config_file, backup);
+    Ok(())
+}
+
+// stages an update right away, instead of waiting for the service to check
+fn update_now() -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+    let (service_config, _) = FileConfig::load(&paths.config_file, &variables(&paths, &[])?)?.into_parts();
+
+    // the staged update is logged along with the service log
+    let log_file = service_config.log_file.as_ref().map(PathBuf::from).unwrap_or_else(|| paths.log_file.clone());
+    logging::init_console(&log_file)?;
+
+    let update_manifest = match service_config.update_manifest {
+        Some(update_manifest) => update_manifest,
+        None => bail!("No update_manifest is configured in {:?}", paths.config_file),
+    };
+
+    match update::check(&update_manifest, service_config.update_publisher.as_deref())? {
+        Some(version) => println!("Update to version {} is staged, restart the service to apply it", version),
+        None => println!("No update is available, or one is already staged"),
+    }
+
+    Ok(())
+}
+
+// accounts the scm logs on without a password
+fn is_builtin_account(account: &str) -> bool {
+    let account = account.to_lowercase();
+
+    account == "localsystem" || account.starts_with("nt authority\\") || account.starts_with("nt service\\")
+        || account.ends_with('$')
+}
+
+// typed without echo, or the first line piped in, e.g. by a script fetching it from a vault
+fn read_password(prompt: &str) -> Result<String> {
+    let hidden = win::hide_console_input();
+
+    if hidden.is_some() {
+        eprint!("{}", prompt);
+    }
+
+    let mut line = String::new();
+    let res = io::stdin().lock().read_line(&mut line);
+
+    if hidden.is_some() {
+        eprintln!();
+    }
+
+    res.chain_err(|| "Unable to read the password")?;
+    Ok(line.trim_end_matches(|c| c == '\r' || c == '\n').to_owned())
+}
+
+// rotates the password of the account the service logs on as, or switches it to the given account,
+// logging on as it first so that a typo or a missing right does not leave a service that fails to start
+fn set_credentials(args: &[String]) -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+
+    let account = match args {
+        [] => win::service_account(&paths.name)
+            .chain_err(|| format!("Unable to query the account of service {}", paths.name))?,
+        [account] => account.clone(),
+        _ => bail!("Expected at most the account to log on as, e.g. set-credentials .\\svc_app"),
+    };
+
+    if is_builtin_account(&account) {
+        bail!("Service {} logs on as {}, which has no password, give the account to switch to", paths.name, account);
+    }
+
+    let password = read_password(&format!("Password of {}: ", account))?;
+
+    if password.is_empty() {
+        bail!("No password given");
+    }
+
+    if let Err(e) = win::check_service_logon(&account, &password) {
+        if e.raw_os_error() == Some(ERROR_LOGON_TYPE_NOT_GRANTED as i32) {
+            bail!("{} lacks the \"Log on as a service\" right, grant it in secpol.msc under User Rights Assignment", account);
+        }
+
+        return Err(e).chain_err(|| format!("Unable to log on as {} with the password", account));
+    }
+
+    win::set_service_credentials(&paths.name, &account, &password)
+        .chain_err(|| format!("Unable to change the credentials of service {}", paths.name))?;
+
+    // the rotation is logged along with the service log, even if the config does not load right now
+    let log_file = variables(&paths, &[])
+        .and_then(|variables| FileConfig::load(&paths.config_file, &variables))
+        .ok()
+        .and_then(|config| config.into_parts().0.log_file)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| paths.log_file.clone());
+    logging::init_console(&log_file)?;
+    info!("Changed the credentials of service {} to account {}", paths.name, account);
+
+    println!("Service {} logs on as {} from its next start", paths.name, account);
+    Ok(())
+}
@@ -0,0 +1,285 @@
+use config::{CommandConfig, CommandKind, CreationFlag};
+use std::env;
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use winapi::shared::minwindef::DWORD;
+use winapi::um::winbase::{BELOW_NORMAL_PRIORITY_CLASS, CREATE_DEFAULT_ERROR_MODE, CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW,
+    DETACHED_PROCESS};
+
+// characters interpreted by cmd.exe even within an argument
+const CMD_METACHARS: &[char] = &['(', ')', '%', '!', '^', '"', '<', '>', '&', '|'];
+
+// quotes an argument so that CommandLineToArgvW and the msvcrt parse it back as is
+pub fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c == ' ' || c == '\t' || c == '\n' || c == '\x0b' || c == '"') {
+        return arg.to_owned();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // backslashes preceding a quote must be escaped, and so must the quote
+                quoted.extend((0..backslashes * 2 + 1).map(|_| '\\'));
+                quoted.push('"');
+                backslashes = 0;
+                continue;
+            },
+            _ => (),
+        }
+
+        if c != '\\' {
+            quoted.extend((0..backslashes).map(|_| '\\'));
+            backslashes = 0;
+            quoted.push(c);
+        }
+    }
+
+    // backslashes preceding the closing quote must be escaped
+    quoted.extend((0..backslashes * 2).map(|_| '\\'));
+    quoted.push('"');
+    quoted
+}
+
+// escapes every cmd.exe metacharacter with ^, including the quotes,
+// so that cmd passes the argument through untouched instead of expanding or splitting it
+pub fn escape_cmd(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if CMD_METACHARS.contains(&c) {
+            escaped.push('^');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+// splits a shell command line into the program, unquoted, and the rest of the line
+pub fn split_program(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+
+    if line.starts_with('"') {
+        line[1..].find('"').map(|end| (&line[1..end + 1], &line[end + 2..]))
+    } else {
+        let end = line.find(char::is_whitespace).unwrap_or_else(|| line.len());
+        Some((&line[..end], &line[end..]))
+    }
+}
+
+// mirrors the lookup of cmd.exe, relative to the working directory first and then PATH,
+// trying every PATHEXT extension for programs given without one
+pub fn find_program(program: &str, cwd: Option<&Path>) -> Option<PathBuf> {
+    let exts = env::var("PATHEXT")
+        .unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_owned())
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| ext.to_owned())
+        .collect::<Vec<_>>();
+
+    let find = |path: &Path| {
+        if path.is_file() {
+            return Some(path.to_owned());
+        }
+
+        exts.iter()
+            .map(|ext| {
+                let mut with_ext = path.as_os_str().to_owned();
+                with_ext.push(ext);
+                PathBuf::from(with_ext)
+            })
+            .find(|with_ext| with_ext.is_file())
+    };
+
+    let path = Path::new(program);
+
+    if path.has_root() || program.contains(|c| c == '/' || c == '\\') {
+        return match cwd {
+            Some(cwd) => find(&cwd.join(path)),
+            None => find(path),
+        };
+    }
+
+    let cwd_dir = cwd.map(|cwd| cwd.to_owned()).or_else(|| env::current_dir().ok());
+
+    let path_dirs = env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    cwd_dir.into_iter().chain(path_dirs).filter_map(|dir| find(&dir.join(path))).next()
+}
+
+// the file actually run, the executable found like cmd.exe would, or the script of the powershell_script kind
+pub fn target_file(config: &CommandConfig) -> Option<PathBuf> {
+    match config.kind {
+        CommandKind::Shell => split_program(&config.cmd)
+            .and_then(|(program, _)| find_program(program, config.cwd.as_ref().map(Path::new))),
+
+        CommandKind::PowershellScript => config.script.as_ref().map(PathBuf::from),
+    }
+}
+
+// file name of the process image, which windows error reporting keys its settings on
+pub fn image_name(config: &CommandConfig) -> Option<String> {
+    match config.kind {
+        CommandKind::Shell => target_file(config)
+            .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned())),
+
+        CommandKind::PowershellScript => Some("powershell.exe".to_owned()),
+    }
+}
+
+// the extra arguments made literal, each preceded by a space
+fn extra_args(config: &CommandConfig, escape: bool) -> String {
+    config.args.iter().fold(String::new(), |mut line, arg| {
+        let quoted = quote_arg(arg);
+        line.push(' ');
+        line.push_str(&if escape { escape_cmd(&quoted) } else { quoted });
+        line
+    })
+}
+
+// the shell command line as given, followed by the extra arguments
+fn shell_line(config: &CommandConfig, escape: bool) -> String {
+    format!("{}{}", config.cmd, extra_args(config, escape))
+}
+
+pub fn creation_flags(config: &CommandConfig) -> DWORD {
+    // the service runs with error dialogs suppressed, which children inherit unless given the default mode
+    let error_mode = if config.suppress_error_dialogs { 0 } else { CREATE_DEFAULT_ERROR_MODE };
+
+    config.creation_flags.iter().fold(error_mode, |bits, flag| bits | match *flag {
+        CreationFlag::CreateNoWindow => CREATE_NO_WINDOW,
+        CreationFlag::CreateNewProcessGroup => CREATE_NEW_PROCESS_GROUP,
+        CreationFlag::DetachedProcess => DETACHED_PROCESS,
+        CreationFlag::BelowNormalPriority => BELOW_NORMAL_PRIORITY_CLASS,
+    })
+}
+
+// the program and the raw argument line to start it with,
+// without a shell the program of the command line is started directly with the rest as its arguments
+pub fn command_line(config: &CommandConfig, shell: bool) -> (String, String) {
+    match config.kind {
+        CommandKind::Shell if !shell => {
+            let (program, rest) = split_program(&config.cmd).unwrap_or((&config.cmd, ""));
+
+            // the rest of the line is passed on as written, like cmd.exe would
+            (program.to_owned(), format!("{}{}", rest.trim_start(), extra_args(config, false)))
+        },
+
+        // with /S, cmd strips only the outermost quotes and runs the rest verbatim,
+        // whereas the default quoting would escape inner quotes in a way cmd does not understand
+        CommandKind::Shell => {
+            // the console of the child is the one cmd.exe gets, so chcp applies to whatever it runs
+            let chcp = config.console_codepage().map(|codepage| format!("chcp {} >nul & ", codepage)).unwrap_or_default();
+            ("cmd.exe".to_owned(), format!("/D /S /C \"{}{}\"", chcp, shell_line(config, true)))
+        },
+
+        CommandKind::PowershellScript => {
+            let script = config.script.as_ref().map(|script| script.as_str()).unwrap_or("");
+
+            let args = ["-NoProfile", "-NonInteractive", "-ExecutionPolicy", &config.execution_policy, "-File", script]
+                .iter()
+                .map(|arg| *arg)
+                .chain(config.args.iter().map(|arg| arg.as_str()))
+                .map(quote_arg)
+                .collect::<Vec<_>>();
+
+            ("powershell.exe".to_owned(), args.join(" "))
+        },
+    }
+}
+
+// builds the process to spawn for the configured command
+pub fn build(config: &CommandConfig, shell: bool) -> Command {
+    let (program, args) = command_line(config, shell);
+    let mut process = Command::new(program);
+
+    process
+        .raw_arg(args)
+        .creation_flags(creation_flags(config));
+
+    process
+}
+
+// builds a one off command line run on behalf of a command, e.g. its reload_cmd, without a console window
+pub fn build_line(line: &str, shell: bool) -> Command {
+    let (program, args) = if shell {
+        ("cmd.exe", format!("/D /S /C \"{}\"", line))
+    } else {
+        let (program, args) = split_program(line).unwrap_or((line, ""));
+        (program, args.to_owned())
+    };
+
+    let mut process = Command::new(program);
+    process.raw_arg(args).creation_flags(CREATE_NO_WINDOW);
+    process
+}
+
+// human readable form of the command for logs and status output, not for execution
+pub fn display(config: &CommandConfig) -> String {
+    match config.kind {
+        CommandKind::Shell => shell_line(config, false),
+        CommandKind::PowershellScript => {
+            let (program, args) = command_line(config, true);
+            format!("{} {}", program, args)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_plain_args_unquoted() {
+        assert_eq!(quote_arg("plain"), "plain");
+        assert_eq!(quote_arg(r"C:\dir\"), r"C:\dir\");
+    }
+
+    #[test]
+    fn quotes_empty_args() {
+        assert_eq!(quote_arg(""), r#""""#);
+    }
+
+    #[test]
+    fn quotes_args_with_whitespace() {
+        assert_eq!(quote_arg("two words"), r#""two words""#);
+        assert_eq!(quote_arg("tab\tbed"), "\"tab\tbed\"");
+        assert_eq!(quote_arg(r"C:\a dir\b"), r#""C:\a dir\b""#);
+    }
+
+    #[test]
+    fn escapes_quotes_and_the_backslashes_before_them() {
+        assert_eq!(quote_arg(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_arg(r#"a\"b"#), r#""a\\\"b""#);
+        assert_eq!(quote_arg(r#"a\\"b"#), r#""a\\\\\"b""#);
+    }
+
+    #[test]
+    fn escapes_trailing_backslashes() {
+        assert_eq!(quote_arg(r"C:\a dir\"), r#""C:\a dir\\""#);
+        assert_eq!(quote_arg(r"a b\\"), r#""a b\\\\""#);
+    }
+
+    #[test]
+    fn escapes_cmd_metachars() {
+        assert_eq!(escape_cmd("a&b|c<d>e%f^g"), "a^&b^|c^<d^>e^%f^^g");
+        assert_eq!(escape_cmd(r#"(x!)"y""#), r#"^(x^!^)^"y^""#);
+        assert_eq!(escape_cmd("plain text"), "plain text");
+        assert_eq!(escape_cmd(""), "");
+    }
+
+    #[test]
+    fn splits_quoted_and_bare_programs() {
+        assert_eq!(split_program(r#""C:\a dir\app.exe" --flag"#), Some((r"C:\a dir\app.exe", " --flag")));
+        assert_eq!(split_program("  app.exe --flag"), Some(("app.exe", " --flag")));
+        assert_eq!(split_program("app.exe"), Some(("app.exe", "")));
+    }
+}
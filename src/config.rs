@@ -0,0 +1,1544 @@
+use command;
+use errors::*;
+use humantime;
+use log::LogLevelFilter;
+use profile;
+use redact::{Redactor, MASK};
+use schedule::{self, DstGap, ExcludedDates, TimeOfDay};
+use service::CONTROL_MAINTENANCE;
+use serde::{Deserialize, Deserializer, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml;
+use vars::Variables;
+use win;
+
+// separators, redirections, escapes and variable expansion of cmd.exe, even within quotes
+const STRICT_METACHARS: &[char] = &['&', '|', '<', '>', '^', '%', '!', '\n'];
+
+const UTF8_CODEPAGE: u16 = 65001;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileConfig {
+    #[serde(default)]
+    pub service: ServiceConfig,
+
+    // legacy form, a bare list of shell command lines
+    #[serde(default)]
+    pub cmds: Vec<String>,
+
+    #[serde(default)]
+    pub commands: Vec<CommandConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServiceConfig {
+    // extra regexes of secrets to mask in logged command lines, on top of the builtin ones
+    #[serde(default)]
+    pub redact: Vec<String>,
+
+    // how long maintenance mode lasts when toggled on without an explicit duration
+    #[serde(default = "default_maintenance_duration", with = "duration_str")]
+    pub maintenance_duration: Duration,
+
+    // defaults to <exe>.log next to the executable
+    #[serde(default)]
+    pub log_file: Option<String>,
+
+    #[serde(default)]
+    pub log_encoding: LogEncoding,
+
+    // relative paths are resolved against the config file directory,
+    // false keeps them relative to the working directory of the service (usually System32)
+    #[serde(default = "default_relative_to_config")]
+    pub relative_to_config: bool,
+
+    // number of recent supervisor events kept for the history control request
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+
+    // upper bound of the per command stop_timeout when the os is shutting down
+    #[serde(default = "default_shutdown_timeout", with = "duration_str")]
+    pub shutdown_timeout: Duration,
+
+    // on stop, manual commands stop first, the others only once they did or after this long
+    #[serde(default = "default_jobs_stop_timeout", with = "duration_str")]
+    pub jobs_stop_timeout: Duration,
+
+    // once a stop takes this long in total, whatever is left running is killed and the service reports stopped
+    #[serde(default = "default_stop_budget", with = "duration_str")]
+    pub stop_budget: Duration,
+
+    // host whose name must resolve for the network to count as up,
+    // otherwise any route out of the machine will do
+    #[serde(default)]
+    pub network_check_host: Option<String>,
+
+    // needs_network commands are started anyway after waiting this long
+    #[serde(default = "default_network_wait_timeout", with = "duration_str")]
+    pub network_wait_timeout: Duration,
+
+    // for server core and nano server containers, shell commands are started directly
+    // instead of through cmd.exe, and stops skip the console ctrl-c
+    #[serde(default)]
+    pub container_mode: bool,
+
+    // delay between the first spawns of successive commands at service start, so that dozens of them
+    // do not all load up the machine at once, zero starts them together
+    #[serde(default = "default_startup_stagger", with = "duration_str")]
+    pub startup_stagger: Duration,
+
+    // sddl dacl applied to the log and lock files, since child output may hold sensitive data,
+    // empty keeps the permissions inherited from the directory
+    #[serde(default = "default_file_acl")]
+    pub file_acl: String,
+
+    // append only json lines log of spawns, kills, restarts, control requests and reloads
+    #[serde(default)]
+    pub audit_file: Option<String>,
+
+    // url of a json manifest naming the latest binary, checked for self-updates if set
+    #[serde(default)]
+    pub update_manifest: Option<String>,
+
+    #[serde(default = "default_update_interval", with = "duration_str")]
+    pub update_interval: Duration,
+
+    // authenticode publisher updates must be signed by, that of the running binary if unset
+    #[serde(default)]
+    pub update_publisher: Option<String>,
+
+    // rejects shell commands whose cmd, after variable expansion, holds anything cmd.exe interprets,
+    // so that only the program goes in cmd and everything else in the literal args
+    #[serde(default)]
+    pub strict: bool,
+
+    // restarts by restart policy across all commands, bursts up to this many and then this many per minute,
+    // so that a systemic failure does not respawn children in a tight loop, zero is unlimited
+    #[serde(default = "default_max_restarts_per_minute")]
+    pub max_restarts_per_minute: u32,
+
+    // restart budgets by group name, shared by the commands of the group, likewise in bursts up to this many
+    // and then this many per minute, so that a flapping dependency does not restart each dependent without bound
+    #[serde(default)]
+    pub restart_budgets: BTreeMap<String, u32>,
+
+    // where windows error reporting writes the minidumps of crashing minidump commands,
+    // defaults to a dumps directory next to the log file
+    #[serde(default)]
+    pub dump_dir: Option<String>,
+
+    // root of the data and log directories of each command, <data_dir>\<command>\data and \logs, created before
+    // the command first starts, so that deployments need not create them, the children find them in
+    // SUPERVISOR_DATA_DIR and SUPERVISOR_LOG_DIR
+    #[serde(default)]
+    pub data_dir: Option<String>,
+
+    // sddl dacl of the directory of each command under data_dir, which what the child creates in it inherits,
+    // empty keeps the permissions inherited from data_dir
+    #[serde(default = "default_data_dir_acl")]
+    pub data_dir_acl: String,
+
+    // most recent minidumps kept per executable
+    #[serde(default = "default_dump_count")]
+    pub dump_count: u32,
+
+    // csv of per command availability and resource usage in the perfmon format, appended to every metrics_interval
+    #[serde(default)]
+    pub metrics_file: Option<String>,
+
+    #[serde(default = "default_metrics_interval", with = "duration_str")]
+    pub metrics_interval: Duration,
+
+    // json of the health of the service, rewritten every heartbeat_interval
+    #[serde(default)]
+    pub heartbeat_file: Option<String>,
+
+    #[serde(default = "default_heartbeat_interval", with = "duration_str")]
+    pub heartbeat_interval: Duration,
+
+    // how often handle and thread counts are checked for steady growth, and the children of the supervisor for strays
+    #[serde(default = "default_leak_check_interval", with = "duration_str")]
+    pub leak_check_interval: Duration,
+
+    // free space below which the volumes of the log, audit log, metrics and dumps raise alerts, 0 disables the check
+    #[serde(default = "default_min_free_space_mb")]
+    pub min_free_space_mb: u64,
+
+    #[serde(default = "default_disk_check_interval", with = "duration_str")]
+    pub disk_check_interval: Duration,
+
+    // forwards the log to a central collector
+    #[serde(default)]
+    pub syslog: Option<SyslogConfig>,
+
+    // log4rs loggers by target, "cmd::<name>" for what is logged about a command, its output included,
+    // and "cmd::<name>::output" for its output alone, each with its own level and log file
+    #[serde(default)]
+    pub loggers: BTreeMap<String, LoggerConfig>,
+
+    // after a crash alert, further crash alerts of the same command are held back for this long, the next one
+    // then counts the crashes in between, by default every crash alerts
+    #[serde(default, with = "opt_duration_str")]
+    pub alert_window: Option<Duration>,
+
+    // service start and stop, spawns, exits, crashes, restarts and stops in the application event log,
+    // with every value as a separate EventData field for event forwarding
+    #[serde(default)]
+    pub event_log: bool,
+
+    // the control requests as a json api on localhost, for dashboards and scripts
+    #[serde(default)]
+    pub rest_api: Option<RestApiConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RestApiConfig {
+    pub port: u16,
+
+    // expected as `Authorization: Bearer <token>`, anyone able to read this file can control the service
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyslogConfig {
+    // host:port of the collector
+    pub address: String,
+
+    #[serde(default)]
+    pub protocol: SyslogProtocol,
+
+    // records below this level are not forwarded, regardless of the log level
+    #[serde(default)]
+    pub level: SyslogLevel,
+
+    // child output is mostly noise for a collector, so only the supervisor records are forwarded by default
+    #[serde(default)]
+    pub child_output: bool,
+
+    // 3 is daemon
+    #[serde(default = "default_syslog_facility")]
+    pub facility: u8,
+}
+
+fn default_syslog_facility() -> u8 {
+    3
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoggerConfig {
+    // the log level of the service unless set, which "log-level" changes at runtime
+    #[serde(default)]
+    pub level: Option<LoggerLevel>,
+
+    // also logged to this file
+    #[serde(default)]
+    pub file: Option<String>,
+
+    // whether the records still go to the service log and syslog, or only to the file of the logger
+    #[serde(default = "default_additive")]
+    pub additive: bool,
+}
+
+fn default_additive() -> bool {
+    true
+}
+
+// of the log files, which are utf-8 either way
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogEncoding {
+    Utf8,
+
+    // with a byte order mark starting each new log file, for editors and tools that otherwise read it
+    // in the ansi codepage of a non-english system locale
+    Utf8Bom,
+}
+
+impl Default for LogEncoding {
+    fn default() -> LogEncoding {
+        LogEncoding::Utf8
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoggerLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LoggerLevel {
+    pub fn filter(&self) -> LogLevelFilter {
+        match *self {
+            LoggerLevel::Off => LogLevelFilter::Off,
+            LoggerLevel::Error => LogLevelFilter::Error,
+            LoggerLevel::Warn => LogLevelFilter::Warn,
+            LoggerLevel::Info => LogLevelFilter::Info,
+            LoggerLevel::Debug => LogLevelFilter::Debug,
+            LoggerLevel::Trace => LogLevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogProtocol {
+    Udp,
+
+    // with octet counting framing, as in rfc 6587
+    Tcp,
+}
+
+impl Default for SyslogProtocol {
+    fn default() -> SyslogProtocol {
+        SyslogProtocol::Udp
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Default for SyslogLevel {
+    fn default() -> SyslogLevel {
+        SyslogLevel::Info
+    }
+}
+
+impl SyslogLevel {
+    pub fn filter(&self) -> LogLevelFilter {
+        match *self {
+            SyslogLevel::Error => LogLevelFilter::Error,
+            SyslogLevel::Warn => LogLevelFilter::Warn,
+            SyslogLevel::Info => LogLevelFilter::Info,
+            SyslogLevel::Debug => LogLevelFilter::Debug,
+            SyslogLevel::Trace => LogLevelFilter::Trace,
+        }
+    }
+}
+
+impl Default for ServiceConfig {
+    fn default() -> ServiceConfig {
+        ServiceConfig {
+            redact: vec![],
+            maintenance_duration: default_maintenance_duration(),
+            log_file: None,
+            log_encoding: LogEncoding::default(),
+            relative_to_config: default_relative_to_config(),
+            history_size: default_history_size(),
+            shutdown_timeout: default_shutdown_timeout(),
+            jobs_stop_timeout: default_jobs_stop_timeout(),
+            stop_budget: default_stop_budget(),
+            network_check_host: None,
+            network_wait_timeout: default_network_wait_timeout(),
+            container_mode: false,
+            startup_stagger: default_startup_stagger(),
+            file_acl: default_file_acl(),
+            audit_file: None,
+            update_manifest: None,
+            update_interval: default_update_interval(),
+            update_publisher: None,
+            strict: false,
+            max_restarts_per_minute: default_max_restarts_per_minute(),
+            restart_budgets: BTreeMap::new(),
+            dump_dir: None,
+            data_dir: None,
+            data_dir_acl: default_data_dir_acl(),
+            dump_count: default_dump_count(),
+            metrics_file: None,
+            metrics_interval: default_metrics_interval(),
+            heartbeat_file: None,
+            heartbeat_interval: default_heartbeat_interval(),
+            leak_check_interval: default_leak_check_interval(),
+            min_free_space_mb: default_min_free_space_mb(),
+            disk_check_interval: default_disk_check_interval(),
+            syslog: None,
+            loggers: BTreeMap::new(),
+            alert_window: None,
+            event_log: false,
+            rest_api: None,
+        }
+    }
+}
+
+fn default_relative_to_config() -> bool {
+    true
+}
+
+fn default_history_size() -> usize {
+    200
+}
+
+fn default_shutdown_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+// full access for SYSTEM, Administrators and the owner, i.e. the service account, only
+fn default_file_acl() -> String {
+    "D:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;OW)".to_owned()
+}
+
+// like file_acl, but inherited by the files and directories within
+fn default_data_dir_acl() -> String {
+    "D:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)(A;OICI;FA;;;OW)".to_owned()
+}
+
+fn default_startup_stagger() -> Duration {
+    Duration::from_secs(0)
+}
+
+fn default_network_wait_timeout() -> Duration {
+    Duration::from_secs(2 * 60)
+}
+
+fn default_max_restarts_per_minute() -> u32 {
+    30
+}
+
+fn default_metrics_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_replicas() -> u32 {
+    1
+}
+
+fn default_jobs_stop_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_stop_budget() -> Duration {
+    Duration::from_secs(3 * 60)
+}
+
+fn default_heartbeat_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_leak_check_interval() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+fn default_min_free_space_mb() -> u64 {
+    1024
+}
+
+fn default_disk_check_interval() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_dump_count() -> u32 {
+    10
+}
+
+fn default_update_interval() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn default_maintenance_duration() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandConfig {
+    pub name: String,
+
+    #[serde(default)]
+    pub kind: CommandKind,
+
+    // shell command line, for the shell kind
+    #[serde(default)]
+    pub cmd: String,
+
+    // path to the .ps1 file, for the powershell_script kind
+    #[serde(default)]
+    pub script: Option<String>,
+
+    // parameters passed to the script, or appended literally to the shell command line
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    #[serde(default = "default_execution_policy")]
+    pub execution_policy: String,
+
+    // working directory of the child, defaults to the config file directory unless relative_to_config is off
+    #[serde(default)]
+    pub cwd: Option<String>,
+
+    // if non-empty, only output lines matching at least one of the regexes are logged
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    // output lines matching any of the regexes are dropped
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    // lines of stdout and stderr together logged per second, the rest is dropped and counted, 0 is unlimited
+    #[serde(default)]
+    pub max_log_lines_per_second: u32,
+
+    // longer output lines are cut off and the rest of them dropped and counted, so that a child writing without
+    // line breaks cannot make the supervisor hold its output in memory without bound
+    #[serde(default = "default_max_line_bytes")]
+    pub max_line_bytes: usize,
+
+    // an exit code of 0 only counts as success if a line of the output matched this regex, e.g. "0 errors"
+    #[serde(default)]
+    pub success_output: Option<String>,
+
+    // and if no line matched this one, e.g. "^ERROR"
+    #[serde(default)]
+    pub failure_output: Option<String>,
+
+    // manual commands are not started with the service, only on request
+    #[serde(default)]
+    pub start: StartMode,
+
+    // identical instances run and supervised independently, e.g. a worker pool, named <name>.0, <name>.1 and so on
+    #[serde(default = "default_replicas")]
+    pub replicas: u32,
+
+    // index of the instance among the replicas, set when the command is replicated
+    #[serde(skip)]
+    pub instance: u32,
+
+    // custom service control code between 129 and 255 that starts the command, e.g. `sc control <service> 129`
+    #[serde(default)]
+    pub start_control_code: Option<u32>,
+
+    // manual command started whenever this one exits successfully, e.g. backup, then verify, then upload,
+    // a failed step ends the chain
+    #[serde(default)]
+    pub on_success: Option<String>,
+
+    // stdout goes to the stdin of the other command instead of the log, held back while it is respawned
+    #[serde(default)]
+    pub pipe_to: Option<String>,
+
+    // the service listens in place of the manual command and starts it on the first connection
+    #[serde(default)]
+    pub activation: Option<ActivationConfig>,
+
+    // ports the service forwards to the child or another host, e.g. for a child that binds to localhost only
+    #[serde(default)]
+    pub forward: Vec<ForwardConfig>,
+
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    // initial delay before respawning, doubled on every consecutive restart
+    #[serde(default = "default_restart_delay", with = "duration_str")]
+    pub restart_delay: Duration,
+
+    // upper bound of the delay, a child that ran for longer than this resets the backoff
+    #[serde(default = "default_restart_delay_max", with = "duration_str")]
+    pub restart_delay_max: Duration,
+
+    // daily local time at which the child is gracefully bounced, e.g. "04:00"
+    #[serde(default)]
+    pub restart_schedule: Option<TimeOfDay>,
+
+    // windows time zone the schedule is in, e.g. "W. Europe Standard Time", defaults to the machine's
+    #[serde(default)]
+    pub schedule_timezone: Option<String>,
+
+    // a scheduled time skipped as the clocks are put forward runs right after the jump or not at all
+    #[serde(default)]
+    pub schedule_dst_gap: DstGap,
+
+    // dates on which the schedule is skipped, e.g. holidays, as "2024-12-25", "2024-12-24..2025-01-01" or yearly "12-25"
+    #[serde(default)]
+    pub schedule_exclude: Vec<ExcludedDates>,
+
+    // more of them, one per line, read with every load of the config
+    #[serde(default)]
+    pub schedule_exclude_file: Option<String>,
+
+    // time given to the child to exit after ctrl-c before it is killed
+    #[serde(default = "default_stop_timeout", with = "duration_str")]
+    pub stop_timeout: Duration,
+
+    // how a run still going when the service stops ends, e.g. a scheduled backup that must not be cut short
+    #[serde(default)]
+    pub on_service_stop: OnServiceStop,
+
+    // run through cmd.exe by `reload <name>` to have the running child reload itself, e.g. `app.exe --reload`,
+    // instead of a stop and start, with the pid of the child in SUPERVISOR_CHILD_PID
+    #[serde(default)]
+    pub reload_cmd: Option<String>,
+
+    // the reload_cmd is killed and the reload failed after this long
+    #[serde(default = "default_reload_timeout", with = "duration_str")]
+    pub reload_timeout: Duration,
+
+    // a respawned child that is still running after this long is considered ready
+    #[serde(default = "default_ready_after", with = "duration_str")]
+    pub ready_after: Duration,
+
+    // files whose changes make the child gracefully restart
+    #[serde(default)]
+    pub watch: Vec<String>,
+
+    // re-read the machine and user environment from the registry before every spawn
+    #[serde(default)]
+    pub refresh_env: bool,
+
+    // extra environment variables of the child
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    // the first spawn waits for the network to be up
+    #[serde(default)]
+    pub needs_network: bool,
+
+    // drive letter like "D:" or mount path, the first spawn waits for it to be mounted
+    #[serde(default)]
+    pub requires_drive: Option<String>,
+
+    #[serde(default)]
+    pub creation_flags: Vec<CreationFlag>,
+
+    // for 32-bit builds, spawn with System32 not redirected to SysWOW64
+    #[serde(default)]
+    pub disable_wow64_redirection: bool,
+
+    #[serde(default)]
+    pub session: Session,
+
+    // window station and desktop of console session children
+    #[serde(default = "default_desktop")]
+    pub desktop: String,
+
+    // the child runs at this integrity level instead of the one of the service, e.g. medium for an untrusted
+    // workload wrapped by a LocalSystem service, which can only be lowered
+    #[serde(default)]
+    pub integrity_level: Option<IntegrityLevel>,
+
+    // the only privileges the token of the child keeps, e.g. ["SeChangeNotifyPrivilege"], all of them if unset
+    #[serde(default)]
+    pub privileges: Option<Vec<String>>,
+
+    // privileges removed from the token of the child, e.g. ["SeDebugPrivilege", "SeImpersonatePrivilege"]
+    #[serde(default)]
+    pub remove_privileges: Vec<String>,
+
+    // utf-8 python io encoding regardless of the system locale,
+    // and the utf-8 console codepage for shell commands run through cmd.exe
+    #[serde(default)]
+    pub utf8: bool,
+
+    // console codepage for shell commands run through cmd.exe, e.g. 1252 or 932, for legacy apps that assume
+    // a codepage other than the one of the system locale, and the one output that is not utf-8 is read in
+    #[serde(default)]
+    pub codepage: Option<u16>,
+
+    // TZ of the child, e.g. "EST5EDT", honored by the c runtime, python and ported unix tools
+    #[serde(default)]
+    pub tz: Option<String>,
+
+    // LANG and LC_ALL of the child, e.g. "de_DE.UTF-8"
+    #[serde(default)]
+    pub locale: Option<String>,
+
+    // expected hex sha-256 of the executable, or of the script for the powershell_script kind,
+    // checked before every spawn
+    #[serde(default)]
+    pub sha256: Option<String>,
+
+    // the executable, or the script for the powershell_script kind, must carry a valid
+    // authenticode signature by this publisher, as shown in the digital signatures tab
+    #[serde(default)]
+    pub publisher: Option<String>,
+
+    // names of the commands this one relies on
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
+    // gracefully restart once any of depends_on was restarted and is ready again,
+    // so that stale connections to it do not linger
+    #[serde(default)]
+    pub restart_with_dependencies: bool,
+
+    // restarts by restart policy and along with dependencies take from the budget of this group in restart_budgets
+    #[serde(default)]
+    pub restart_group: Option<String>,
+
+    // names of the commands never to be starting up at the same time as this one,
+    // a start waits until the others are ready, i.e. have run for ready_after
+    #[serde(default)]
+    pub anti_affinity: Vec<String>,
+
+    // capture a minidump when the executable crashes, which applies machine wide to every process of its name
+    #[serde(default)]
+    pub minidump: bool,
+
+    // no critical error or crash dialogs, which nobody could ever dismiss in session 0, so that a crashed child
+    // exits right away instead of hanging on windows error reporting
+    #[serde(default)]
+    pub suppress_error_dialogs: bool,
+
+    // a child that wrote no output at all for this long is considered hung,
+    // it is killed and respawned on its own while the service and the other children keep running
+    #[serde(default, with = "opt_duration_str")]
+    pub hang_timeout: Option<Duration>,
+
+    // the child has started up once a line of its output matched this regex, e.g. "Listening on",
+    // until then hang_timeout does not apply
+    #[serde(default)]
+    pub startup_output: Option<String>,
+
+    // time the child gets to start up, killed and respawned if startup_output did not match by then,
+    // without startup_output hang_timeout only applies once this much time passed
+    #[serde(default, with = "opt_duration_str")]
+    pub startup_timeout: Option<Duration>,
+
+    // gracefully stopped while a volume of the log or other output is below min_free_space_mb,
+    // and started again once space is freed, for commands that would otherwise fill it up
+    #[serde(default)]
+    pub pause_on_low_disk: bool,
+
+    // hard cap on the cpu time of the child and the processes it starts, as a percentage of the whole machine,
+    // for background batch work that would otherwise starve the other commands
+    #[serde(default)]
+    pub cpu_limit_percent: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    Shell,
+    PowershellScript,
+}
+
+impl Default for CommandKind {
+    fn default() -> CommandKind {
+        CommandKind::Shell
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl IntegrityLevel {
+    // the mandatory label sid of the level
+    pub fn sid(&self) -> &'static str {
+        match *self {
+            IntegrityLevel::Low => "S-1-16-4096",
+            IntegrityLevel::Medium => "S-1-16-8192",
+            IntegrityLevel::High => "S-1-16-12288",
+        }
+    }
+}
+
+// process creation flags, some gui and console apps behave differently depending on these
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CreationFlag {
+    CreateNoWindow,
+
+    // the child then ignores ctrl-c, so graceful stops end up killing it
+    CreateNewProcessGroup,
+
+    // the child then has no console to send ctrl-c to, so graceful stops end up killing it
+    DetachedProcess,
+
+    BelowNormalPriority,
+}
+
+// where the child runs, gui apps are invisible in the session 0 of services
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Session {
+    Service,
+
+    // as the user logged on to the physical console, requires the service to run as LocalSystem
+    Console,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::Service
+    }
+}
+
+fn default_desktop() -> String {
+    r"winsta0\default".to_owned()
+}
+
+fn default_execution_policy() -> String {
+    "Bypass".to_owned()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActivationConfig {
+    // address the service accepts connections on, e.g. "0.0.0.0:8080"
+    pub listen: String,
+
+    // address the child listens on, connections are proxied there once it accepts them
+    pub target: String,
+
+    // how long a connection waits for the child to accept before it is dropped
+    #[serde(default = "default_activation_ready_timeout", with = "duration_str")]
+    pub ready_timeout: Duration,
+
+    // the child is gracefully stopped after this long without connections, by default it keeps running
+    #[serde(default, with = "opt_duration_str")]
+    pub idle_timeout: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ForwardConfig {
+    // address the service accepts connections on, e.g. "0.0.0.0:8080"
+    pub listen: String,
+
+    // where every connection is proxied to, e.g. "127.0.0.1:8080"
+    pub target: String,
+}
+
+fn default_activation_ready_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartMode {
+    Auto,
+    Manual,
+}
+
+impl Default for StartMode {
+    fn default() -> StartMode {
+        StartMode::Auto
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnServiceStop {
+    // ctrl-c, then killed after stop_timeout
+    Stop,
+
+    // left to complete for up to stop_timeout, then killed
+    Wait,
+
+    // killed right away
+    Kill,
+}
+
+impl Default for OnServiceStop {
+    fn default() -> OnServiceStop {
+        OnServiceStop::Stop
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+impl RestartPolicy {
+    pub fn should_restart(&self, success: bool) -> bool {
+        match *self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+fn default_restart_delay() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_restart_delay_max() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_stop_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_max_line_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_reload_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_ready_after() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn resolve_path(base_dir: &Path, path: &str) -> String {
+    let path = Path::new(path);
+
+    if path.has_root() {
+        path.to_string_lossy().into_owned()
+    } else {
+        base_dir.join(path).to_string_lossy().into_owned()
+    }
+}
+
+// only the program of the command line is resolved, and only if it is given as a relative path,
+// bare program names are still looked up in PATH by cmd.exe
+fn resolve_program(base_dir: &Path, cmd: &str) -> String {
+    let (program, rest) = match command::split_program(cmd) {
+        Some(split) => split,
+        None => return cmd.to_owned(),
+    };
+
+    if !program.contains(|c| c == '/' || c == '\\') || Path::new(program).has_root() {
+        return cmd.to_owned();
+    }
+
+    let resolved = resolve_path(base_dir, program);
+
+    if resolved.contains(' ') {
+        format!("\"{}\"{}", resolved, rest)
+    } else {
+        format!("{}{}", resolved, rest)
+    }
+}
+
+// the config as the service applies it, after the profile, variables, replicas and defaults,
+// for troubleshooting what actually runs, with the secrets masked
+#[derive(Serialize, Debug)]
+pub struct EffectiveConfig {
+    pub config_file: PathBuf,
+    pub profile: Option<String>,
+    pub service: ServiceConfig,
+    pub commands: Vec<CommandConfig>,
+}
+
+impl EffectiveConfig {
+    pub fn new(config_file: &Path, vars: &Variables, service: &ServiceConfig, commands: &[CommandConfig])
+        -> Result<EffectiveConfig> {
+        let redactor = Redactor::new(&service.redact)?;
+        let mut service = service.clone();
+        let mut commands = commands.to_vec();
+
+        if let Some(ref mut rest_api) = service.rest_api {
+            rest_api.token = MASK.to_owned();
+        }
+
+        service.update_manifest = service.update_manifest.map(|update_manifest| redactor.redact(&update_manifest));
+
+        for cmd in commands.iter_mut() {
+            cmd.cmd = redactor.redact(&cmd.cmd);
+            cmd.reload_cmd = cmd.reload_cmd.take().map(|reload_cmd| redactor.redact(&reload_cmd));
+
+            for arg in cmd.args.iter_mut() {
+                *arg = redactor.redact(arg);
+            }
+
+            for (name, value) in cmd.env.iter_mut() {
+                *value = redactor.redact_var(name, value);
+            }
+        }
+
+        Ok(EffectiveConfig {
+            config_file: config_file.to_owned(),
+            profile: vars.profile().map(|profile| profile.to_owned()),
+            service: service,
+            commands: commands,
+        })
+    }
+}
+
+fn expand_opt(value: &Option<String>, vars: &Variables) -> Result<Option<String>> {
+    match *value {
+        Some(ref value) => vars.expand(value).map(Some),
+        None => Ok(None),
+    }
+}
+
+// durations are written in the human readable form, e.g. "500ms", "2s", "1h 30m"
+pub mod duration_str {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Duration, D::Error> {
+        use serde::de::Error;
+
+        let s = String::deserialize(deserializer)?;
+        humantime::parse_duration(&s).map_err(|e| D::Error::custom(format!("invalid duration {:?}: {}", s, e)))
+    }
+}
+
+pub mod opt_duration_str {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        match *duration {
+            Some(ref duration) => duration_str::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Option<Duration>, D::Error> {
+        duration_str::deserialize(deserializer).map(Some)
+    }
+}
+
+impl CommandConfig {
+    fn validate(&self) -> Result<()> {
+        match self.kind {
+            CommandKind::Shell => {
+                if self.cmd.trim().is_empty() {
+                    bail!("Command [{}] requires cmd to be set", self.name);
+                }
+
+                if self.script.is_some() {
+                    bail!("Command [{}] is a shell command, script is not supported", self.name);
+                }
+            },
+
+            CommandKind::PowershellScript => {
+                if self.script.is_none() {
+                    bail!("Command [{}] requires script to be set", self.name);
+                }
+
+                if !self.cmd.is_empty() {
+                    bail!("Command [{}] is a powershell_script command, cmd is not supported", self.name);
+                }
+            },
+        }
+
+        if let Some(ref sha256) = self.sha256 {
+            if sha256.len() != 64 || !sha256.chars().all(|c| c.is_digit(16)) {
+                bail!("Command [{}] has sha256 {:?}, which is not 64 hex digits", self.name, sha256);
+            }
+        }
+
+        if self.hang_timeout == Some(Duration::from_secs(0)) {
+            bail!("Command [{}] has a zero hang_timeout, leave it out to never consider the child hung", self.name);
+        }
+
+        if let Some(code) = self.start_control_code {
+            if code <= CONTROL_MAINTENANCE || code > 255 {
+                bail!("Command [{}] has start_control_code {}, which must be between {} and 255",
+                    self.name, code, CONTROL_MAINTENANCE + 1);
+            }
+
+            if self.start != StartMode::Manual {
+                bail!("Command [{}] has a start_control_code, which requires start = \"manual\"", self.name);
+            }
+        }
+
+        if self.activation.is_some() && self.start != StartMode::Manual {
+            bail!("Command [{}] has activation, which requires start = \"manual\"", self.name);
+        }
+
+        if let Some(ref timezone) = self.schedule_timezone {
+            win::TimeZone::find(timezone)
+                .chain_err(|| format!("Command [{}] has an invalid schedule_timezone, `tzutil /l` lists them", self.name))?;
+        }
+
+        if self.startup_timeout == Some(Duration::from_secs(0)) {
+            bail!("Command [{}] has a zero startup_timeout, leave it out to give the child unlimited time", self.name);
+        }
+
+        match self.codepage {
+            Some(0) => bail!("Command [{}] has codepage 0, which is no codepage", self.name),
+            Some(codepage) if self.utf8 && codepage != UTF8_CODEPAGE =>
+                bail!("Command [{}] has utf8 along with codepage {}, utf8 implies codepage {}", self.name, codepage, UTF8_CODEPAGE),
+            _ => (),
+        }
+
+        if self.max_line_bytes == 0 {
+            bail!("Command [{}] has max_line_bytes 0, which would drop all of its output", self.name);
+        }
+
+        for privilege in self.privileges.iter().flatten().chain(self.remove_privileges.iter()) {
+            if !privilege.starts_with("Se") || !privilege.ends_with("Privilege") {
+                bail!("Command [{}] has privilege {:?}, which is no privilege name like SeDebugPrivilege", self.name, privilege);
+            }
+        }
+
+        if let Some(percent) = self.cpu_limit_percent {
+            if percent == 0 || percent > 100 {
+                bail!("Command [{}] has cpu_limit_percent {}, which must be from 1 to 100", self.name, percent);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_strict(&self) -> Result<()> {
+        if self.kind != CommandKind::Shell {
+            return Ok(());
+        }
+
+        if let Some(c) = self.cmd.chars().find(|c| STRICT_METACHARS.contains(c)) {
+            bail!("Command [{}] has '{}' in cmd, which strict mode rejects, move the arguments to args instead",
+                self.name, c);
+        }
+
+        Ok(())
+    }
+
+    // adds the dates of schedule_exclude_file to the inline ones
+    fn read_schedule_exclude_file(&mut self) -> Result<()> {
+        let file = match self.schedule_exclude_file {
+            Some(ref file) => file,
+            None => return Ok(()),
+        };
+
+        let mut text = String::new();
+
+        File::open(file)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .chain_err(|| format!("Unable to read schedule_exclude_file {:?} of [{}]", file, self.name))?;
+
+        let dates = schedule::parse_excluded_dates(&text)
+            .chain_err(|| format!("Invalid schedule_exclude_file {:?} of [{}]", file, self.name))?;
+
+        self.schedule_exclude.extend(dates);
+        Ok(())
+    }
+
+    // time left until the next scheduled restart, if any
+    pub fn until_scheduled_restart(&self) -> Option<Duration> {
+        self.restart_schedule.and_then(|restart_schedule| restart_schedule.until_next(
+            self.schedule_timezone.as_deref(), self.schedule_dst_gap, &self.schedule_exclude))
+    }
+
+    // extra environment variables of the child, including the ones implied by other options
+    pub fn child_env(&self) -> BTreeMap<String, String> {
+        let mut env = BTreeMap::new();
+
+        if self.utf8 {
+            env.insert("PYTHONIOENCODING".to_owned(), "utf-8".to_owned());
+            env.insert("PYTHONUTF8".to_owned(), "1".to_owned());
+        }
+
+        if let Some(ref tz) = self.tz {
+            env.insert("TZ".to_owned(), tz.clone());
+        }
+
+        if let Some(ref locale) = self.locale {
+            env.insert("LANG".to_owned(), locale.clone());
+            env.insert("LC_ALL".to_owned(), locale.clone());
+        }
+
+        env.extend(self.env.iter().map(|(key, value)| (key.clone(), value.clone())));
+        env
+    }
+
+    // the console codepage set with chcp before a shell command runs
+    pub fn console_codepage(&self) -> Option<u16> {
+        self.codepage.or(if self.utf8 { Some(UTF8_CODEPAGE) } else { None })
+    }
+
+    fn expand(&mut self, vars: &Variables) -> Result<()> {
+        self.cmd = vars.expand(&self.cmd)?;
+        self.script = expand_opt(&self.script, vars)?;
+        self.cwd = expand_opt(&self.cwd, vars)?;
+        self.reload_cmd = expand_opt(&self.reload_cmd, vars)?;
+        self.requires_drive = expand_opt(&self.requires_drive, vars)?;
+        self.schedule_exclude_file = expand_opt(&self.schedule_exclude_file, vars)?;
+
+        for arg in self.args.iter_mut().chain(self.watch.iter_mut()).chain(self.env.values_mut()) {
+            *arg = vars.expand(arg)?;
+        }
+
+        Ok(())
+    }
+
+    fn resolve_paths(&mut self, base_dir: &Path) {
+        self.cmd = resolve_program(base_dir, &self.cmd);
+        self.script = self.script.take().map(|script| resolve_path(base_dir, &script));
+        self.schedule_exclude_file = self.schedule_exclude_file.take().map(|file| resolve_path(base_dir, &file));
+
+        self.cwd = Some(match self.cwd.take() {
+            Some(cwd) => resolve_path(base_dir, &cwd),
+            None => base_dir.to_string_lossy().into_owned(),
+        });
+
+        for path in self.watch.iter_mut() {
+            *path = resolve_path(base_dir, path);
+        }
+    }
+
+    fn from_legacy(idx: usize, cmd: String) -> CommandConfig {
+        CommandConfig {
+            name: format!("cmd{}", idx),
+            kind: CommandKind::Shell,
+            cmd: cmd,
+            script: None,
+            args: vec![],
+            execution_policy: default_execution_policy(),
+            cwd: None,
+            include: vec![],
+            exclude: vec![],
+            max_log_lines_per_second: 0,
+            max_line_bytes: default_max_line_bytes(),
+            success_output: None,
+            failure_output: None,
+            start: StartMode::default(),
+            replicas: default_replicas(),
+            instance: 0,
+            start_control_code: None,
+            on_success: None,
+            pipe_to: None,
+            activation: None,
+            forward: vec![],
+            restart: RestartPolicy::default(),
+            restart_delay: default_restart_delay(),
+            restart_delay_max: default_restart_delay_max(),
+            restart_schedule: None,
+            schedule_timezone: None,
+            schedule_dst_gap: DstGap::default(),
+            schedule_exclude: vec![],
+            schedule_exclude_file: None,
+            stop_timeout: default_stop_timeout(),
+            on_service_stop: OnServiceStop::default(),
+            reload_cmd: None,
+            reload_timeout: default_reload_timeout(),
+            ready_after: default_ready_after(),
+            watch: vec![],
+            refresh_env: false,
+            env: BTreeMap::new(),
+            needs_network: false,
+            requires_drive: None,
+            creation_flags: vec![],
+            disable_wow64_redirection: false,
+            session: Session::default(),
+            desktop: default_desktop(),
+            integrity_level: None,
+            privileges: None,
+            remove_privileges: vec![],
+            utf8: false,
+            codepage: None,
+            tz: None,
+            locale: None,
+            sha256: None,
+            publisher: None,
+            depends_on: vec![],
+            restart_with_dependencies: false,
+            restart_group: None,
+            anti_affinity: vec![],
+            minidump: false,
+            suppress_error_dialogs: false,
+            hang_timeout: None,
+            startup_output: None,
+            startup_timeout: None,
+            pause_on_low_disk: false,
+            cpu_limit_percent: None,
+        }
+    }
+}
+
+impl FileConfig {
+    pub fn load<P: AsRef<Path>>(config_path: P, vars: &Variables) -> Result<FileConfig> {
+        let config_path = config_path.as_ref();
+
+        let config_str = {
+            let mut config_file = File::open(config_path)
+                .chain_err(|| format!("Unable to open config file path at {:?}", config_path))?;
+
+            let mut s = String::new();
+
+            config_file.read_to_string(&mut s)
+                .map(|_| s)
+                .chain_err(|| "Unable to read config file into string")?
+        };
+
+        // the config may hold connection strings, so it cannot be dumped as it is
+        let mut value: toml::Value = toml::from_str(&config_str)
+            .chain_err(|| format!("Unable to parse config as required toml format: {}",
+                Redactor::builtin().redact(&config_str)))?;
+
+        profile::apply(&mut value, vars.profile())?;
+
+        let mut config: FileConfig = value.try_into()
+            .chain_err(|| format!("Unable to parse config as required toml format: {}",
+                Redactor::builtin().redact(&config_str)))?;
+
+        if config.service.stop_budget == Duration::from_secs(0) {
+            bail!("stop_budget must be greater than zero, it bounds how long the service may take to stop");
+        }
+
+        // legacy cmds are launched first, followed by the named commands
+        let mut commands = config.cmds.drain(..)
+            .enumerate()
+            .map(|(idx, cmd)| CommandConfig::from_legacy(idx, cmd))
+            .collect::<Vec<_>>();
+
+        commands.append(&mut config.commands);
+        config.commands = commands;
+
+        config.replicate()?;
+        config.expand(vars)?;
+
+        if config.service.relative_to_config {
+            let base_dir = config_path.parent()
+                .map(|dir| dir.to_owned())
+                .unwrap_or_else(PathBuf::new);
+
+            config.resolve_paths(&base_dir);
+        }
+
+        for cmd in config.commands.iter_mut() {
+            cmd.read_schedule_exclude_file()?;
+        }
+
+        for cmd in &config.commands {
+            cmd.validate()?;
+
+            if config.service.strict {
+                cmd.validate_strict()?;
+            }
+        }
+
+        for (i, cmd) in config.commands.iter().enumerate() {
+            if let Some(code) = cmd.start_control_code {
+                if let Some(other) = config.commands[..i].iter().find(|other| other.start_control_code == Some(code)) {
+                    bail!("Commands [{}] and [{}] have the same start_control_code {}", other.name, cmd.name, code);
+                }
+            }
+        }
+
+        for cmd in &config.commands {
+            if let Some(ref next) = cmd.on_success {
+                match config.commands.iter().find(|other| other.name == *next) {
+                    None => bail!("Command [{}] has on_success [{}], which is not a command", cmd.name, next),
+                    Some(other) if other.start != StartMode::Manual =>
+                        bail!("Command [{}] has on_success [{}], which requires start = \"manual\" on it", cmd.name, next),
+                    Some(_) => (),
+                }
+            }
+        }
+
+        for cmd in &config.commands {
+            if let Some(ref group) = cmd.restart_group {
+                if !config.service.restart_budgets.contains_key(group) {
+                    bail!("Command [{}] has restart_group {}, which has no budget in restart_budgets", cmd.name, group);
+                }
+            }
+
+            if let Some(ref consumer) = cmd.pipe_to {
+                match config.commands.iter().find(|other| other.name == *consumer) {
+                    None => bail!("Command [{}] has pipe_to [{}], which is not a command", cmd.name, consumer),
+                    Some(other) if other.name == cmd.name => bail!("Command [{}] cannot pipe_to itself", cmd.name),
+                    Some(other) if cmd.session == Session::Console || other.session == Session::Console =>
+                        bail!("Command [{}] has pipe_to [{}], which is not supported in the console session", cmd.name, consumer),
+                    Some(_) => (),
+                }
+            }
+        }
+
+        if let Some(ref rest_api) = config.service.rest_api {
+            if rest_api.token.len() < 16 {
+                bail!("The REST API token must be at least 16 characters long");
+            }
+        }
+
+        if let Some(ref update_manifest) = config.service.update_manifest {
+            if !update_manifest.to_ascii_lowercase().starts_with("https://") {
+                bail!("update_manifest {} must be an https url, updates are only fetched over https", update_manifest);
+            }
+        }
+
+        if let Some(ref syslog) = config.service.syslog {
+            if syslog.facility > 23 {
+                bail!("Syslog facility {} is out of range, it must be between 0 and 23", syslog.facility);
+            }
+        }
+
+        if config.service.loggers.contains_key("") {
+            bail!("A logger has an empty target, which would be the whole log, use the log level instead");
+        }
+
+        config.validate_dependencies()?;
+        config.complete_anti_affinity()?;
+        Ok(config)
+    }
+
+    // turns a command with replicas into one command per instance, a reference to it into one to every instance
+    fn replicate(&mut self) -> Result<()> {
+        let mut replicated = HashMap::new();
+        let mut commands = Vec::new();
+
+        for cmd in self.commands.drain(..) {
+            if cmd.replicas == 0 {
+                bail!("Command [{}] has 0 replicas, set start = \"manual\" to not start it with the service", cmd.name);
+            }
+
+            if cmd.replicas == 1 {
+                commands.push(cmd);
+                continue;
+            }
+
+            // an instance of each would compete for the same control code, port or pipe
+            if cmd.start_control_code.is_some() || cmd.activation.is_some() || !cmd.forward.is_empty()
+                || cmd.pipe_to.is_some() || cmd.on_success.is_some() {
+                bail!("Command [{}] has replicas, which is not supported along with start_control_code, activation, \
+                    forward, pipe_to or on_success", cmd.name);
+            }
+
+            let names = (0..cmd.replicas).map(|instance| format!("{}.{}", cmd.name, instance)).collect::<Vec<_>>();
+
+            for (instance, name) in names.iter().enumerate() {
+                let mut replica = cmd.clone();
+                replica.name = name.clone();
+                replica.instance = instance as u32;
+                commands.push(replica);
+            }
+
+            replicated.insert(cmd.name, names);
+        }
+
+        for cmd in commands.iter_mut() {
+            for next in cmd.on_success.iter().chain(cmd.pipe_to.iter()) {
+                if replicated.contains_key(next) {
+                    bail!("Command [{}] refers to [{}], which has replicas, in on_success or pipe_to", cmd.name, next);
+                }
+            }
+
+            let expand = |names: &[String]| names.iter()
+                .flat_map(|name| replicated.get(name).cloned().unwrap_or_else(|| vec![name.clone()]))
+                .collect::<Vec<_>>();
+
+            cmd.depends_on = expand(&cmd.depends_on);
+            cmd.anti_affinity = expand(&cmd.anti_affinity);
+        }
+
+        for name in replicated.values().flat_map(|names| names.iter()) {
+            if commands.iter().filter(|cmd| cmd.name == *name).count() > 1 {
+                bail!("Command name [{}] is used by a replica too, which are named <name>.<instance>", name);
+            }
+        }
+
+        self.commands = commands;
+        Ok(())
+    }
+
+    // anti affinity goes both ways, so each side lists the other
+    fn complete_anti_affinity(&mut self) -> Result<()> {
+        let mut pairs = Vec::new();
+
+        for cmd in &self.commands {
+            for other in &cmd.anti_affinity {
+                if !self.commands.iter().any(|cmd| cmd.name == *other) {
+                    bail!("Command [{}] has anti affinity to unknown command [{}]", cmd.name, other);
+                }
+
+                pairs.push((other.clone(), cmd.name.clone()));
+            }
+        }
+
+        for (name, other) in pairs {
+            for cmd in self.commands.iter_mut().filter(|cmd| cmd.name == name) {
+                if !cmd.anti_affinity.contains(&other) {
+                    cmd.anti_affinity.push(other.clone());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_dependencies(&self) -> Result<()> {
+        let find = |name: &str| self.commands.iter().find(|cmd| cmd.name == name);
+
+        for cmd in &self.commands {
+            for dependency in &cmd.depends_on {
+                if find(dependency).is_none() {
+                    bail!("Command [{}] depends on unknown command [{}]", cmd.name, dependency);
+                }
+            }
+
+            // a cycle would make cascading restarts go round forever
+            let mut pending = cmd.depends_on.iter().collect::<Vec<_>>();
+            let mut seen = Vec::new();
+
+            while let Some(dependency) = pending.pop() {
+                if *dependency == cmd.name {
+                    bail!("Command [{}] depends on itself through depends_on", cmd.name);
+                }
+
+                if !seen.contains(&dependency) {
+                    seen.push(dependency);
+                    pending.extend(find(dependency).into_iter().flat_map(|cmd| cmd.depends_on.iter()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_paths(&mut self, base_dir: &Path) {
+        self.service.log_file = self.service.log_file.take().map(|log_file| resolve_path(base_dir, &log_file));
+        self.service.audit_file = self.service.audit_file.take().map(|audit_file| resolve_path(base_dir, &audit_file));
+        self.service.dump_dir = self.service.dump_dir.take().map(|dump_dir| resolve_path(base_dir, &dump_dir));
+        self.service.data_dir = self.service.data_dir.take().map(|data_dir| resolve_path(base_dir, &data_dir));
+        self.service.metrics_file = self.service.metrics_file.take().map(|metrics_file| resolve_path(base_dir, &metrics_file));
+        self.service.heartbeat_file = self.service.heartbeat_file.take().map(|heartbeat_file| resolve_path(base_dir, &heartbeat_file));
+
+        for logger in self.service.loggers.values_mut() {
+            logger.file = logger.file.take().map(|file| resolve_path(base_dir, &file));
+        }
+
+        for cmd in self.commands.iter_mut() {
+            cmd.resolve_paths(base_dir);
+        }
+    }
+
+    fn expand(&mut self, vars: &Variables) -> Result<()> {
+        self.service.log_file = expand_opt(&self.service.log_file, vars)?;
+        self.service.audit_file = expand_opt(&self.service.audit_file, vars)?;
+        self.service.dump_dir = expand_opt(&self.service.dump_dir, vars)?;
+        self.service.data_dir = expand_opt(&self.service.data_dir, vars)?;
+        self.service.metrics_file = expand_opt(&self.service.metrics_file, vars)?;
+        self.service.heartbeat_file = expand_opt(&self.service.heartbeat_file, vars)?;
+        self.service.update_manifest = expand_opt(&self.service.update_manifest, vars)?;
+
+        for logger in self.service.loggers.values_mut() {
+            logger.file = expand_opt(&logger.file, vars)?;
+        }
+
+        for cmd in self.commands.iter_mut() {
+            cmd.expand(vars)
+                .chain_err(|| format!("Unable to expand variables of [{}]", cmd.name))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn into_parts(self) -> (ServiceConfig, Vec<CommandConfig>) {
+        (self.service, self.commands)
+    }
+}
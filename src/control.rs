@@ -0,0 +1,466 @@
+use errors::*;
+use humantime;
+use log::LogLevelFilter;
+use logging;
+use serde_json::{self, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use supervisor::{Maintenance, Supervisor};
+use tail::{self, Follower};
+use win::{self, to_wide, SecurityAttributes};
+use winapi::shared::minwindef::{DWORD, ULONG};
+use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+use winapi::um::fileapi::FlushFileBuffers;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, ImpersonateNamedPipeClient};
+use winapi::um::securitybaseapi::RevertToSelf;
+use winapi::um::winbase::{GetNamedPipeClientProcessId, GetUserNameW, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+    PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT};
+use winapi::um::winnt::{WinBuiltinAdministratorsSid, WinLocalSystemSid, FILE_WRITE_DATA, GENERIC_READ, HANDLE};
+
+const PIPE_BUFFER_SIZE: u32 = 4096;
+
+// full access for the local system and the administrators, who may change the supervisor, and just enough for
+// authenticated users to send a request and read the response, which are only served status and history,
+// without the right to create pipe instances of their own
+const PIPE_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;0x12008b;;;AU)";
+
+// requests are a few words, anything longer is no request
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+const DEFAULT_TAIL_LINES: usize = 20;
+const MAX_TAIL_LINES: usize = 10000;
+
+// how often a followed log is checked for new lines, and how long the client may go without a response,
+// as only a write notices that it went away
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+const FOLLOW_KEEPALIVE: Duration = Duration::from_secs(5);
+
+// requests are single lines of text, responses are single json documents
+#[derive(Debug)]
+pub enum Request {
+    Status,
+    Drain,
+    Maintenance(Maintenance),
+    Reload,
+    History,
+    PrintConfig,
+    Restart(String),
+    Start(String),
+
+    // has the child reload itself through its reload_cmd
+    ReloadCommand(String),
+
+    // queries the log level without one
+    LogLevel(Option<LogLevelFilter>),
+
+    // the last lines of the service log, or of the output of a command,
+    // following it streams further responses until the client disconnects
+    Tail { lines: usize, command: Option<String>, follow: bool },
+}
+
+// tail [<lines>] [<command>] [follow], in any order
+fn parse_tail(args: &[&str]) -> Result<Request> {
+    let mut lines = DEFAULT_TAIL_LINES;
+    let mut command = None;
+    let mut follow = false;
+
+    for arg in args {
+        if *arg == "follow" {
+            follow = true;
+        } else if let Ok(count) = arg.parse::<usize>() {
+            if count == 0 || count > MAX_TAIL_LINES {
+                bail!("Invalid number of lines to tail, expected 1 to {}: {}", MAX_TAIL_LINES, count);
+            }
+
+            lines = count;
+        } else if command.is_none() {
+            command = Some(arg.to_string());
+        } else {
+            bail!("Unknown tail argument: {}", arg);
+        }
+    }
+
+    Ok(Request::Tail { lines: lines, command: command, follow: follow })
+}
+
+impl FromStr for Request {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Request> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+
+        let request = match words.as_slice() {
+            ["status"] => Request::Status,
+            ["drain"] => Request::Drain,
+            ["reload"] => Request::Reload,
+            ["reload", name] => Request::ReloadCommand(name.to_string()),
+            ["history"] => Request::History,
+            ["print-config"] => Request::PrintConfig,
+            ["restart", name] => Request::Restart(name.to_string()),
+            ["start", name] => Request::Start(name.to_string()),
+            ["log-level"] => Request::LogLevel(None),
+            ["log-level", level] => Request::LogLevel(Some(level.parse()
+                .map_err(|_| format!("Invalid log level, expected off, error, warn, info, debug or trace: {}", level))?)),
+            ["tail", args @ ..] => parse_tail(args)?,
+            ["maintenance"] => Request::Maintenance(Maintenance::Toggle),
+            ["maintenance", "off"] => Request::Maintenance(Maintenance::Leave),
+            ["maintenance", duration] => Request::Maintenance(Maintenance::Enter(
+                humantime::parse_duration(duration)
+                    .chain_err(|| format!("Invalid maintenance duration: {}", duration))?)),
+            [] => bail!("Empty control request"),
+            [op, ..] => bail!("Unknown control operation or arguments: {}", op),
+        };
+
+        Ok(request)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub ok: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn handle(request: Request, supervisor: &Supervisor, initiator: &str) -> Result<Value> {
+    match request {
+        Request::Status => serde_json::to_value(supervisor.status())
+            .chain_err(|| "Unable to serialize status"),
+
+        Request::Drain => {
+            supervisor.drain();
+            Ok(Value::Null)
+        },
+
+        Request::Maintenance(maintenance) => {
+            supervisor.maintenance(maintenance);
+            Ok(Value::Null)
+        },
+
+        Request::Reload => serde_json::to_value(supervisor.reload(initiator)?)
+            .chain_err(|| "Unable to serialize reload summary"),
+
+        Request::LogLevel(None) => Ok(json!({ "level": logging::level().map(|level| level.to_string()) })),
+
+        Request::LogLevel(Some(level)) => {
+            let previous = logging::set_level(level)?;
+            info!("Changed log level from {} to {}", previous, level);
+            Ok(json!({ "level": level.to_string(), "previous": previous.to_string() }))
+        },
+
+        Request::History => serde_json::to_value(supervisor.history())
+            .chain_err(|| "Unable to serialize history"),
+
+        Request::PrintConfig => serde_json::to_value(supervisor.effective_config()?)
+            .chain_err(|| "Unable to serialize the effective config"),
+
+        Request::Restart(name) => {
+            supervisor.restart(&name, initiator)?;
+            Ok(Value::Null)
+        },
+
+        Request::Start(name) => {
+            supervisor.start_command(&name, initiator)?;
+            Ok(Value::Null)
+        },
+
+        Request::ReloadCommand(name) => {
+            supervisor.reload_command(&name, initiator)?;
+            Ok(Value::Null)
+        },
+
+        Request::Tail { follow: true, .. } => bail!("Following the log is only possible over the control pipe"),
+
+        Request::Tail { lines, command, .. } => {
+            let (lines, _) = tail_lines(lines, command.as_deref(), supervisor)?;
+            Ok(json!({ "lines": lines }))
+        },
+    }
+}
+
+fn tail_lines(count: usize, command: Option<&str>, supervisor: &Supervisor) -> Result<(Vec<String>, u64)> {
+    if let Some(name) = command {
+        if supervisor.state_of(name).is_none() {
+            bail!("Unknown command: {}", name);
+        }
+    }
+
+    let log_file = logging::log_file()
+        .ok_or("Logging is not initialized")?;
+
+    tail::last_lines(&log_file, count, command)
+        .chain_err(|| format!("Unable to read log file {:?}", log_file))
+}
+
+fn write_response(mut writer: &File, response: &Response) -> Result<()> {
+    serde_json::to_writer(&mut writer, response)
+        .chain_err(|| "Unable to write control response")?;
+
+    writer.write_all(b"\n")
+        .chain_err(|| "Unable to write control response")
+}
+
+fn lines_response(lines: Vec<String>) -> Response {
+    Response { ok: true, result: Some(json!({ "lines": lines })), error: None }
+}
+
+// writes the last lines, then a response line for every batch of new lines until the client goes away
+fn follow(pipe: &File, count: usize, command: Option<&str>, supervisor: &Supervisor) -> Result<()> {
+    let (lines, offset) = tail_lines(count, command, supervisor)?;
+    write_response(pipe, &lines_response(lines))?;
+
+    let log_file = logging::log_file()
+        .ok_or("Logging is not initialized")?;
+
+    let mut follower = Follower::new(&log_file, command, offset);
+    let mut last_write = Instant::now();
+
+    loop {
+        thread::sleep(FOLLOW_INTERVAL);
+
+        let lines = follower.next_lines()
+            .chain_err(|| format!("Unable to read log file {:?}", log_file))?;
+
+        if !lines.is_empty() || last_write.elapsed() >= FOLLOW_KEEPALIVE {
+            if write_response(pipe, &lines_response(lines)).is_err() {
+                return Ok(());
+            }
+
+            last_write = Instant::now();
+        }
+    }
+}
+
+fn create_instance(wide_name: &[u16], first: bool) -> io::Result<File> {
+    let mut security = SecurityAttributes::from_sddl(PIPE_SDDL)?;
+
+    let flags = if first {
+        PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE
+    } else {
+        PIPE_ACCESS_DUPLEX
+    };
+
+    let handle = unsafe {
+        CreateNamedPipeW(
+            wide_name.as_ptr(),
+            flags,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            PIPE_BUFFER_SIZE,
+            PIPE_BUFFER_SIZE,
+            0,
+            security.as_mut_ptr())
+    };
+
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { File::from_raw_handle(handle as _) })
+}
+
+fn connect(pipe: &File) -> io::Result<()> {
+    let connected = unsafe { ConnectNamedPipe(pipe.as_raw_handle() as _, ptr::null_mut()) };
+
+    // the client may have connected between the creation and the connect call
+    if connected == 0 {
+        let e = io::Error::last_os_error();
+
+        if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+// the user and process at the other end of the pipe, for the audit log
+fn client_identity(pipe: &File) -> String {
+    let handle = pipe.as_raw_handle() as HANDLE;
+    let mut pid: ULONG = 0;
+
+    let pid = if unsafe { GetNamedPipeClientProcessId(handle, &mut pid) } != 0 {
+        pid.to_string()
+    } else {
+        "?".to_owned()
+    };
+
+    // only possible once something was read from the pipe
+    let user = unsafe {
+        if ImpersonateNamedPipeClient(handle) != 0 {
+            let mut buf = [0u16; 257];
+            let mut len = buf.len() as DWORD;
+            let got = GetUserNameW(buf.as_mut_ptr(), &mut len) != 0;
+            RevertToSelf();
+
+            // the length includes the terminating null
+            if got && len > 0 {
+                Some(String::from_utf16_lossy(&buf[..len as usize - 1]))
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    };
+
+    format!("pipe client {} (pid {})", user.unwrap_or_else(|| "?".to_owned()), pid)
+}
+
+// whether the client may change the supervisor rather than just query it, as the local system or as an elevated
+// administrator, only possible once something was read from the pipe
+fn client_is_admin(pipe: &File) -> bool {
+    unsafe {
+        if ImpersonateNamedPipeClient(pipe.as_raw_handle() as HANDLE) == 0 {
+            return false;
+        }
+
+        let is_admin = win::is_member(WinLocalSystemSid) || win::is_member(WinBuiltinAdministratorsSid);
+        RevertToSelf();
+        is_admin
+    }
+}
+
+fn serve_client(pipe: File, supervisor: Arc<Supervisor>) -> Result<()> {
+    let mut line = String::new();
+
+    BufReader::new((&pipe).take(MAX_REQUEST_BYTES)).read_line(&mut line)
+        .chain_err(|| "Unable to read control request")?;
+
+    if line.len() as u64 >= MAX_REQUEST_BYTES && !line.ends_with('\n') {
+        bail!("Control request longer than {} bytes", MAX_REQUEST_BYTES);
+    }
+
+    let line = line.trim();
+    let initiator = client_identity(&pipe);
+    let is_admin = client_is_admin(&pipe);
+
+    debug!("Received control request from {}: {}", initiator, line);
+    supervisor.record_control(line, &initiator);
+
+    let result = line.parse().and_then(|request| match request {
+        Request::Status | Request::History => handle(request, &supervisor, &initiator).map(Some),
+        _ if !is_admin => bail!("Only the local system and elevated administrators may send {:?}, \
+            other users may query status and history", line),
+        Request::Tail { lines, command, follow: true } =>
+            follow(&pipe, lines, command.as_deref(), &supervisor).map(|_| None),
+        request => handle(request, &supervisor, &initiator).map(Some),
+    });
+
+    // a followed log has been written already
+    match result {
+        Ok(Some(result)) => write_response(&pipe, &Response { ok: true, result: Some(result), error: None })?,
+        Ok(None) => (),
+        Err(e) => {
+            warn!("Control request [{}] failed: {}", line, e);
+            write_response(&pipe, &Response { ok: false, result: None, error: Some(e.to_string()) })?;
+        },
+    }
+
+    // let the client drain the pipe before cutting it off
+    unsafe {
+        FlushFileBuffers(pipe.as_raw_handle() as _);
+        DisconnectNamedPipe(pipe.as_raw_handle() as _);
+    }
+
+    Ok(())
+}
+
+// serves control requests over the named pipe, one thread per connected client
+pub fn serve(pipe_name: &str, supervisor: Arc<Supervisor>) -> Result<JoinHandle<()>> {
+    let wide_name = to_wide(pipe_name);
+
+    // the first instance is created upfront so that a name clash fails the start
+    let first_pipe = create_instance(&wide_name, true)
+        .chain_err(|| format!("Unable to create control pipe {}", pipe_name))?;
+
+    let pipe_name = pipe_name.to_owned();
+
+    Ok(thread::spawn(move || {
+        let mut next_pipe = Some(first_pipe);
+
+        loop {
+            let pipe = match next_pipe.take().map(Ok).unwrap_or_else(|| create_instance(&wide_name, false)) {
+                Ok(pipe) => pipe,
+                Err(e) => {
+                    error!("Unable to create control pipe {}: {}", pipe_name, e);
+                    break;
+                },
+            };
+
+            if let Err(e) = connect(&pipe) {
+                error!("Unable to connect control pipe {}: {}", pipe_name, e);
+                continue;
+            }
+
+            let supervisor = supervisor.clone();
+
+            let _ = thread::spawn(move || {
+                if let Err(e) = serve_client(pipe, supervisor) {
+                    error!("Control client error: {}", e);
+                }
+            });
+        }
+    }))
+}
+
+// opens the pipe without FILE_APPEND_DATA, which the pipe only grants to the administrators since it would allow
+// creating instances, so that other users can query status too
+fn open_pipe(pipe_name: &str) -> Result<File> {
+    OpenOptions::new()
+        .access_mode(GENERIC_READ | FILE_WRITE_DATA)
+        .open(pipe_name)
+        .chain_err(|| format!("Unable to open control pipe {}, is the service running?", pipe_name))
+}
+
+// client side of the control pipe
+pub fn request(pipe_name: &str, request: &str) -> Result<Response> {
+    let mut pipe = open_pipe(pipe_name)?;
+
+    pipe.write_all(format!("{}\n", request).as_bytes())
+        .chain_err(|| "Unable to write control request")?;
+
+    let mut response = String::new();
+
+    pipe.read_to_string(&mut response)
+        .chain_err(|| "Unable to read control response")?;
+
+    serde_json::from_str(&response)
+        .chain_err(|| format!("Invalid control response: {}", response))
+}
+
+// client side of a request answered with a response per line, such as a followed tail,
+// until the service closes the pipe or on_response returns false
+pub fn request_stream<F>(pipe_name: &str, request: &str, mut on_response: F) -> Result<()>
+    where F: FnMut(Response) -> bool
+{
+    let mut pipe = open_pipe(pipe_name)?;
+
+    pipe.write_all(format!("{}\n", request).as_bytes())
+        .chain_err(|| "Unable to write control request")?;
+
+    for line in BufReader::new(pipe).lines() {
+        let line = line.chain_err(|| "Unable to read control response")?;
+
+        let response = serde_json::from_str(&line)
+            .chain_err(|| format!("Invalid control response: {}", line))?;
+
+        if !on_response(response) {
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use chrono::{Local, TimeZone};
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const HOUR_SECS: i64 = 60 * 60;
+
+// restarts stamped ahead of now, after the clock was set back, would otherwise count for longer than an hour
+fn within_hour(now: i64, time: i64) -> bool {
+    (0..HOUR_SECS).contains(&(now - time))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+struct Entry {
+    restarts: u64,
+
+    // unix times of the restarts within the last hour
+    recent_restarts: Vec<i64>,
+
+    last_exit_code: Option<i32>,
+
+    // unix time
+    last_crash: Option<i64>,
+
+    // failed runs in a row, which the backoff grows with
+    failures: u32,
+
+    // unix time the current backoff ends at
+    backoff_until: Option<i64>,
+
+    // the running child and when it was created, cleared once it exits, to kill it on the next start of the
+    // service if it outlived a killed one
+    pid: Option<u32>,
+    created: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct CommandCounters {
+    pub restarts: u64,
+    pub restarts_last_hour: usize,
+    pub last_exit_code: Option<i32>,
+    pub last_crash_at: Option<String>,
+}
+
+// what the previous run of the service left off at for a command
+pub struct Saved {
+    pub failures: u32,
+    pub backoff_left: Option<Duration>,
+
+    // pid and creation time of a child that was running when the previous run ended
+    pub child: Option<(u32, u64)>,
+}
+
+// per command restart and exit counters and backoff, kept in a json file so that they survive service restarts
+// and reboots, a crash looping child is then not given a fresh start by restarting the service
+pub struct Counters {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl Counters {
+    // starts afresh if the file is missing or unreadable
+    pub fn load(path: &Path) -> Counters {
+        let entries = match fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Unable to parse counters {:?}, starting afresh: {}", path, e);
+                BTreeMap::new()
+            }),
+
+            Err(_) => BTreeMap::new(),
+        };
+
+        Counters {
+            path: path.to_owned(),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn record_restart(&self, command: &str) {
+        let now = Local::now().timestamp();
+
+        self.update(command, |entry| {
+            entry.restarts += 1;
+            entry.recent_restarts.retain(|&time| within_hour(now, time));
+            entry.recent_restarts.push(now);
+        });
+    }
+
+    pub fn record_spawn(&self, command: &str, pid: u32, created: Option<u64>) {
+        self.update(command, |entry| {
+            entry.pid = Some(pid);
+            entry.created = created;
+            entry.backoff_until = None;
+        });
+    }
+
+    pub fn record_backoff(&self, command: &str, failures: u32, delay: Duration) {
+        let until = Local::now().timestamp() + delay.as_secs() as i64;
+
+        self.update(command, |entry| {
+            entry.failures = failures;
+            entry.backoff_until = Some(until);
+        });
+    }
+
+    // a child that ran long enough is considered healthy again
+    pub fn reset_backoff(&self, command: &str) {
+        self.update(command, |entry| {
+            entry.failures = 0;
+            entry.backoff_until = None;
+        });
+    }
+
+    pub fn saved(&self, command: &str) -> Saved {
+        let now = Local::now().timestamp();
+        let entries = self.entries.lock().unwrap();
+
+        match entries.get(command) {
+            Some(entry) => Saved {
+                failures: entry.failures,
+                backoff_left: entry.backoff_until
+                    .filter(|&until| until > now)
+                    .map(|until| Duration::from_secs((until - now) as u64)),
+                child: entry.pid.zip(entry.created),
+            },
+
+            None => Saved {
+                failures: 0,
+                backoff_left: None,
+                child: None,
+            },
+        }
+    }
+
+    pub fn record_exit(&self, command: &str, exit_code: Option<i32>, crashed: bool) {
+        self.update(command, |entry| {
+            entry.last_exit_code = exit_code;
+            entry.pid = None;
+            entry.created = None;
+
+            if crashed {
+                entry.last_crash = Some(Local::now().timestamp());
+            }
+        });
+    }
+
+    pub fn get(&self, command: &str) -> CommandCounters {
+        let now = Local::now().timestamp();
+        let entries = self.entries.lock().unwrap();
+
+        match entries.get(command) {
+            Some(entry) => CommandCounters {
+                restarts: entry.restarts,
+                restarts_last_hour: entry.recent_restarts.iter().filter(|&&time| within_hour(now, time)).count(),
+                last_exit_code: entry.last_exit_code,
+                last_crash_at: entry.last_crash
+                    .and_then(|time| Local.timestamp_opt(time, 0).single())
+                    .map(|time| time.to_rfc3339()),
+            },
+
+            None => CommandCounters::default(),
+        }
+    }
+
+    fn update<F: FnOnce(&mut Entry)>(&self, command: &str, f: F) {
+        let mut entries = self.entries.lock().unwrap();
+        f(entries.entry(command.to_owned()).or_default());
+
+        // replaced in one go, so that a crash never leaves a torn file behind
+        let staged = self.path.with_extension("json.tmp");
+
+        let res = serde_json::to_vec_pretty(&*entries)
+            .map_err(|e| e.into())
+            .and_then(|content| fs::write(&staged, content))
+            .and_then(|_| fs::rename(&staged, &self.path));
+
+        if let Err(e) = res {
+            warn!("Unable to save counters to {:?}: {}", self.path, e);
+        }
+    }
+}
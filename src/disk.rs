@@ -0,0 +1,77 @@
+use eventlog::{self, Lifecycle};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use supervisor::Supervisor;
+use win;
+
+const MB: u64 = 1024 * 1024;
+
+pub struct DiskMonitor {
+    // volume roots to the paths on them, for the log messages
+    volumes: BTreeMap<String, Vec<PathBuf>>,
+    min_free_mb: u64,
+    low: BTreeMap<String, bool>,
+    events: Lifecycle,
+}
+
+impl DiskMonitor {
+    pub fn new(paths: &[PathBuf], min_free_mb: u64, events: Lifecycle) -> DiskMonitor {
+        let mut volumes = BTreeMap::<String, Vec<PathBuf>>::new();
+
+        for path in paths {
+            match win::volume_of(path) {
+                Ok(volume) => volumes.entry(volume.to_uppercase()).or_default().push(path.clone()),
+                Err(e) => warn!("Unable to find the volume of {:?}, its free space is not checked: {}", path, e),
+            }
+        }
+
+        DiskMonitor {
+            volumes: volumes,
+            min_free_mb: min_free_mb,
+            low: BTreeMap::new(),
+            events: events,
+        }
+    }
+
+    // alerts on every volume that ran low or recovered since the last check, returns whether any is low
+    pub fn check(&mut self) -> bool {
+        for (volume, paths) in &self.volumes {
+            let free_mb = match win::free_space(volume) {
+                Ok(free) => free / MB,
+                Err(e) => {
+                    debug!("Unable to query free space of {}: {}", volume, e);
+                    continue;
+                },
+            };
+
+            let low = free_mb < self.min_free_mb;
+            let was_low = self.low.insert(volume.clone(), low).unwrap_or(false);
+
+            if low && !was_low {
+                warn!("Volume {} holding {:?} is low on space with {} MB free, below min_free_space_mb of {} MB",
+                    volume, paths, free_mb, self.min_free_mb);
+
+                self.events.warn(eventlog::EVENT_LOW_DISK,
+                    &[volume, &free_mb.to_string(), &self.min_free_mb.to_string()]);
+            } else if !low && was_low {
+                info!("Volume {} has {} MB free again", volume, free_mb);
+                self.events.info(eventlog::EVENT_DISK_RECOVERED, &[volume, &free_mb.to_string()]);
+            }
+        }
+
+        self.low.values().any(|&low| low)
+    }
+}
+
+// keeps checking every interval, pausing and resuming the commands with pause_on_low_disk accordingly
+pub fn spawn_monitor(mut monitor: DiskMonitor, interval: Duration, supervisor: Arc<Supervisor>) {
+    let _ = thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            supervisor.set_low_disk(monitor.check());
+        }
+    });
+}
@@ -0,0 +1,117 @@
+use std::io;
+use std::ptr;
+use win::to_wide;
+use winapi::shared::minwindef::{DWORD, WORD};
+use winapi::um::winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW};
+use winapi::um::winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, LPCWSTR};
+
+// event ids, the source has no message file registered,
+// so event viewer shows the message as the only inserted string
+pub const EVENT_PANIC: DWORD = 1000;
+// log records while the log file cannot be written
+pub const EVENT_LOG: DWORD = 1001;
+// the service failed before starting any command
+pub const EVENT_STARTUP_FAILURE: DWORD = 1002;
+
+// lifecycle event ids, each value is its own inserted string, which event forwarding renders as a <Data> element
+// of EventData in the order given here, so that collectors and siem parsers pick fields by position
+
+// service name, version with its commit, build time, target and profile
+pub const EVENT_SERVICE_START: DWORD = 100;
+// service name, SERVICE_STOP_REASON code, reason
+pub const EVENT_SERVICE_STOP: DWORD = 101;
+// volume, free megabytes, threshold in megabytes
+pub const EVENT_LOW_DISK: DWORD = 102;
+// volume, free megabytes
+pub const EVENT_DISK_RECOVERED: DWORD = 103;
+// health, commands waiting out a backoff
+pub const EVENT_HEALTH: DWORD = 104;
+// command, pid, command line
+pub const EVENT_SPAWN: DWORD = 200;
+// command, exit code, meaning of the exit code
+pub const EVENT_EXIT: DWORD = 201;
+// command, exit code or spawn error, meaning of the exit code
+pub const EVENT_CRASH: DWORD = 202;
+// command, reason
+pub const EVENT_RESTART: DWORD = 203;
+// command, exit code
+pub const EVENT_STOP: DWORD = 204;
+// command, exit code or spawn error, meaning of the exit code, for failures during maintenance that raise no alert
+pub const EVENT_MAINTENANCE_EXIT: DWORD = 205;
+
+// the event log refuses longer strings
+const MAX_MESSAGE_CHARS: usize = 31000;
+
+// writes to the application log under the given source, normally the service name
+pub fn report(source: &str, kind: WORD, id: DWORD, message: &str) -> io::Result<()> {
+    report_fields(source, kind, id, &[message])
+}
+
+pub fn report_fields(source: &str, kind: WORD, id: DWORD, fields: &[&str]) -> io::Result<()> {
+    let source = to_wide(source);
+
+    let fields = fields.iter()
+        .map(|field| to_wide(field.chars().take(MAX_MESSAGE_CHARS).collect::<String>()))
+        .collect::<Vec<_>>();
+
+    unsafe {
+        let handle = RegisterEventSourceW(ptr::null(), source.as_ptr());
+
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut strings = fields.iter().map(|field| field.as_ptr()).collect::<Vec<LPCWSTR>>();
+
+        let reported = ReportEventW(handle, kind, 0, id, ptr::null_mut(), strings.len() as WORD, 0, strings.as_mut_ptr(),
+            ptr::null_mut());
+        let e = io::Error::last_os_error();
+        DeregisterEventSource(handle);
+
+        if reported == 0 {
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn error(source: &str, id: DWORD, message: &str) -> io::Result<()> {
+    report(source, EVENTLOG_ERROR_TYPE, id, message)
+}
+
+// lifecycle events in the application log, if enabled
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    source: Option<String>,
+}
+
+impl Lifecycle {
+    pub fn new(source: &str) -> Lifecycle {
+        Lifecycle { source: Some(source.to_owned()) }
+    }
+
+    pub fn disabled() -> Lifecycle {
+        Lifecycle { source: None }
+    }
+
+    pub fn info(&self, id: DWORD, fields: &[&str]) {
+        self.record(EVENTLOG_INFORMATION_TYPE, id, fields);
+    }
+
+    pub fn warn(&self, id: DWORD, fields: &[&str]) {
+        self.record(EVENTLOG_WARNING_TYPE, id, fields);
+    }
+
+    pub fn error(&self, id: DWORD, fields: &[&str]) {
+        self.record(EVENTLOG_ERROR_TYPE, id, fields);
+    }
+
+    fn record(&self, kind: WORD, id: DWORD, fields: &[&str]) {
+        if let Some(ref source) = self.source {
+            if let Err(e) = report_fields(source, kind, id, fields) {
+                warn!("Unable to write event {} to the event log: {}", id, e);
+            }
+        }
+    }
+}
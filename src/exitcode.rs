@@ -0,0 +1,61 @@
+use std::process::ExitStatus;
+use win;
+
+// the usual suspects among crashing children, with what they mostly come down to
+const KNOWN: &[(u32, &str, &str)] = &[
+    (0x4001_0004, "DBG_TERMINATE_PROCESS", "terminated by a debugger"),
+    (0x8000_0003, "STATUS_BREAKPOINT", "breakpoint hit without a debugger attached"),
+    (0xC000_0005, "STATUS_ACCESS_VIOLATION", "access violation"),
+    (0xC000_0017, "STATUS_NO_MEMORY", "out of memory"),
+    (0xC000_001D, "STATUS_ILLEGAL_INSTRUCTION", "illegal instruction"),
+    (0xC000_007B, "STATUS_INVALID_IMAGE_FORMAT", "invalid executable or dll, e.g. a 32/64-bit mismatch"),
+    (0xC000_0094, "STATUS_INTEGER_DIVIDE_BY_ZERO", "integer division by zero"),
+    (0xC000_00FD, "STATUS_STACK_OVERFLOW", "stack overflow"),
+    (0xC000_0135, "STATUS_DLL_NOT_FOUND", "a required dll was not found"),
+    (0xC000_0139, "STATUS_ENTRYPOINT_NOT_FOUND", "a function was not found in a dll, likely a dll version mismatch"),
+    (0xC000_013A, "STATUS_CONTROL_C_EXIT", "terminated by ctrl-c"),
+    (0xC000_0142, "STATUS_DLL_INIT_FAILED", "a dll failed to initialize, e.g. in a non-interactive session"),
+    (0xC000_0374, "STATUS_HEAP_CORRUPTION", "heap corruption"),
+    (0xC000_0409, "STATUS_STACK_BUFFER_OVERRUN", "stack buffer overrun or fail fast abort"),
+    (0xC000_0417, "STATUS_INVALID_CRUNTIME_PARAMETER", "invalid parameter passed to the c runtime"),
+    (0xC06D_007E, "VcppException", "a delay loaded dll was not found"),
+    (0xE043_4352, "CLR exception", "unhandled .NET exception"),
+    (0xE06D_7363, "C++ exception", "unhandled C++ exception"),
+];
+
+// what an exit code most likely means, if anything beyond success or the generic failure,
+// codes with the error or warning severity bits set are ntstatus values, smaller ones may be win32 errors
+fn meaning(code: u32) -> Option<String> {
+    if let Some(&(_, name, description)) = KNOWN.iter().find(|&&(known, _, _)| known == code) {
+        return Some(format!("{} ({})", description, name));
+    }
+
+    match code {
+        0 | 1 => None,
+        0x8000_0000..=0xFFFF_FFFF => win::error_message(code, true),
+        0x2..=0xFFFF => win::error_message(code, false).map(|message| format!("if a win32 error: {}", message)),
+        _ => None,
+    }
+}
+
+// the exit code, in hex when it is an ntstatus
+pub fn raw(exit_status: &ExitStatus) -> String {
+    match exit_status.code() {
+        Some(code) if code as u32 >= 0x4000_0000 => format!("{:#010X}", code as u32),
+        Some(code) => format!("{}", code),
+        None => format!("{}", exit_status),
+    }
+}
+
+// the decoding of the exit code, if known
+pub fn meaning_of(exit_status: &ExitStatus) -> Option<String> {
+    exit_status.code().and_then(|code| meaning(code as u32))
+}
+
+// the raw exit code followed by its decoding if known
+pub fn describe(exit_status: &ExitStatus) -> String {
+    match meaning_of(exit_status) {
+        Some(meaning) => format!("exit code {}, {}", raw(exit_status), meaning),
+        None => format!("exit code {}", raw(exit_status)),
+    }
+}
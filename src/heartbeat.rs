@@ -0,0 +1,60 @@
+use chrono::Local;
+use serde_json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use supervisor::{Health, Supervisor};
+
+#[derive(Serialize)]
+struct Heartbeat {
+    time: String,
+    pid: u32,
+    health: Health,
+    unhealthy: Vec<String>,
+}
+
+fn write(path: &Path, heartbeat: &Heartbeat) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // replaced in one go, so that a reader never sees a torn file
+    let staged = path.with_extension("tmp");
+
+    fs::write(&staged, serde_json::to_vec_pretty(heartbeat)?)?;
+    fs::rename(&staged, path)
+}
+
+// the health of the service as a small json file rewritten every interval, for monitoring agents that only read
+// files, a time older than the interval means the service hangs or is gone
+pub fn spawn_writer(path: PathBuf, interval: Duration, supervisor: Arc<Supervisor>) {
+    let _ = thread::spawn(move || {
+        let mut failing = false;
+
+        loop {
+            let heartbeat = Heartbeat {
+                time: Local::now().to_rfc3339(),
+                pid: process::id(),
+                health: supervisor.health(),
+                unhealthy: supervisor.unhealthy(),
+            };
+
+            match write(&path, &heartbeat) {
+                Ok(()) => failing = false,
+                Err(e) => {
+                    if !failing {
+                        warn!("Unable to write heartbeat to {:?}: {}", path, e);
+                    }
+
+                    failing = true;
+                },
+            }
+
+            thread::sleep(interval);
+        }
+    });
+}
@@ -0,0 +1,63 @@
+use chrono::Local;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Spawn,
+    Exit,
+    Restart,
+    Stop,
+    Control,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Event {
+    pub time: String,
+    pub kind: EventKind,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+
+    pub detail: String,
+}
+
+// the most recent supervisor events, the oldest are dropped once full
+pub struct History {
+    capacity: usize,
+    events: Mutex<VecDeque<Event>>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            capacity: capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, kind: EventKind, command: Option<&str>, detail: String) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut events = self.events.lock().unwrap();
+
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+
+        events.push_back(Event {
+            time: Local::now().to_rfc3339(),
+            kind: kind,
+            command: command.map(|command| command.to_owned()),
+            detail: detail,
+        });
+    }
+
+    // oldest first
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+}
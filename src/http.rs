@@ -0,0 +1,101 @@
+use std::io;
+use std::mem;
+use std::ptr;
+use win::to_wide;
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::um::winhttp::{WinHttpCloseHandle, WinHttpConnect, WinHttpCrackUrl, WinHttpOpen, WinHttpOpenRequest,
+    WinHttpQueryHeaders, WinHttpReadData, WinHttpReceiveResponse, WinHttpSendRequest, HINTERNET,
+    INTERNET_SCHEME_HTTPS, URL_COMPONENTS, WINHTTP_ACCESS_TYPE_DEFAULT_PROXY, WINHTTP_FLAG_SECURE,
+    WINHTTP_QUERY_FLAG_NUMBER, WINHTTP_QUERY_STATUS_CODE};
+
+struct Handle(HINTERNET);
+
+impl Handle {
+    fn new(handle: HINTERNET) -> io::Result<Handle> {
+        if handle.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Handle(handle))
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe {
+            WinHttpCloseHandle(self.0);
+        }
+    }
+}
+
+fn check(ok: i32) -> io::Result<()> {
+    if ok == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// downloads the body of an http or https url with winhttp, which follows redirects
+// and uses the machine proxy settings, so that no tls stack has to be shipped
+pub fn get(url: &str) -> io::Result<Vec<u8>> {
+    let wide_url = to_wide(url);
+
+    let mut components: URL_COMPONENTS = unsafe { mem::zeroed() };
+    components.dwStructSize = mem::size_of::<URL_COMPONENTS>() as DWORD;
+
+    // non-zero lengths without buffers make the parts point into the url
+    components.dwHostNameLength = DWORD::max_value();
+    components.dwUrlPathLength = DWORD::max_value();
+    components.dwExtraInfoLength = DWORD::max_value();
+
+    unsafe {
+        check(WinHttpCrackUrl(wide_url.as_ptr(), 0, 0, &mut components))?;
+
+        let host = to_wide(String::from_utf16_lossy(
+            ::std::slice::from_raw_parts(components.lpszHostName, components.dwHostNameLength as usize)));
+
+        // the path runs on into the query string
+        let path = to_wide(String::from_utf16_lossy(::std::slice::from_raw_parts(
+            components.lpszUrlPath, (components.dwUrlPathLength + components.dwExtraInfoLength) as usize)));
+
+        let flags = if components.nScheme == INTERNET_SCHEME_HTTPS { WINHTTP_FLAG_SECURE } else { 0 };
+
+        let session = Handle::new(WinHttpOpen(
+            to_wide("windows_service").as_ptr(), WINHTTP_ACCESS_TYPE_DEFAULT_PROXY, ptr::null(), ptr::null(), 0))?;
+
+        let connection = Handle::new(WinHttpConnect(session.0, host.as_ptr(), components.nPort, 0))?;
+
+        let request = Handle::new(WinHttpOpenRequest(
+            connection.0, to_wide("GET").as_ptr(), path.as_ptr(), ptr::null(), ptr::null(), ptr::null_mut(), flags))?;
+
+        check(WinHttpSendRequest(request.0, ptr::null(), 0, ptr::null_mut(), 0, 0, 0))?;
+        check(WinHttpReceiveResponse(request.0, ptr::null_mut()))?;
+
+        let mut status: DWORD = 0;
+        let mut status_len = mem::size_of::<DWORD>() as DWORD;
+
+        check(WinHttpQueryHeaders(request.0, WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER, ptr::null(),
+            &mut status as *mut DWORD as LPVOID, &mut status_len, ptr::null_mut()))?;
+
+        if status != 200 {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("HTTP status {} for {}", status, url)));
+        }
+
+        let mut body = Vec::new();
+        let mut buf = [0u8; 8192];
+
+        loop {
+            let mut read: DWORD = 0;
+            check(WinHttpReadData(request.0, buf.as_mut_ptr() as LPVOID, buf.len() as DWORD, &mut read))?;
+
+            if read == 0 {
+                break;
+            }
+
+            body.extend_from_slice(&buf[..read as usize]);
+        }
+
+        Ok(body)
+    }
+}
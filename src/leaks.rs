@@ -0,0 +1,144 @@
+use logging;
+use std::collections::{HashMap, HashSet};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use supervisor::Supervisor;
+use win;
+
+// consecutive checks with growth before a count is reported as a possible leak
+const RISING_CHECKS: u32 = 6;
+
+// a count that is suspected to leak when it keeps growing from one check to the next
+struct Series {
+    rising_from: u64,
+    last: u64,
+    rising: u32,
+}
+
+impl Series {
+    fn new(value: u64) -> Series {
+        Series { rising_from: value, last: value, rising: 0 }
+    }
+
+    // returns the value the growth started at once it grew on another RISING_CHECKS checks in a row
+    fn sample(&mut self, value: u64) -> Option<u64> {
+        if value > self.last {
+            self.rising += 1;
+        } else if value < self.last {
+            self.rising_from = value;
+            self.rising = 0;
+        }
+
+        self.last = value;
+
+        if self.rising > 0 && self.rising % RISING_CHECKS == 0 {
+            Some(self.rising_from)
+        } else {
+            None
+        }
+    }
+}
+
+struct Auditor {
+    series: HashMap<String, Series>,
+    strays: HashSet<u32>,
+    reported: HashSet<u32>,
+}
+
+impl Auditor {
+    fn sample(&mut self, what: String, value: u64) {
+        let rising_from = match self.series.get_mut(&what) {
+            Some(series) => series.sample(value),
+            None => {
+                self.series.insert(what.clone(), Series::new(value));
+                None
+            },
+        };
+
+        if let Some(rising_from) = rising_from {
+            warn!("The {} grew on {} consecutive checks from {} to {}, which may be a leak", what, RISING_CHECKS, rising_from, value);
+        }
+    }
+
+    fn check(&mut self, supervisor: &Supervisor) {
+        let own_pid = process::id();
+        let commands = supervisor.status().commands;
+
+        let mut seen = HashSet::new();
+
+        match win::process_usage(own_pid) {
+            Ok(usage) => self.sample("handle count of the supervisor".to_owned(), u64::from(usage.handles)),
+            Err(e) => debug!("Unable to query the handle count of the supervisor: {}", e),
+        }
+
+        seen.insert("handle count of the supervisor".to_owned());
+
+        // a respawned child starts a series of its own
+        for status in &commands {
+            if let Some(pid) = status.pid {
+                let what = format!("handle count of [{}] pid={}", status.name, pid);
+
+                match win::process_usage(pid) {
+                    Ok(usage) => self.sample(what.clone(), u64::from(usage.handles)),
+                    Err(e) => debug!(target: &logging::target(&status.name),
+                        "Unable to query the handle count of [{}]: {}", status.name, e),
+                }
+
+                seen.insert(what);
+            }
+        }
+
+        match win::processes() {
+            Ok(processes) => {
+                if let Some(own) = processes.iter().find(|entry| entry.pid == own_pid) {
+                    self.sample("thread count of the supervisor".to_owned(), u64::from(own.threads));
+                }
+
+                seen.insert("thread count of the supervisor".to_owned());
+
+                // children of the supervisor that no command accounts for were left behind by an earlier run,
+                // e.g. a spawn that failed half way or a kill that did not go through
+                let tracked = commands.iter().filter_map(|status| status.pid).collect::<HashSet<_>>();
+
+                let strays = processes.iter()
+                    .filter(|entry| entry.parent_pid == own_pid && !tracked.contains(&entry.pid))
+                    .collect::<Vec<_>>();
+
+                // only once seen on two checks, as a child may have been spawned after the status was taken
+                for stray in &strays {
+                    if self.strays.contains(&stray.pid) && self.reported.insert(stray.pid) {
+                        warn!("Process {} pid={} was started by the supervisor but no command tracks it, it may be a leftover",
+                            stray.exe, stray.pid);
+                    }
+                }
+
+                self.strays = strays.iter().map(|stray| stray.pid).collect();
+                let strays = &self.strays;
+                self.reported.retain(|pid| strays.contains(pid));
+            },
+            Err(e) => debug!("Unable to list processes: {}", e),
+        }
+
+        // exited children must not pile up
+        self.series.retain(|what, _| seen.contains(what));
+    }
+}
+
+// checks the handles and threads of the supervisor and the handles of its children every interval, warning on
+// steady growth, which long supervision with many restarts would otherwise only reveal once resources run out
+pub fn spawn_auditor(interval: Duration, supervisor: Arc<Supervisor>) {
+    let _ = thread::spawn(move || {
+        let mut auditor = Auditor {
+            series: HashMap::new(),
+            strays: HashSet::new(),
+            reported: HashSet::new(),
+        };
+
+        loop {
+            thread::sleep(interval);
+            auditor.check(&supervisor);
+        }
+    });
+}
@@ -0,0 +1,83 @@
+use command;
+use config::{CommandConfig, CommandKind};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+// commands built into cmd.exe, which are never found on disk
+const CMD_BUILTINS: &[&str] = &[
+    "assoc", "call", "cd", "chdir", "cls", "copy", "del", "dir", "echo", "erase", "for", "ftype",
+    "if", "md", "mkdir", "move", "path", "pause", "pushd", "popd", "rd", "ren", "rename", "rmdir",
+    "set", "setlocal", "start", "time", "title", "type", "ver",
+];
+
+// likely mistakes that still make a valid config, one human readable warning each
+pub fn lint(cmds: &[CommandConfig]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut names = HashSet::new();
+
+    for cmd in cmds {
+        if !names.insert(cmd.name.as_str()) {
+            warnings.push(format!("[{}] duplicate command name, reload is unable to tell the commands apart", cmd.name));
+        }
+
+        match cmd.kind {
+            CommandKind::Shell => {
+                if let Some((program, _)) = command::split_program(&cmd.cmd) {
+                    if !program_exists(program, cmd.cwd.as_ref().map(Path::new)) {
+                        warnings.push(format!("[{}] executable {:?} not found", cmd.name, program));
+                    }
+                }
+
+                if let Some(c) = unescaped_metachar(&cmd.cmd) {
+                    warnings.push(format!(
+                        "[{}] cmd contains unescaped shell metacharacter '{}', escape it as ^{} or quote it if it is meant literally",
+                        cmd.name, c, c));
+                }
+            },
+
+            CommandKind::PowershellScript => {
+                if let Some(ref script) = cmd.script {
+                    if !Path::new(script).is_file() {
+                        warnings.push(format!("[{}] script {:?} not found", cmd.name, script));
+                    }
+                }
+
+                if cmd.codepage.is_some() {
+                    warnings.push(format!("[{}] codepage only applies to shell commands run through cmd.exe", cmd.name));
+                }
+            },
+        }
+
+        if let Some(ref cwd) = cmd.cwd {
+            if let Err(e) = fs::read_dir(cwd) {
+                warnings.push(format!("[{}] working directory {:?} is not readable: {}", cmd.name, cwd, e));
+            }
+        }
+    }
+
+    warnings
+}
+
+fn program_exists(program: &str, cwd: Option<&Path>) -> bool {
+    program.is_empty() || CMD_BUILTINS.contains(&program.to_lowercase().as_str()) || command::find_program(program, cwd).is_some()
+}
+
+// &, |, < and > outside of double quotes and not preceded by ^,
+// which cmd.exe treats as command separators and redirections
+fn unescaped_metachar(line: &str) -> Option<char> {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in line.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '^' if !quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '&' | '|' | '<' | '>' if !quoted => return Some(c),
+            _ => (),
+        }
+    }
+
+    None
+}
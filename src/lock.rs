@@ -0,0 +1,41 @@
+use errors::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::windows::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process;
+use winapi::shared::winerror::ERROR_SHARING_VIOLATION;
+use winapi::um::winnt::FILE_SHARE_READ;
+
+// held open for as long as the supervisor runs, the os releases it even on a crash
+pub struct InstanceLock {
+    _file: File,
+}
+
+// fails if another supervisor instance already runs off the same config,
+// since both would then manage the same children
+pub fn acquire(lock_path: &Path) -> Result<InstanceLock> {
+    let res = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .share_mode(FILE_SHARE_READ)
+        .open(lock_path);
+
+    let mut file = match res {
+        Ok(file) => file,
+        Err(ref e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION as i32) => {
+            let owner = fs::read_to_string(lock_path).unwrap_or_default();
+
+            bail!("Another instance (pid {}) already holds {:?}, refusing to manage the same commands",
+                owner.trim(), lock_path);
+        },
+        Err(e) => return Err(e).chain_err(|| format!("Unable to create lock file {:?}", lock_path)),
+    };
+
+    // readable by others thanks to FILE_SHARE_READ, for the error above
+    write!(file, "{}", process::id())
+        .chain_err(|| format!("Unable to write lock file {:?}", lock_path))?;
+
+    Ok(InstanceLock { _file: file })
+}
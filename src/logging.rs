@@ -0,0 +1,365 @@
+use config::{LogEncoding, LoggerConfig, SyslogConfig};
+use errors::*;
+use eventlog;
+use log::{LogLevel, LogLevelFilter, LogRecord};
+use log4rs;
+use log4rs::Handle;
+use log4rs::append::Append;
+use log4rs::append::console::{ConsoleAppender, Target};
+use log4rs::append::file::FileAppender;
+use log4rs::config::{Appender, Config, Logger, Root};
+use log4rs::encode::Encode;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::filter::threshold::ThresholdFilter;
+use output;
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use syslog::SyslogAppender;
+use winapi::um::winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE};
+
+type LogWriter = Arc<Mutex<SimpleWriter<BufWriter<File>>>>;
+
+const PATTERN: &str = "{h({d(%Y-%m-%d %H:%M:%S %Z)} [{l}] - {m}{n})}";
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// the log target of what the supervisor logs about a command, e.g. cmd::nginx,
+// so that loggers can give each command its own level and log file
+pub fn target(command: &str) -> String {
+    format!("cmd::{}", command)
+}
+
+// log records in the application event log while the log file cannot be written, so that the service keeps
+// running and its log is not lost, leaving out debug records and child output, which would flood it
+#[derive(Debug)]
+struct EventLogAppender {
+    source: String,
+    encoder: PatternEncoder,
+}
+
+impl EventLogAppender {
+    fn new(source: &str) -> EventLogAppender {
+        EventLogAppender {
+            source: source.to_owned(),
+            encoder: PatternEncoder::new("{m}"),
+        }
+    }
+}
+
+impl Append for EventLogAppender {
+    fn append(&self, record: &LogRecord) -> ::std::result::Result<(), Box<dyn StdError + Sync + Send>> {
+        if record.level() > LogLevel::Info || output::is_output(record.target()) {
+            return Ok(());
+        }
+
+        let mut message = SimpleWriter(Vec::new());
+        self.encoder.encode(&mut message, record)?;
+
+        let kind = match record.level() {
+            LogLevel::Error => EVENTLOG_ERROR_TYPE,
+            LogLevel::Warn => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+
+        eventlog::report(&self.source, kind, eventlog::EVENT_LOG, &String::from_utf8_lossy(&message.0))?;
+        Ok(())
+    }
+}
+
+// like the log4rs file appender, which only flushes into the os cache,
+// but forces error records to disk so that the lines before a crash of the machine are kept
+#[derive(Debug)]
+struct SyncingFileAppender {
+    file: LogWriter,
+    log_file: PathBuf,
+    encoder: PatternEncoder,
+
+    // a full disk makes writes fail, the file is retried with every record
+    failing: AtomicBool,
+    fallback: EventLogAppender,
+}
+
+impl SyncingFileAppender {
+    fn write(&self, record: &LogRecord) -> ::std::result::Result<(), Box<dyn StdError + Sync + Send>> {
+        // a panic while logging must not stop all further logging
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        self.encoder.encode(&mut *file, record)?;
+        file.flush()?;
+
+        if record.level() <= LogLevel::Error {
+            (file.0).get_ref().sync_data()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Append for SyncingFileAppender {
+    fn append(&self, record: &LogRecord) -> ::std::result::Result<(), Box<dyn StdError + Sync + Send>> {
+        match self.write(record) {
+            Ok(()) => {
+                if self.failing.swap(false, Ordering::SeqCst) {
+                    let _ = eventlog::report(&self.fallback.source, EVENTLOG_INFORMATION_TYPE, eventlog::EVENT_LOG,
+                        &format!("Log file {:?} is writable again, logging to it", self.log_file));
+                }
+
+                Ok(())
+            },
+
+            Err(e) => {
+                if !self.failing.swap(true, Ordering::SeqCst) {
+                    let _ = eventlog::report(&self.fallback.source, EVENTLOG_ERROR_TYPE, eventlog::EVENT_LOG,
+                        &format!("Unable to write log file {:?}, logging to the event log until it is writable: {}",
+                            self.log_file, e));
+                }
+
+                self.fallback.append(record)
+            },
+        }
+    }
+}
+
+// what the current log4rs config was built from, so that either can change at runtime
+#[derive(Clone)]
+struct Settings {
+    log_file: PathBuf,
+    level: LogLevelFilter,
+    syslog: Option<SyslogConfig>,
+    loggers: BTreeMap<String, LoggerConfig>,
+    encoding: LogEncoding,
+    name: String,
+}
+
+struct State {
+    handle: Handle,
+    settings: Settings,
+
+    // none while logging to the event log, as the log file could not be opened
+    file: Option<LogWriter>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn open(log_file: &Path, encoding: LogEncoding) -> Result<File> {
+    if let Some(dir) = log_file.parent() {
+        fs::create_dir_all(dir)
+            .chain_err(|| format!("Unable to create log directory {:?}", dir))?;
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(log_file)
+        .chain_err(|| format!("Unable to open log file {:?}", log_file))?;
+
+    // only a file that is still empty is started with the bom, never one that is appended to
+    if encoding == LogEncoding::Utf8Bom && file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(false) {
+        file.write_all(UTF8_BOM)
+            .chain_err(|| format!("Unable to write log file {:?}", log_file))?;
+    }
+
+    Ok(file)
+}
+
+// the name is the event log source and the syslog app name
+fn build_config(log_file: &Path, level: LogLevelFilter, syslog: Option<&SyslogConfig>,
+    loggers: &BTreeMap<String, LoggerConfig>, encoding: LogEncoding, name: &str) -> Result<(Config, Option<LogWriter>)> {
+    let (appender, file): (Box<dyn Append>, _) = match open(log_file, encoding) {
+        Ok(file) => {
+            let file = Arc::new(Mutex::new(SimpleWriter(BufWriter::new(file))));
+
+            let appender = SyncingFileAppender {
+                file: file.clone(),
+                log_file: log_file.to_owned(),
+                encoder: PatternEncoder::new(PATTERN),
+                failing: AtomicBool::new(false),
+                fallback: EventLogAppender::new(name),
+            };
+
+            (Box::new(appender), Some(file))
+        },
+
+        Err(e) => {
+            let _ = eventlog::report(name, EVENTLOG_ERROR_TYPE, eventlog::EVENT_LOG,
+                &format!("{}, logging to the event log instead", e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": ")));
+
+            (Box::new(EventLogAppender::new(name)), None)
+        },
+    };
+
+    let mut builder = Config::builder()
+        .appender(Appender::builder().build("file_appender", appender));
+
+    let mut root = Root::builder().appender("file_appender");
+
+    if let Some(syslog) = syslog {
+        builder = builder.appender(Appender::builder()
+            .filter(Box::new(ThresholdFilter::new(syslog.level.filter())))
+            .build("syslog_appender", Box::new(SyslogAppender::new(syslog.clone(), name))));
+
+        root = root.appender("syslog_appender");
+    }
+
+    for (target, logger) in loggers {
+        let mut logger_builder = Logger::builder().additive(logger.additive);
+
+        if let Some(ref file) = logger.file {
+            let appender_name = format!("{}_appender", target);
+
+            // the log4rs appender opens the file again, after the bom went in
+            open(Path::new(file), encoding)
+                .chain_err(|| format!("Unable to open log file {:?} of logger {}", file, target))?;
+
+            let appender = FileAppender::builder()
+                .encoder(Box::new(PatternEncoder::new(PATTERN)))
+                .build(file)
+                .chain_err(|| format!("Unable to open log file {:?} of logger {}", file, target))?;
+
+            builder = builder.appender(Appender::builder().build(appender_name.as_str(), Box::new(appender)));
+            logger_builder = logger_builder.appender(appender_name.as_str());
+        }
+
+        let logger_level = logger.level.map(|level| level.filter()).unwrap_or(level);
+        builder = builder.logger(logger_builder.build(target.as_str(), logger_level));
+    }
+
+    let config = builder.build(root.build(level))
+        .chain_err(|| "Unable to create log configuration")?;
+
+    Ok((config, file))
+}
+
+// the name is the event log source and the syslog app name,
+// an unwritable log file falls back to the event log rather than failing the start
+pub fn init(log_file: &Path, name: &str) -> Result<()> {
+    let level = LogLevelFilter::Debug;
+    let (config, file) = build_config(log_file, level, None, &BTreeMap::new(), LogEncoding::default(), name)?;
+
+    let handle = log4rs::init_config(config)
+        .chain_err(|| "Unable to initialize from log configuration")?;
+
+    *STATE.lock().unwrap() = Some(State {
+        handle: handle,
+        settings: Settings {
+            log_file: log_file.to_owned(),
+            level: level,
+            syslog: None,
+            loggers: BTreeMap::new(),
+            encoding: LogEncoding::default(),
+            name: name.to_owned(),
+        },
+        file: file,
+    });
+
+    Ok(())
+}
+
+// for verbs run from a console, logging to the log file like the service, or to stderr with a warning if it cannot
+// be opened, e.g. when run from a usb stick or a read-only share, rather than failing the verb
+pub fn init_console(log_file: &Path) -> Result<()> {
+    let (appender, failure): (Box<dyn Append>, _) = match FileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(PATTERN)))
+        .build(log_file) {
+        Ok(appender) => (Box::new(appender), None),
+        Err(e) => {
+            let appender = ConsoleAppender::builder()
+                .encoder(Box::new(PatternEncoder::new(PATTERN)))
+                .target(Target::Stderr)
+                .build();
+
+            (Box::new(appender), Some(e))
+        },
+    };
+
+    let config = Config::builder()
+        .appender(Appender::builder().build("appender", appender))
+        .build(Root::builder().appender("appender").build(LogLevelFilter::Info))
+        .chain_err(|| "Unable to create log configuration")?;
+
+    log4rs::init_config(config)
+        .chain_err(|| "Unable to initialize from log configuration")?;
+
+    if let Some(e) = failure {
+        warn!("Unable to open log file {:?}, logging to stderr instead: {}", log_file, e);
+    }
+
+    Ok(())
+}
+
+// the change is only kept once the config built from it is in effect, so that a failed update changes nothing
+fn update<F: FnOnce(&mut Settings)>(f: F) -> Result<()> {
+    let mut state = STATE.lock().unwrap();
+
+    let state = match *state {
+        Some(ref mut state) => state,
+        None => bail!("Logging is not initialized"),
+    };
+
+    let mut settings = state.settings.clone();
+    f(&mut settings);
+
+    let (config, file) = build_config(&settings.log_file, settings.level, settings.syslog.as_ref(), &settings.loggers,
+        settings.encoding, &settings.name)?;
+    state.handle.set_config(config);
+    state.settings = settings;
+    state.file = file;
+    Ok(())
+}
+
+// switches the file logging over once the configured log path is known
+pub fn redirect(log_file: &Path) -> Result<()> {
+    update(|settings| settings.log_file = log_file.to_owned())
+}
+
+// of the log files started from then on
+pub fn set_encoding(encoding: LogEncoding) -> Result<()> {
+    update(|settings| settings.encoding = encoding)
+}
+
+// additionally sends the log to a syslog collector
+pub fn forward(syslog: &SyslogConfig) -> Result<()> {
+    update(|settings| settings.syslog = Some(syslog.clone()))
+}
+
+// the levels and log files of single targets, e.g. cmd::nginx
+pub fn set_loggers(loggers: &BTreeMap<String, LoggerConfig>) -> Result<()> {
+    update(|settings| settings.loggers = loggers.clone())
+}
+
+// where the service currently logs to, for tailing it
+pub fn log_file() -> Option<PathBuf> {
+    STATE.lock().unwrap().as_ref().map(|state| state.settings.log_file.clone())
+}
+
+pub fn level() -> Option<LogLevelFilter> {
+    STATE.lock().unwrap().as_ref().map(|state| state.settings.level)
+}
+
+// e.g. debug or trace while capturing diagnostics of an incident, returns the previous level
+pub fn set_level(level: LogLevelFilter) -> Result<LogLevelFilter> {
+    let mut previous = level;
+    update(|settings| previous = ::std::mem::replace(&mut settings.level, level))?;
+    Ok(previous)
+}
+
+// forces everything logged so far to disk, for the paths on which the process ends,
+// never blocking since it may run while a panicking thread holds the state
+pub fn sync() {
+    let file = match STATE.try_lock() {
+        Ok(state) => state.as_ref().and_then(|state| state.file.clone()),
+        Err(_) => None,
+    };
+
+    if let Some(file) = file {
+        if let Ok(mut file) = file.try_lock() {
+            let _ = file.flush();
+            let _ = (file.0).get_ref().sync_data();
+        }
+    }
+}
@@ -1,38 +1,50 @@
 #![no_main]
-#![feature(link_args)]
+// for the json! of the config schema
+#![recursion_limit = "256"]
 
+extern crate chrono;
 #[macro_use]
 extern crate error_chain;
-extern crate futures;
-extern crate futures_cpupool;
+extern crate humantime;
 
 #[macro_use]
 extern crate log;
 extern crate log4rs;
+extern crate os_pipe;
+extern crate regex;
+extern crate serde;
 
 #[macro_use]
 extern crate serde_derive;
-extern crate shared_child;
-extern crate toml;
 
 #[macro_use]
-extern crate winservice;
-
-use futures::Future;
-use futures_cpupool::CpuPool;
-use log::LogLevelFilter;
-use log4rs::append::file::FileAppender;
-use log4rs::config::{Appender, Config, Root};
-use log4rs::encode::pattern::PatternEncoder;
-use shared_child::SharedChild;
+extern crate serde_json;
+extern crate shared_child;
+extern crate toml;
+extern crate winapi;
+
+use audit::Audit;
+use config::{CommandConfig, FileConfig, LogEncoding, ServiceConfig};
+use counters::Counters;
+use disk::DiskMonitor;
+use eventlog::Lifecycle;
+use paths::ServicePaths;
+use redact::Redactor;
+use service::{ServiceControl, StopReason, CONTROL_MAINTENANCE};
+use std::backtrace::Backtrace;
+use std::cmp;
 use std::env;
-use std::fs::File;
-use std::io::{self, Read};
+use std::io;
+use std::panic;
+use std::process;
 use std::os::raw::{c_char, c_int, c_void};
-use std::process::{Command, ExitStatus};
-use std::sync::Arc;
-use std::sync::mpsc::{self, Receiver};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
 use std::thread;
+use std::time::{Duration, Instant};
+use supervisor::{Maintenance, Supervisor};
+use vars::Variables;
 
 mod errors {
     error_chain! {
@@ -43,10 +55,49 @@ mod errors {
 
 use errors::*;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct FileConfig {
-    cmds: Vec<String>,
-}
+mod child;
+mod activation;
+mod alerts;
+mod audit;
+mod authenticode;
+mod cli;
+mod command;
+mod config;
+mod control;
+mod counters;
+mod disk;
+mod eventlog;
+mod exitcode;
+mod heartbeat;
+mod history;
+mod http;
+mod leaks;
+mod lint;
+mod lock;
+mod logging;
+mod metrics;
+mod migrate;
+mod network;
+mod output;
+mod paths;
+mod profile;
+mod proxy;
+mod ratelimit;
+mod redact;
+mod rest;
+mod schedule;
+mod schema;
+mod selftest;
+mod service;
+mod session;
+mod supervisor;
+mod syslog;
+mod tail;
+mod update;
+mod vars;
+mod version;
+mod watch;
+mod win;
 
 #[allow(non_snake_case)]
 #[allow(unused_variables)]
@@ -55,192 +106,404 @@ pub extern "system" fn WinMain(
     h_instance : *const c_void, h_prev_instance : *const c_void,
     lp_cmd_line : *const c_char, n_cmd_show : c_int) -> c_int
 {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Some(exit_code) = cli::run(&args) {
+        return exit_code;
+    }
+
     // the name does not seem to matter
     // it can be renamed during sc create <servicename>
-    Service!("windows_service", service_main)
+    match service::dispatch(service_main) {
+        Ok(_) => 0,
+        Err(_) => 1,
+    }
 }
 
-fn run(_: Vec<String>, end: Receiver<()>) -> Result<()> {
-    // set up the logging by using the same file name as 
-    let exe_path = env::current_exe()
-        .chain_err(|| "Unable to get current executable path")?;
+// the effective configuration, so that a log read after an incident tells what was running and how
+fn log_summary(paths: &ServicePaths, log_file: &Path, service_config: &ServiceConfig, cmds: &[CommandConfig], redactor: &Redactor) {
+    info!("==== {} {} starting ====", paths.name, version::describe());
+    info!("Config file: {:?}", paths.config_file);
+    info!("Log file: {:?}", log_file);
 
-    let exe_dir_path = match exe_path.parent() {
-        Some(exe_dir_path) => exe_dir_path,
-        None => bail!(format!("Unable to get parent directory of executable path: {:?}", exe_path)),
-    };
+    match service_config.audit_file {
+        Some(ref audit_file) => info!("Audit log: {:?}", audit_file),
+        None => info!("Audit log: disabled"),
+    }
+
+    if let Some(ref data_dir) = service_config.data_dir {
+        info!("Data directory: {:?}", data_dir);
+    }
+
+    info!("{} command(s): {:?}", cmds.len(), cmds.iter().map(|cmd| cmd.name.as_str()).collect::<Vec<_>>());
+
+    for cmd in cmds {
+        info!(target: &logging::target(&cmd.name), "[{}] restart={:?} session={:?} cwd={:?} cmdline={:?}",
+            cmd.name, cmd.restart, cmd.session, cmd.cwd, redactor.redact(&command::display(cmd)));
+    }
+}
+
+// how long a change of health may go unnoticed in the log and the event log
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// service exit code of a panic, as opposed to 1 for errors
+const EXIT_PANIC: u32 = 2;
+
+// service exit code of a stop that ran out of its stop budget
+const EXIT_STOP_BUDGET: u32 = 3;
+
+// how long the children killed once the stop budget ran out get to be reported as exited
+const FORCED_STOP_GRACE: Duration = Duration::from_secs(10);
+
+// how often the progress of a stop is reported to the scm
+const STOP_PENDING_INTERVAL: Duration = Duration::from_secs(5);
+
+// reports the stop as pending all along, as the scm considers a service that stays silent for longer than its
+// wait hint hung
+fn wait_stop_pending(duration: Duration) {
+    let until = Instant::now() + duration;
+
+    loop {
+        let now = Instant::now();
+
+        if now >= until {
+            break;
+        }
+
+        service::report_stop_pending(STOP_PENDING_INTERVAL * 2);
+        thread::sleep(cmp::min(STOP_PENDING_INTERVAL, until - now));
+    }
+}
 
-    let exe_file_stem = match exe_path.file_stem() {
-        Some(exe_file_stem) => exe_file_stem,
-        None => bail!("Unable to get file stem of executable path: {:?}", exe_path),
+// a panic takes the whole service down with an error that the log, the event log and the scm all show,
+// rather than leaving commands unsupervised or the service silently vanishing
+fn install_panic_hook(name: String) {
+    panic::set_hook(Box::new(move |info| {
+        let message = format!("Panic: {}\n{}", info, Backtrace::force_capture());
+
+        error!("{}", message);
+        error!("Service stopping, reason: {}", StopReason::Panic.describe());
+        logging::sync();
+
+        let _ = eventlog::error(&name, eventlog::EVENT_PANIC, &message);
+        service::report_stopped(EXIT_PANIC);
+        process::exit(EXIT_PANIC as i32);
+    }));
+}
+
+// bounds the whole stop, so that a child refusing to die or a hanging hook cannot leave the service in STOP_PENDING,
+// reporting the progress from the moment the stop control arrived, the process exits on its own once the stop
+// completes in time
+fn spawn_stop_watchdog(name: String, supervisor: Arc<Supervisor>, stop_reason: StopReason, events: Lifecycle) {
+    let budget = supervisor.stop_budget();
+
+    let _ = thread::spawn(move || {
+        wait_stop_pending(budget);
+
+        let offenders = supervisor.force_stop();
+        error!("The service did not stop within stop_budget {:?}, killed what was left of {:?}", budget, offenders);
+
+        wait_stop_pending(FORCED_STOP_GRACE);
+
+        error!("Service stopping regardless after {:?}, reason: {}", budget + FORCED_STOP_GRACE, stop_reason.describe());
+        logging::sync();
+
+        events.error(eventlog::EVENT_SERVICE_STOP, &[&name, &format!("{:#010x}", stop_reason.code()),
+            &format!("{:?}, forced after stop_budget {:?}", stop_reason, budget)]);
+
+        service::report_stopped(EXIT_STOP_BUDGET);
+        process::exit(EXIT_STOP_BUDGET as i32);
+    });
+}
+
+fn run(args: Vec<String>, end: Receiver<ServiceControl>) -> Result<StopReason> {
+    let paths = ServicePaths::from_current_exe()?;
+
+    // set up the logging by using the same file name as the executable,
+    // so that config errors are logged before any configured log path is known
+    logging::init(&paths.log_file, &paths.name)?;
+    install_panic_hook(paths.name.clone());
+    win::suppress_error_dialogs();
+
+    let _lock = lock::acquire(&paths.lock_file())?;
+
+    update::remove_replaced();
+
+    // the first argument is the name the service was created with
+    let service_name = args.first().cloned().unwrap_or_else(|| paths.name.clone());
+    let profile = profile::selected(args.get(1..).unwrap_or(&[]), &service_name)?;
+
+    if let Some(ref profile) = profile {
+        info!("Using config profile {}", profile);
+    }
+
+    let variables = Variables::new(&paths).with_profile(profile);
+    let (service_config, cmds) = FileConfig::load(&paths.config_file, &variables)?.into_parts();
+
+    if service_config.log_encoding != LogEncoding::default() {
+        logging::set_encoding(service_config.log_encoding)?;
+    }
+
+    let log_file = match service_config.log_file {
+        Some(ref log_file) => {
+            info!("Switching log file to {}", log_file);
+            logging::redirect(log_file.as_ref())?;
+            PathBuf::from(log_file)
+        },
+        None => paths.log_file.clone(),
     };
 
-    let log_file_path = {
-        let mut tmp_file_path = exe_dir_path.join(exe_file_stem);
-        tmp_file_path.set_extension("log");
-        tmp_file_path
+    if let Some(ref syslog) = service_config.syslog {
+        info!("Forwarding the log to syslog collector {} over {:?}", syslog.address, syslog.protocol);
+        logging::forward(syslog)?;
+    }
+
+    if !service_config.loggers.is_empty() {
+        info!("Logging {:?} with their own levels and files", service_config.loggers.keys().collect::<Vec<_>>());
+        logging::set_loggers(&service_config.loggers)?;
+    }
+
+    let mut restricted_files = vec![log_file.clone(), paths.lock_file()];
+    restricted_files.extend(service_config.loggers.values().filter_map(|logger| logger.file.as_ref().map(PathBuf::from)));
+
+    let audit = match service_config.audit_file {
+        Some(ref audit_file) => {
+            restricted_files.push(PathBuf::from(audit_file));
+            Audit::open(audit_file.as_ref())?
+        },
+        None => Audit::disabled(),
     };
 
-    let file_appender = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{h({d(%Y-%m-%d %H:%M:%S %Z)} [{l}] - {m}{n})}")))
-        .build(log_file_path)
-        .chain_err(|| "Unable to create file appender")?;
+    if !service_config.file_acl.is_empty() {
+        for path in &restricted_files {
+            if let Err(e) = win::set_file_dacl(path, &service_config.file_acl) {
+                warn!("Unable to restrict access to {:?}: {}", path, e);
+            }
+        }
+    }
+
+    for warning in lint::lint(&cmds) {
+        warn!("Config warning: {}", warning);
+    }
 
-    let log_config = Config::builder()
-        .appender(Appender::builder().build("file_appender", Box::new(file_appender)))
-        .build(Root::builder().appender("file_appender").build(LogLevelFilter::Debug))
-        .chain_err(|| "Unable to create log configuration")?;
+    let redactor = Arc::new(Redactor::new(&service_config.redact)
+        .chain_err(|| "Unable to compile redaction patterns")?);
 
-    let _ = log4rs::init_config(log_config)
-        .chain_err(|| "Unable to initialize from log configuration")?;
+    log_summary(&paths, &log_file, &service_config, &cmds, &redactor);
 
-    // similarly derive the configuration file path from the dir path
-    let config_path = {
-        let mut tmp_file_path = exe_dir_path.join(exe_file_stem);
-        tmp_file_path.set_extension("toml");
-        tmp_file_path
+    if let Some(ref update_manifest) = service_config.update_manifest {
+        update::spawn_checker(update_manifest.clone(), service_config.update_publisher.clone(), service_config.update_interval);
+    }
+
+    let dump_dir = match service_config.dump_dir {
+        Some(ref dump_dir) => PathBuf::from(dump_dir),
+        None => log_file.parent().unwrap_or_else(|| Path::new(".")).join("dumps"),
     };
 
-    let config_str = {
-        let mut config_file = File::open(&config_path)
-            .chain_err(|| format!("Unable to open config file path at {:?}", config_path))?;
+    let counters = Counters::load(&paths.counters_file());
+
+    let events = if service_config.event_log {
+        Lifecycle::new(&paths.name)
+    } else {
+        Lifecycle::disabled()
+    };
+
+    let rest_api = service_config.rest_api.as_ref()
+        .map(|rest_api| (rest_api.port, rest_api.token.clone()));
+
+    let metrics = service_config.metrics_file.as_ref()
+        .map(|metrics_file| (PathBuf::from(metrics_file), service_config.metrics_interval));
+
+    let heartbeat = service_config.heartbeat_file.as_ref()
+        .map(|heartbeat_file| (PathBuf::from(heartbeat_file), service_config.heartbeat_interval));
 
-        let mut s = String::new();
+    let leak_check_interval = service_config.leak_check_interval;
 
-        config_file.read_to_string(&mut s)
-            .map(|_| s)
-            .chain_err(|| "Unable to read config file into string")?
+    let forwards = cmds.iter()
+        .flat_map(|cmd| cmd.forward.iter().map(move |forward| (cmd.name.clone(), forward.clone())))
+        .collect::<Vec<_>>();
+
+    let activations = cmds.iter()
+        .filter_map(|cmd| cmd.activation.clone().map(|activation| (cmd.name.clone(), activation)))
+        .collect::<Vec<_>>();
+
+    let disk_monitor = if service_config.min_free_space_mb > 0 {
+        let disk_paths = Some(log_file.clone()).into_iter()
+            .chain(service_config.audit_file.as_ref().map(PathBuf::from))
+            .chain(service_config.metrics_file.as_ref().map(PathBuf::from))
+            .chain(Some(dump_dir.clone()))
+            .collect::<Vec<_>>();
+
+        Some((DiskMonitor::new(&disk_paths, service_config.min_free_space_mb, events.clone()),
+            service_config.disk_check_interval))
+    } else {
+        None
     };
 
-    let config: FileConfig = toml::from_str(&config_str)
-        .chain_err(|| format!("Unable to parse config as required toml format: {}", config_str))?;
+    let supervisor = Arc::new(Supervisor::new(&paths.config_file, variables, service_config, cmds, redactor, audit,
+        dump_dir, counters, events.clone())?);
+    let _ = control::serve(&paths.pipe_name(), supervisor.clone())?;
+
+    if let Some((port, token)) = rest_api {
+        rest::serve(port, token, supervisor.clone())?;
+        info!("Serving the REST API on localhost port {}", port);
+    }
+
+    if let Some((metrics_file, metrics_interval)) = metrics {
+        metrics::spawn_exporter(metrics_file, metrics_interval, paths.name.clone(), supervisor.clone());
+    }
+
+    if let Some((heartbeat_file, heartbeat_interval)) = heartbeat {
+        heartbeat::spawn_writer(heartbeat_file, heartbeat_interval, supervisor.clone());
+    }
+
+    leaks::spawn_auditor(leak_check_interval, supervisor.clone());
 
-    let (txs, rxs): (Vec<_>, Vec<_>) = (0..config.cmds.len())
-        .map(|_| mpsc::channel::<()>())
-        .unzip();
+    for (name, activation) in activations {
+        activation::serve(name, activation, supervisor.clone())?;
+    }
+
+    for (name, forward) in forwards {
+        proxy::forward(&name, &forward.listen, &forward.target)?;
+    }
 
     // maintain the loop to stop service in a separate thread
+    let supervisor_end = supervisor.clone();
+
+    // unless stopped by the scm, the service ends once every command has ended for good
+    let stop_reason = Arc::new(Mutex::new(StopReason::Completed));
+    let stop_reason_end = stop_reason.clone();
+    let service_name = paths.name.clone();
+    let events_end = events.clone();
+
+    // blocks until the scm sends a control, the sender lives for as long as the process does
     let _ = thread::spawn(move || {
-        loop {
-            if let Ok(_) = end.try_recv() {
-                for (idx, tx) in txs.into_iter().enumerate() {
-                    match tx.send(()) {
-                        Ok(_) => debug!("Sent into channel #{}", idx),
-                        Err(e) => error!("Error sending into channel #{}: {}", idx, e),
-                    }
-                }
-
-                debug!("Received service end message");
-                break;
+        for control in end.iter() {
+            match control {
+                ServiceControl::Stop => {
+                    *stop_reason_end.lock().unwrap() = StopReason::Requested;
+                    supervisor_end.record_control("stop", audit::SCM);
+                    info!("Stopping service on request");
+                    spawn_stop_watchdog(service_name, supervisor_end.clone(), StopReason::Requested, events_end);
+                    supervisor_end.stop_all();
+                    break;
+                },
+
+                ServiceControl::Shutdown => {
+                    *stop_reason_end.lock().unwrap() = StopReason::Shutdown;
+                    supervisor_end.record_control("shutdown", audit::SCM);
+                    info!("Stopping service for system shutdown");
+                    spawn_stop_watchdog(service_name, supervisor_end.clone(), StopReason::Shutdown, events_end);
+                    supervisor_end.shutdown();
+                    break;
+                },
+
+                // sent by `sc paramchange` and other standard tooling
+                ServiceControl::ParamChange => {
+                    supervisor_end.record_control("paramchange", audit::SCM);
+                    let supervisor_reload = supervisor_end.clone();
+
+                    // a reload waits for replaced commands to stop, which must not hold up a stop
+                    let _ = thread::spawn(move || {
+                        if let Err(e) = supervisor_reload.reload(audit::SCM) {
+                            error!("Unable to reload config on paramchange: {}", e);
+                        }
+                    });
+                },
+
+                ServiceControl::Custom(CONTROL_MAINTENANCE) => {
+                    supervisor_end.record_control("maintenance", audit::SCM);
+                    supervisor_end.maintenance(Maintenance::Toggle);
+                },
+
+                // starts the manual command the code is configured for
+                ServiceControl::Custom(code) => match supervisor_end.command_for_control_code(code) {
+                    Some(name) => {
+                        supervisor_end.record_control(&format!("start {}", name), audit::SCM);
+
+                        if let Err(e) = supervisor_end.start_command(&name, audit::SCM) {
+                            warn!(target: &logging::target(&name), "Unable to start [{}] on control code {}: {}",
+                                name, code, e);
+                        }
+                    },
+                    None => warn!("Unknown control code: {}", code),
+                },
             }
         }
     });
-    
+
+    let supervisor_health = supervisor.clone();
+    service::set_health_check(move || supervisor_health.check_health());
+
+    let supervisor_check = supervisor.clone();
+
+    let _ = thread::spawn(move || {
+        while !supervisor_check.is_stopping() {
+            thread::sleep(HEALTH_CHECK_INTERVAL);
+            supervisor_check.check_health();
+        }
+    });
+
+    // a preflight check, so that paused commands do not even start on a full disk
+    if let Some((mut disk_monitor, disk_check_interval)) = disk_monitor {
+        supervisor.set_low_disk(disk_monitor.check());
+        disk::spawn_monitor(disk_monitor, disk_check_interval, supervisor.clone());
+    }
+
     // starts launching of processes
+    supervisor.start();
+    events.info(eventlog::EVENT_SERVICE_START, &[&paths.name, &version::describe()]);
+
+    supervisor.wait();
+
+    let stop_reason = *stop_reason.lock().unwrap();
+    events.info(eventlog::EVENT_SERVICE_STOP, &[&paths.name, &format!("{:#010x}", stop_reason.code()),
+        &format!("{:?}", stop_reason)]);
+
+    Ok(stop_reason)
+}
 
-    // set up the CPU pool
-    // needs * 2 because of each subprocess requires another force stopper future,    
-    let required_pool_count = config.cmds.len() * 2;
-    let pool = CpuPool::new(required_pool_count);
-
-    let fut_threads: Vec<_> = rxs.into_iter().enumerate()
-        .zip(config.cmds.iter().cloned())
-        .map(|((idx, rx), cmd)| {
-            // create the command and shared between both sides of futures
-            let mut process = if cfg!(target_os = "windows") {
-                let mut process = Command::new("cmd");
-                process.args(&["/C", &cmd]);
-                process
-            } else {
-                let mut process = Command::new("sh");
-                process.args(&["-c", &cmd]);
-                process
-            };
-
-            let shared_child = SharedChild::spawn(&mut process).unwrap();
-                // .chain_err(|| "Unable to spawn shared child")?;
-
-            let child_arc = Arc::new(shared_child);
-            let child_arc_rx = child_arc.clone();
-
-            // rx receiving for forced stop
-            let rx_fut = pool.spawn_fn(move || -> Result<Option<ExitStatus>> {
-                match rx.recv() {
-                    Ok(_) => debug!("Received from channel #{}", idx),
-                    Err(e) => error!("Error receiving from channel #{}: {}", idx, e),
-                }
-
-                // terminate the process
-                if let Ok(None) = child_arc_rx.try_wait() {
-                    debug!("Killing process #{}", idx);
-
-                    let kill_res = child_arc_rx.kill();
-
-                    match kill_res {
-                        Ok(_) => info!("Killed process #{}", idx),
-                        Err(e) => error!("Error killing process #{}: {}", idx, e),
-                    } 
-                }
-
-                Ok(None)
-            });
-
-            // subprocess launch
-            let child_arc_process = child_arc.clone();
-
-            let process_fut = pool.spawn_fn(move || {
-                let cmd_str = cmd.clone();
-
-                let process_run = move || {
-                    // process thread body
-                    let exit_status = child_arc_process
-                        .wait()
-                        .chain_err(|| format!("Unable to join shell process"))?;
-
-                    Ok(Some(exit_status))
-                };
-
-                let process_res = process_run();
-
-                match process_res {
-                    Ok(ref exit_status) => info!("Shell terminated [{}], exit code: {:?}", cmd_str, exit_status),
-                    Err(ref e) => error!("Shell error [{}]: {}", cmd_str, e),
-                }
-
-                process_res
-            });
-
-            thread::spawn(move || {
-                let win_res = rx_fut.select(process_fut)
-                    .map(|(win_fut, _)| win_fut)
-                    .wait();
-
-                match win_res {
-                    Ok(ref exit_status) => info!("Process #{} exit status: {:?}", idx, exit_status),
-                    Err((ref e, _)) => error!("Process #{} error: {}", idx, e),
-                }
-
-                win_res
-            })
-        })
-        // must collect first in order to force all the futures to be executed
-        .collect();
-    
-    let combined_res: std::result::Result<Vec<_>, _> = fut_threads.into_iter()
-        .map(|fut_thread| fut_thread.join())
-        .collect();
-
-    if let Err(e) = combined_res {
-        error!("Error combining threads: {:?}", e);
+// a failure before any command started, typically of the config, may leave no log file behind,
+// so the event log gets the whole error along with where the service looked for its files
+fn report_startup_failure(args: &[String], e: &Error) {
+    let paths = ServicePaths::from_current_exe();
+    let source = match paths {
+        Ok(ref paths) => paths.name.clone(),
+        Err(_) => args.first().cloned().unwrap_or_else(|| "windows_service".to_owned()),
+    };
+
+    let mut message = format!("The service failed to start: {}", e);
+
+    for e in e.iter().skip(1) {
+        message.push_str(&format!("\n- Caused by: {}", e));
+    }
+
+    match paths {
+        Ok(ref paths) => {
+            let found = |path: &Path| if path.exists() { "" } else { " (not found)" };
+
+            message.push_str(&format!("\n\nConfig file: {}{}", paths.config_file.display(), found(&paths.config_file)));
+            message.push_str(&format!("\nLog file: {}{}", paths.log_file.display(), found(&paths.log_file)));
+        },
+        Err(ref e) => message.push_str(&format!("\n\nUnable to locate the service files: {}", e)),
     }
 
-    Ok(())
+    if let Ok(dir) = env::current_dir() {
+        message.push_str(&format!("\nWorking directory: {}", dir.display()));
+    }
+
+    message.push_str(&format!("\nArguments: {:?}", args));
+
+    if let Err(e) = eventlog::error(&source, eventlog::EVENT_STARTUP_FAILURE, &message) {
+        error!("Unable to write the startup failure to the event log: {}", e);
+    }
 }
 
 #[allow(unused_variables)]
-fn service_main(args: Vec<String>, end: Receiver<()>) -> u32 {
-    match run(args, end) {
-        Ok(_) => {
+fn service_main(args: Vec<String>, end: Receiver<ServiceControl>) -> u32 {
+    let exit_code = match run(args.clone(), end) {
+        Ok(stop_reason) => {
+            info!("Service stopping, reason: {}", stop_reason.describe());
             info!("Program completed!");
             0
         },
@@ -253,7 +516,12 @@ fn service_main(args: Vec<String>, end: Receiver<()>) -> u32 {
                 error!("- Caused by: {}", e);
             }
 
+            error!("Service stopping, reason: {}", StopReason::Failure.describe());
+            report_startup_failure(&args, e);
             1
         },
-    }
+    };
+
+    logging::sync();
+    exit_code
 }
\ No newline at end of file
@@ -0,0 +1,106 @@
+use chrono::{Local, Offset};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use supervisor::{CommandStatus, Supervisor};
+use win;
+
+const COUNTERS: &[&str] = &["Running", "Uptime %", "Restarts", "CPU Time (s)", "Working Set (bytes)", "Handle Count"];
+
+// pdh marks values that could not be collected with a blank
+const MISSING: &str = " ";
+
+fn quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+fn line<I: IntoIterator<Item = String>>(fields: I) -> String {
+    fields.into_iter().map(|field| quote(&field)).collect::<Vec<_>>().join(",")
+}
+
+// columns named like perfmon counter paths, \\host\object(instance)\counter, with the time zone bias in minutes
+fn header(service: &str, commands: &[CommandStatus]) -> String {
+    let host = env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_owned());
+    let host = &host;
+    let bias = -Local::now().offset().fix().local_minus_utc() / 60;
+
+    let columns = commands.iter().flat_map(|status| {
+        COUNTERS.iter().map(move |counter| format!(r"\\{}\{}({})\{}", host, service, status.name, counter))
+    });
+
+    line(Some(format!("(PDH-CSV 4.0) (Local Time)({})", bias)).into_iter().chain(columns))
+}
+
+fn row(commands: &[CommandStatus]) -> String {
+    let values = commands.iter().flat_map(|status| {
+        let usage = status.pid.and_then(|pid| win::process_usage(pid).ok());
+
+        let usage_values = match usage {
+            Some(usage) => vec![
+                format!("{:.3}", usage.cpu_time.as_secs_f64()),
+                usage.working_set.to_string(),
+                usage.handles.to_string(),
+            ],
+            None => vec![MISSING.to_owned(); 3],
+        };
+
+        vec![
+            (status.pid.is_some() as u8).to_string(),
+            format!("{:.3}", status.uptime_percent),
+            status.restarts.to_string(),
+        ].into_iter().chain(usage_values)
+    });
+
+    line(Some(Local::now().format("%m/%d/%Y %H:%M:%S%.3f").to_string()).into_iter().chain(values))
+}
+
+fn first_line(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    BufReader::new(file).lines().next().and_then(|line| line.ok())
+}
+
+fn append(path: &Path, header: &str, row: &str) -> io::Result<()> {
+    // perfmon expects a single header, so a changed set of commands starts a new file,
+    // keeping the old one with the time of the change
+    if let Some(existing) = first_line(path) {
+        if existing != header {
+            let extension = path.extension().map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "csv".to_owned());
+
+            let kept = path.with_extension(format!("{}.{}", Local::now().format("%Y%m%d%H%M%S"), extension));
+            fs::rename(path, &kept)?;
+            info!("Metrics columns changed, the previous metrics were moved to {:?}", kept);
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", header)?;
+    }
+
+    writeln!(file, "{}", row)
+}
+
+// appends a row of availability and resource usage of every command each interval, for as long as the service runs
+pub fn spawn_exporter(path: PathBuf, interval: Duration, service: String, supervisor: Arc<Supervisor>) {
+    let _ = thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+
+            let commands = supervisor.status().commands;
+
+            if let Err(e) = append(&path, &header(&service, &commands), &row(&commands)) {
+                warn!("Unable to write metrics to {:?}: {}", path, e);
+            }
+        }
+    });
+}
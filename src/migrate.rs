@@ -0,0 +1,246 @@
+use errors::*;
+use std::mem;
+use toml;
+
+// a legacy cmds entry, as its literal source text with the comments around it
+struct LegacyCmd {
+    comments: Vec<String>,
+    literal: String,
+    trailing_comment: Option<String>,
+}
+
+// rewrites the legacy `cmds = [...]` list into [[commands]] tables, leaving the rest of the text as is,
+// returns None if there is nothing to migrate
+pub fn migrate(text: &str) -> Result<Option<String>> {
+    let parsed: toml::Value = toml::from_str(text)
+        .chain_err(|| "Unable to parse config as toml")?;
+
+    let cmd_count = match parsed.get("cmds").and_then(|cmds| cmds.as_array()) {
+        Some(cmds) if !cmds.is_empty() => cmds.len(),
+        _ => return Ok(None),
+    };
+
+    // the migrated commands keep their implicit names so that a reload sees them as unchanged
+    let taken = parsed.get("commands")
+        .and_then(|commands| commands.as_array())
+        .map(|commands| commands.iter()
+            .filter_map(|command| command.get("name").and_then(|name| name.as_str()))
+            .map(|name| name.to_owned())
+            .collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    if let Some(name) = (0..cmd_count).map(|idx| format!("cmd{}", idx)).find(|name| taken.contains(name)) {
+        bail!("Unable to migrate, the command name [{}] is already taken", name);
+    }
+
+    let (start, end) = match find_cmds(text) {
+        Some(span) => span,
+        None => bail!("Unable to locate the cmds list in the config text"),
+    };
+
+    let (cmds, trailing_comments) = split_elements(&text[start..end])?;
+
+    if cmds.len() != cmd_count {
+        bail!("Unable to migrate, found {} cmds entries in the text but {} when parsed", cmds.len(), cmd_count);
+    }
+
+    let mut tables = cmds.iter().enumerate()
+        .map(|(idx, cmd)| {
+            let mut table = cmd.comments.join("\n");
+
+            if !table.is_empty() {
+                table.push('\n');
+            }
+
+            table + &format!("[[commands]]\nname = \"cmd{}\"\ncmd = {}{}\n", idx, cmd.literal,
+                cmd.trailing_comment.as_ref().map(|comment| format!(" {}", comment)).unwrap_or_default())
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    for comment in trailing_comments {
+        tables.push_str(&comment);
+        tables.push('\n');
+    }
+
+    // drop the list along with the rest of its last line
+    let end = text[end..].find('\n').map(|pos| end + pos + 1).unwrap_or_else(|| text.len());
+    let rest = format!("{}{}", &text[..start], &text[end..]);
+
+    // legacy commands used to be launched first, so they go before the existing named commands
+    let migrated = match table_header(&rest, "[[commands]]") {
+        Some(pos) => format!("{}{}\n{}", &rest[..pos], tables, &rest[pos..]),
+        None => {
+            let mut migrated = rest.trim_end().to_owned();
+
+            if !migrated.is_empty() {
+                migrated.push_str("\n\n");
+            }
+
+            migrated + &tables
+        },
+    };
+
+    toml::from_str::<toml::Value>(&migrated)
+        .chain_err(|| "Migrated config is not valid toml, leaving the config untouched")?;
+
+    Ok(Some(migrated))
+}
+
+fn trim_indent(line: &str) -> &str {
+    line.trim_start_matches(|c| c == ' ' || c == '\t')
+}
+
+fn line_starts(text: &str) -> Vec<usize> {
+    Some(0).into_iter()
+        .chain(text.match_indices('\n').map(|(pos, _)| pos + 1))
+        .filter(|&pos| pos < text.len())
+        .collect()
+}
+
+// start of the first line that is the given table header
+fn table_header(text: &str, header: &str) -> Option<usize> {
+    line_starts(text).into_iter().find(|&pos| trim_indent(&text[pos..]).starts_with(header))
+}
+
+// byte span from the start of the `cmds = [` line to just past the closing bracket,
+// only within the root table, i.e. before the first table header
+fn find_cmds(text: &str) -> Option<(usize, usize)> {
+    for pos in line_starts(text) {
+        let line = trim_indent(&text[pos..]);
+
+        if line.starts_with('[') {
+            return None;
+        }
+
+        if !line.starts_with("cmds") || !line["cmds".len()..].trim_start().starts_with('=') {
+            continue;
+        }
+
+        let open = pos + text[pos..].find('[')?;
+        return scan_array(text, open).map(|close| (pos, close + 1));
+    }
+
+    None
+}
+
+// position of the bracket closing the array opened at `open`, skipping strings and comments
+fn scan_array(text: &str, open: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut i = open;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            b'#' => {
+                i = text[i..].find('\n').map(|pos| i + pos).unwrap_or_else(|| bytes.len());
+                continue;
+            },
+            b'"' | b'\'' => {
+                i = string_end(text, i)?;
+                continue;
+            },
+            _ => (),
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+// position just past the string literal starting at `start`
+fn string_end(text: &str, start: usize) -> Option<usize> {
+    let quote = text.as_bytes()[start];
+    let delimiter = if quote == b'"' { "\"" } else { "'" };
+    let triple = delimiter.repeat(3);
+    let multiline = text[start..].starts_with(&triple);
+    let (body, delimiter) = if multiline { (start + 3, triple.as_str()) } else { (start + 1, delimiter) };
+    let mut from = body;
+
+    loop {
+        let pos = from + text[from..].find(delimiter)?;
+
+        // literal strings have no escapes
+        if quote == b'"' && is_escaped(text, body, pos) {
+            from = pos + 1;
+            continue;
+        }
+
+        let end = pos + delimiter.len();
+
+        // up to two more quotes may end the content of a multiline string right before the delimiter
+        return Some(if multiline {
+            end + text[end..].bytes().take_while(|&b| b == quote).take(2).count()
+        } else {
+            end
+        });
+    }
+}
+
+// whether the character at `pos` is preceded by an odd number of backslashes
+fn is_escaped(text: &str, from: usize, pos: usize) -> bool {
+    text[from..pos].bytes().rev().take_while(|&b| b == b'\\').count() % 2 == 1
+}
+
+// the string literals of the cmds array text with their comments,
+// along with the comment lines following the last one
+fn split_elements(array: &str) -> Result<(Vec<LegacyCmd>, Vec<String>)> {
+    let open = match array.find('[') {
+        Some(open) => open + 1,
+        None => bail!("Malformed cmds list"),
+    };
+
+    let bytes = array.as_bytes();
+    let mut cmds = Vec::new();
+    let mut comments = Vec::new();
+    let mut same_line = false;
+    let mut i = open;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => {
+                let end = array[i..].find('\n').map(|pos| i + pos).unwrap_or_else(|| bytes.len());
+                let comment = array[i..end].trim_end().to_owned();
+
+                // a comment on the line of an entry stays on the line of its migrated cmd
+                match cmds.last_mut() {
+                    Some(&mut LegacyCmd { ref mut trailing_comment, .. }) if same_line => *trailing_comment = Some(comment),
+                    _ => comments.push(comment),
+                }
+
+                i = end;
+            },
+            b'\n' => {
+                same_line = false;
+                i += 1;
+            },
+            b'"' | b'\'' => {
+                let end = match string_end(array, i) {
+                    Some(end) => end,
+                    None => bail!("Unterminated string in the cmds list"),
+                };
+
+                cmds.push(LegacyCmd {
+                    comments: mem::replace(&mut comments, Vec::new()),
+                    literal: array[i..end].to_owned(),
+                    trailing_comment: None,
+                });
+
+                same_line = true;
+                i = end;
+            },
+            _ => i += 1,
+        }
+    }
+
+    Ok((cmds, comments))
+}
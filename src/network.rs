@@ -0,0 +1,19 @@
+use std::net::{ToSocketAddrs, UdpSocket};
+
+// any public address does, connecting a udp socket sends nothing and only needs a route
+const ROUTE_PROBE_ADDR: &str = "8.8.8.8:53";
+
+// without a host, the network counts as up once there is a route out of the machine,
+// otherwise once the host resolves, which also requires dns to be reachable
+pub fn is_up(check_host: Option<&str>) -> bool {
+    match check_host {
+        Some(host) => {
+            let addr = if host.contains(':') { host.to_owned() } else { format!("{}:0", host) };
+            addr.to_socket_addrs().map(|mut addrs| addrs.next().is_some()).unwrap_or(false)
+        },
+
+        None => UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.connect(ROUTE_PROBE_ADDR))
+            .is_ok(),
+    }
+}
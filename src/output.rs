@@ -0,0 +1,387 @@
+use config::CommandConfig;
+use errors::*;
+use logging;
+use ratelimit::LineLimit;
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp;
+use std::io::{self, BufRead, BufReader, Read};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use win;
+
+// the log target of the output lines of a command, below the target of the supervisor messages about it,
+// e.g. cmd::nginx::output
+pub fn target(name: &str) -> String {
+    format!("{}::output", logging::target(name))
+}
+
+pub fn is_output(target: &str) -> bool {
+    target.starts_with("cmd::") && target.ends_with("::output")
+}
+
+pub struct OutputFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    success: Option<Regex>,
+    failure: Option<Regex>,
+    startup: Option<Regex>,
+
+    // shared by stdout and stderr, so that a spamming child cannot drown out the others
+    limit: LineLimit,
+
+    max_line_bytes: usize,
+
+    // of output that is not utf-8
+    codepage: u32,
+}
+
+// what the output of the current run showed so far
+pub struct OutputSeen {
+    pub last_line: Instant,
+    pub success: bool,
+    pub failure: bool,
+
+    // a line matched startup_output
+    pub started: bool,
+}
+
+impl OutputSeen {
+    pub fn new() -> OutputSeen {
+        OutputSeen {
+            last_line: Instant::now(),
+            success: false,
+            failure: false,
+            started: false,
+        }
+    }
+}
+
+fn compile_opt(pattern: &Option<String>) -> Result<Option<Regex>> {
+    match *pattern {
+        Some(ref pattern) => Regex::new(pattern)
+            .map(Some)
+            .chain_err(|| format!("Invalid output regex: {}", pattern)),
+        None => Ok(None),
+    }
+}
+
+fn compile_all(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns.iter()
+        .map(|pattern| Regex::new(pattern)
+            .chain_err(|| format!("Invalid output filter regex: {}", pattern)))
+        .collect()
+}
+
+impl OutputFilter {
+    pub fn new(cmd: &CommandConfig) -> Result<OutputFilter> {
+        Ok(OutputFilter {
+            include: compile_all(&cmd.include)
+                .chain_err(|| format!("Unable to compile include filters of [{}]", cmd.name))?,
+            exclude: compile_all(&cmd.exclude)
+                .chain_err(|| format!("Unable to compile exclude filters of [{}]", cmd.name))?,
+            success: compile_opt(&cmd.success_output)
+                .chain_err(|| format!("Unable to compile success_output of [{}]", cmd.name))?,
+            failure: compile_opt(&cmd.failure_output)
+                .chain_err(|| format!("Unable to compile failure_output of [{}]", cmd.name))?,
+            startup: compile_opt(&cmd.startup_output)
+                .chain_err(|| format!("Unable to compile startup_output of [{}]", cmd.name))?,
+            limit: LineLimit::new(cmd.max_log_lines_per_second),
+            max_line_bytes: cmd.max_line_bytes,
+            codepage: cmd.console_codepage().map(u32::from).unwrap_or_else(win::oem_codepage),
+        })
+    }
+
+    // every line counts, whether it is logged or not
+    fn note(&self, line: &str, seen: &mut OutputSeen) {
+        seen.last_line = Instant::now();
+
+        if !seen.success && self.success.as_ref().map(|re| re.is_match(line)).unwrap_or(false) {
+            seen.success = true;
+        }
+
+        if !seen.failure && self.failure.as_ref().map(|re| re.is_match(line)).unwrap_or(false) {
+            seen.failure = true;
+        }
+
+        if !seen.started && self.startup.as_ref().map(|re| re.is_match(line)).unwrap_or(false) {
+            seen.started = true;
+        }
+    }
+
+    // none if the output agrees with a successful exit code, otherwise why it does not
+    pub fn contradiction(&self, seen: &OutputSeen) -> Option<&'static str> {
+        if seen.failure {
+            Some("a line matched failure_output")
+        } else if self.success.is_some() && !seen.success {
+            Some("no line matched success_output")
+        } else {
+            None
+        }
+    }
+
+    // output in utf-8 is taken as it is, anything else as in the console codepage of the child, which is the oem
+    // codepage of the system locale unless the command sets one, so that non-english output is not garbled
+    pub fn decode<'a>(&self, buf: &'a [u8]) -> Cow<'a, str> {
+        match str::from_utf8(buf) {
+            Ok(line) => Cow::Borrowed(line),
+            Err(_) => win::decode(buf, self.codepage)
+                .map(Cow::Owned)
+                .unwrap_or_else(|_| String::from_utf8_lossy(buf)),
+        }
+    }
+
+    pub fn has_startup_probe(&self) -> bool {
+        self.startup.is_some()
+    }
+
+    // whether the output has to be read to the end before the run can be judged
+    pub fn judges_success(&self) -> bool {
+        self.success.is_some() || self.failure.is_some()
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|re| re.is_match(line));
+        included && !self.exclude.iter().any(|re| re.is_match(line))
+    }
+}
+
+fn log_suppressed(name: &str, suppressed: u64) {
+    if suppressed > 0 {
+        warn!(target: &logging::target(name), "[{}] suppressed {} lines over max_log_lines_per_second",
+            name, suppressed);
+    }
+}
+
+// lines cut off at max_line_bytes, reported once the output closes like the suppressed ones
+#[derive(Default)]
+struct Truncated {
+    lines: u64,
+    bytes: u64,
+}
+
+impl Truncated {
+    fn add(&mut self, name: &str, dropped: usize) {
+        if dropped == 0 {
+            return;
+        }
+
+        if self.lines == 0 {
+            warn!(target: &logging::target(name), "[{}] cut off an output line longer than max_line_bytes", name);
+        }
+
+        self.lines += 1;
+        self.bytes += dropped as u64;
+    }
+
+    fn log(&self, name: &str) {
+        if self.lines > 0 {
+            warn!(target: &logging::target(name),
+                "[{}] truncated {} lines longer than max_line_bytes, dropping {} bytes", name, self.lines, self.bytes);
+        }
+    }
+}
+
+// like read_until a line break, but keeps at most max bytes of the line and drops the rest of it, returning
+// the bytes read, 0 at the end, and the bytes dropped, a cut off line still ends with its line break
+fn read_line<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>, max: usize) -> io::Result<(usize, usize)> {
+    let mut read = 0;
+    let mut dropped = 0;
+
+    loop {
+        let (ended, used) = {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let (ended, used) = match available.iter().position(|&b| b == b'\n') {
+                Some(end) => (true, end + 1),
+                None => (false, available.len()),
+            };
+
+            let kept = cmp::min(used, max.saturating_sub(buf.len()));
+            buf.extend_from_slice(&available[..kept]);
+            dropped += used - kept;
+
+            if ended && kept < used {
+                buf.push(b'\n');
+            }
+
+            (ended, used)
+        };
+
+        reader.consume(used);
+        read += used;
+
+        if ended || used == 0 {
+            return Ok((read, dropped));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// reads the child output line by line until the pipe closes,
+// logging only the lines that pass through the filter, but noting every line,
+// a child writing faster than the lines are logged blocks on the full pipe rather than being buffered
+pub fn forward<R>(reader: R, name: String, stream: Stream, filter: Arc<OutputFilter>, seen: Arc<Mutex<OutputSeen>>)
+    -> JoinHandle<()>
+    where R: Read + Send + 'static
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        let output_target = target(&name);
+        let mut truncated = Truncated::default();
+
+        loop {
+            buf.clear();
+
+            match read_line(&mut reader, &mut buf, filter.max_line_bytes) {
+                Ok((0, _)) => break,
+                Ok((_, dropped)) => truncated.add(&name, dropped),
+                Err(e) => {
+                    error!(target: &logging::target(&name), "Error reading {:?} of [{}]: {}", stream, name, e);
+                    break;
+                },
+            }
+
+            let line = filter.decode(&buf);
+            let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+
+            filter.note(line, &mut seen.lock().unwrap());
+
+            if !filter.is_match(line) {
+                continue;
+            }
+
+            let (allowed, suppressed) = filter.limit.try_take();
+            log_suppressed(&name, suppressed);
+
+            if !allowed {
+                continue;
+            }
+
+            match stream {
+                Stream::Stdout => info!(target: &output_target, "[{}] {}", name, line),
+                Stream::Stderr => warn!(target: &output_target, "[{}] {}", name, line),
+            }
+        }
+
+        log_suppressed(&name, filter.limit.take_suppressed());
+        truncated.log(&name);
+        debug!(target: &logging::target(&name), "Closed {:?} of [{}]", stream, name);
+    })
+}
+
+// as much as a pipe hands over at once by default
+const RELAY_CHUNK_BYTES: usize = 8 * 1024;
+
+// hands the child output on to the sink unchanged, in chunks as it arrives, e.g. to the stdin of another child,
+// a sink that blocks holds up the child on its full pipe, max_line_bytes only bounds the lines noted on the way
+pub fn relay<R, F>(mut reader: R, name: String, filter: Arc<OutputFilter>, seen: Arc<Mutex<OutputSeen>>, mut sink: F)
+    -> JoinHandle<()>
+    where R: Read + Send + 'static, F: FnMut(&[u8]) + Send + 'static
+{
+    thread::spawn(move || {
+        let mut chunk = [0u8; RELAY_CHUNK_BYTES];
+        let mut line = Vec::new();
+
+        let note = |line: &[u8]| filter.note(filter.decode(line).trim_end_matches(|c| c == '\r' || c == '\n'),
+            &mut seen.lock().unwrap());
+
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!(target: &logging::target(&name), "Error reading piped output of [{}]: {}", name, e);
+                    break;
+                },
+            };
+
+            for part in chunk[..read].split_inclusive(|&b| b == b'\n') {
+                let kept = cmp::min(part.len(), filter.max_line_bytes.saturating_sub(line.len()));
+                line.extend_from_slice(&part[..kept]);
+
+                if part.ends_with(b"\n") {
+                    note(&line);
+                    line.clear();
+                }
+            }
+
+            sink(&chunk[..read]);
+        }
+
+        if !line.is_empty() {
+            note(&line);
+        }
+
+        debug!(target: &logging::target(&name), "Closed piped output of [{}]", name);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(input: &[u8], capacity: usize, max: usize) -> Vec<(Vec<u8>, usize, usize)> {
+        let mut reader = BufReader::with_capacity(capacity, input);
+        let mut result = Vec::new();
+
+        loop {
+            let mut buf = Vec::new();
+
+            match read_line(&mut reader, &mut buf, max).unwrap() {
+                (0, _) => return result,
+                (read, dropped) => result.push((buf, read, dropped)),
+            }
+        }
+    }
+
+    #[test]
+    fn passes_short_lines_through() {
+        assert_eq!(lines(b"one\r\ntwo\n", 64, 16), vec![
+            (b"one\r\n".to_vec(), 5, 0),
+            (b"two\n".to_vec(), 4, 0),
+        ]);
+    }
+
+    #[test]
+    fn keeps_a_last_line_without_line_break() {
+        assert_eq!(lines(b"one\nlast", 64, 16), vec![
+            (b"one\n".to_vec(), 4, 0),
+            (b"last".to_vec(), 4, 0),
+        ]);
+    }
+
+    #[test]
+    fn cuts_long_lines_keeping_the_line_break() {
+        assert_eq!(lines(b"0123456789\nok\n", 64, 4), vec![
+            (b"0123\n".to_vec(), 11, 7),
+            (b"ok\n".to_vec(), 3, 0),
+        ]);
+    }
+
+    #[test]
+    fn reads_lines_across_buffer_fills() {
+        assert_eq!(lines(b"0123456789\n0123456789abcdef\n", 4, 12), vec![
+            (b"0123456789\n".to_vec(), 11, 0),
+            (b"0123456789ab\n".to_vec(), 17, 5),
+        ]);
+    }
+
+    #[test]
+    fn reads_nothing_at_the_end() {
+        assert!(lines(b"", 64, 16).is_empty());
+    }
+}
@@ -0,0 +1,66 @@
+use errors::*;
+use std::env;
+use std::path::{Path, PathBuf};
+
+// the log, config and control pipe are all named after the executable,
+// so that multiple copies of the executable can be installed as different services
+#[derive(Debug, Clone)]
+pub struct ServicePaths {
+    pub exe_dir: PathBuf,
+    pub name: String,
+    pub log_file: PathBuf,
+    pub config_file: PathBuf,
+}
+
+impl ServicePaths {
+    pub fn from_current_exe() -> Result<ServicePaths> {
+        let exe_path = env::current_exe()
+            .chain_err(|| "Unable to get current executable path")?;
+
+        let exe_dir_path = match exe_path.parent() {
+            Some(exe_dir_path) => exe_dir_path.to_owned(),
+            None => bail!(format!("Unable to get parent directory of executable path: {:?}", exe_path)),
+        };
+
+        let exe_file_stem = match exe_path.file_stem() {
+            Some(exe_file_stem) => exe_file_stem.to_owned(),
+            None => bail!("Unable to get file stem of executable path: {:?}", exe_path),
+        };
+
+        let log_file_path = {
+            let mut tmp_file_path = exe_dir_path.join(&exe_file_stem);
+            tmp_file_path.set_extension("log");
+            tmp_file_path
+        };
+
+        // similarly derive the configuration file path from the dir path
+        let config_path = {
+            let mut tmp_file_path = exe_dir_path.join(&exe_file_stem);
+            tmp_file_path.set_extension("toml");
+            tmp_file_path
+        };
+
+        Ok(ServicePaths {
+            exe_dir: exe_dir_path,
+            name: exe_file_stem.to_string_lossy().into_owned(),
+            log_file: log_file_path,
+            config_file: config_path,
+        })
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        self.config_file.parent().unwrap_or(&self.exe_dir)
+    }
+
+    pub fn lock_file(&self) -> PathBuf {
+        self.config_file.with_extension("lock")
+    }
+
+    pub fn counters_file(&self) -> PathBuf {
+        self.config_file.with_extension("counters.json")
+    }
+
+    pub fn pipe_name(&self) -> String {
+        format!(r"\\.\pipe\{}", self.name)
+    }
+}
@@ -0,0 +1,95 @@
+use errors::*;
+use toml::Value;
+use toml::value::Table;
+use win;
+
+const PROFILE_ARG: &str = "--profile";
+
+// the profile given as `sc start <service> --profile <name>`, otherwise the Profile value
+// under the Parameters key of the service, so that the same files can be promoted across environments
+pub fn selected(args: &[String], service_name: &str) -> Result<Option<String>> {
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == PROFILE_ARG {
+            return match args.next() {
+                Some(name) => Ok(Some(name.clone())),
+                None => bail!("Missing the profile name after {}", PROFILE_ARG),
+            };
+        }
+
+        if let Some(name) = arg.strip_prefix("--profile=") {
+            return Ok(Some(name.to_owned()));
+        }
+    }
+
+    let key = format!(r"SYSTEM\CurrentControlSet\Services\{}\Parameters", service_name);
+
+    win::registry_string(&key, "Profile")
+        .map(|profile| profile.filter(|profile| !profile.is_empty()))
+        .chain_err(|| format!("Unable to read the config profile from HKLM\\{}", key))
+}
+
+// commands are merged by name, so that a profile only lists what differs, and appends commands of its own
+fn merge_commands(base: &mut Vec<Value>, overlay: Vec<Value>) {
+    for command in overlay {
+        let name = command.get("name").and_then(|name| name.as_str()).map(|name| name.to_owned());
+
+        let existing = name.and_then(|name| base.iter_mut()
+            .find(|existing| existing.get("name").and_then(|other| other.as_str()) == Some(name.as_str())));
+
+        match existing {
+            Some(existing) => merge(existing, command),
+            None => base.push(command),
+        }
+    }
+}
+
+// tables are merged key by key, anything else is replaced
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (&mut Value::Table(ref mut base), Value::Table(overlay)) => merge_tables(base, overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(&mut Value::Array(ref mut commands)), Value::Array(overlay)) if key == "commands" =>
+                merge_commands(commands, overlay),
+            (Some(existing), value) => merge(existing, value),
+            (None, value) => {
+                base.insert(key, value);
+            },
+        }
+    }
+}
+
+// takes the [profile.<name>] tables out of the config, merging the selected one over the rest
+pub fn apply(config: &mut Value, profile: Option<&str>) -> Result<()> {
+    let root = match *config {
+        Value::Table(ref mut root) => root,
+        _ => bail!("The config is not a table"),
+    };
+
+    let mut profiles = match root.remove("profile") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => bail!("profile must be a table of [profile.<name>] tables"),
+        None => Table::new(),
+    };
+
+    let name = match profile {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+
+    match profiles.remove(name) {
+        Some(Value::Table(overlay)) => {
+            merge_tables(root, overlay);
+            Ok(())
+        },
+        Some(_) => bail!("Config profile {} is not a table", name),
+        None => bail!("Unknown config profile {}, the config has {:?}", name, profiles.keys().collect::<Vec<_>>()),
+    }
+}
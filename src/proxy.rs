@@ -0,0 +1,88 @@
+use errors::*;
+use logging;
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+// a target that is down must not keep clients waiting
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn copy_half(mut from: TcpStream, mut to: TcpStream) -> io::Result<u64> {
+    let copied = io::copy(&mut from, &mut to);
+
+    // passes the end of the stream on, so that the other side sees it as well
+    let _ = to.shutdown(Shutdown::Write);
+    copied
+}
+
+// forwards both directions between the two connections until both sides are done,
+// returning the bytes sent to the upstream and back to the client
+pub fn pipe(client: TcpStream, upstream: TcpStream) -> io::Result<(u64, u64)> {
+    let client_read = client.try_clone()?;
+    let upstream_write = upstream.try_clone()?;
+
+    let sent = thread::spawn(move || copy_half(client_read, upstream_write));
+    let received = copy_half(upstream, client);
+
+    let sent = sent.join().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Proxy thread panicked")));
+    Ok((sent?, received?))
+}
+
+fn connect(target: &str) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} resolves to no address", target));
+
+    for addr in target.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+// accepts connections on listen for as long as the service runs, proxying each to target, of the command name
+pub fn forward(name: &str, listen: &str, target: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .chain_err(|| format!("Unable to listen on {} to forward to [{}]", listen, name))?;
+
+    info!(target: &logging::target(name), "Forwarding {} to {} for [{}]", listen, target, name);
+
+    let name = name.to_owned();
+    let target = target.to_owned();
+
+    let _ = thread::spawn(move || {
+        for client in listener.incoming() {
+            let client = match client {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!(target: &logging::target(&name), "Unable to accept a connection to forward to [{}]: {}",
+                        name, e);
+                    continue;
+                },
+            };
+
+            let name = name.clone();
+            let target = target.clone();
+
+            let _ = thread::spawn(move || {
+                // the child may just be restarting, the client gets to retry
+                let upstream = match connect(&target) {
+                    Ok(upstream) => upstream,
+                    Err(e) => {
+                        debug!(target: &logging::target(&name), "Unable to forward a connection to [{}] at {}: {}",
+                            name, target, e);
+                        return;
+                    },
+                };
+
+                if let Err(e) = pipe(client, upstream) {
+                    debug!(target: &logging::target(&name), "Forwarded connection to [{}] failed: {}", name, e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
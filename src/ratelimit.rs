@@ -0,0 +1,96 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+// allows bursts of up to `per_minute` and then refills at that rate, zero means unlimited
+pub struct TokenBucket {
+    per_minute: u32,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(per_minute: u32) -> TokenBucket {
+        TokenBucket {
+            per_minute: per_minute,
+            state: Mutex::new((per_minute as f64, Instant::now())),
+        }
+    }
+
+    pub fn try_take(&self) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let (ref mut tokens, ref mut refilled) = *state;
+
+        let elapsed = refilled.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+        *tokens = (*tokens + secs * self.per_minute as f64 / 60.0).min(self.per_minute as f64);
+        *refilled = Instant::now();
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// allows up to `per_second` lines in every second, counting the lines dropped past that, zero means unlimited
+pub struct LineLimit {
+    per_second: u32,
+    state: Mutex<LineWindow>,
+}
+
+struct LineWindow {
+    started: Instant,
+    taken: u32,
+    suppressed: u64,
+}
+
+impl LineLimit {
+    pub fn new(per_second: u32) -> LineLimit {
+        LineLimit {
+            per_second: per_second,
+            state: Mutex::new(LineWindow {
+                started: Instant::now(),
+                taken: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    // whether the line may pass, and how many lines were dropped in the window that just ended
+    pub fn try_take(&self) -> (bool, u64) {
+        if self.per_second == 0 {
+            return (true, 0);
+        }
+
+        let mut window = self.state.lock().unwrap();
+        let mut suppressed = 0;
+
+        if window.started.elapsed().as_secs() >= 1 {
+            suppressed = window.suppressed;
+            *window = LineWindow {
+                started: Instant::now(),
+                taken: 0,
+                suppressed: 0,
+            };
+        }
+
+        if window.taken < self.per_second {
+            window.taken += 1;
+            (true, suppressed)
+        } else {
+            window.suppressed += 1;
+            (false, suppressed)
+        }
+    }
+
+    // the lines dropped so far in the current window, once the output closes
+    pub fn take_suppressed(&self) -> u64 {
+        ::std::mem::replace(&mut self.state.lock().unwrap().suppressed, 0)
+    }
+}
@@ -0,0 +1,284 @@
+use control::{self, Request, Response};
+use errors::*;
+use serde_json;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use supervisor::Supervisor;
+
+// a stalled client must not park a thread for good
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// requests larger than this are not control requests
+const MAX_BODY_LEN: u64 = 64 * 1024;
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    token: Option<String>,
+}
+
+// the request line and headers, reading further is answered with 431
+const MAX_HEAD_LEN: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+type HttpResult<T> = ::std::result::Result<T, (u16, String)>;
+
+// reads a line of the request head, which must end before the head limit of the reader runs out
+fn read_head_line<R: Read>(reader: &mut BufReader<io::Take<R>>, line: &mut String) -> HttpResult<()> {
+    reader.read_line(line)
+        .map_err(|e| (400, format!("Unable to read request: {}", e)))?;
+
+    if !line.ends_with('\n') {
+        return Err(if reader.get_ref().limit() == 0 {
+            (431, format!("Request head larger than {} bytes", MAX_HEAD_LEN))
+        } else {
+            (400, "Incomplete request".to_owned())
+        });
+    }
+
+    Ok(())
+}
+
+fn read_request<R: Read>(stream: R) -> HttpResult<HttpRequest> {
+    // the limit also bounds what the token check is done on
+    let mut reader = BufReader::new(stream.take(MAX_HEAD_LEN));
+    let mut line = String::new();
+
+    read_head_line(&mut reader, &mut line)?;
+
+    let mut parts = line.split_whitespace();
+
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+        _ => return Err((400, format!("Invalid request line: {}", line.trim()))),
+    };
+
+    let mut token = None;
+    let mut content_len: u64 = 0;
+    let mut headers = 0;
+
+    loop {
+        let mut header = String::new();
+        read_head_line(&mut reader, &mut header)?;
+
+        let header = header.trim();
+
+        if header.is_empty() {
+            break;
+        }
+
+        headers += 1;
+
+        if headers > MAX_HEADERS {
+            return Err((431, format!("More than {} request headers", MAX_HEADERS)));
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+
+            if name.eq_ignore_ascii_case("authorization") {
+                token = value.strip_prefix("Bearer ").map(|token| token.trim().to_owned());
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_len = value.parse().unwrap_or(0);
+            }
+        }
+    }
+
+    // everything is in the path, a body is only drained
+    reader.get_mut().set_limit(MAX_BODY_LEN);
+    let _ = io::copy(&mut reader.take(content_len.min(MAX_BODY_LEN)), &mut io::sink());
+
+    Ok(HttpRequest { method: method, path: path, token: token })
+}
+
+// compares in constant time, so that the token cannot be guessed byte by byte
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len() &&
+        given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// the path segments are the words of the pipe request, e.g. POST /maintenance/1h is `maintenance 1h`,
+// anything that changes the supervisor must be a POST
+fn route(request: &HttpRequest) -> HttpResult<(Request, String)> {
+    let path = request.path.split('?').next().unwrap_or("");
+    let line = path.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>().join(" ");
+
+    let parsed = line.parse::<Request>().map_err(|e| (404, e.to_string()))?;
+
+    let read_only = matches!(parsed,
+        Request::Status | Request::History | Request::PrintConfig | Request::LogLevel(None) | Request::Tail { follow: false, .. });
+
+    match request.method.as_str() {
+        "GET" if read_only => Ok((parsed, line)),
+        "POST" => Ok((parsed, line)),
+        _ => Err((405, format!("{} is not allowed for {}", request.method, path))),
+    }
+}
+
+fn respond(mut stream: &TcpStream, status: u16, response: &Response) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
+    };
+
+    let body = serde_json::to_vec(response)
+        .chain_err(|| "Unable to serialize response")?;
+
+    let auth = if status == 401 { "WWW-Authenticate: Bearer\r\n" } else { "" };
+
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        status, reason, body.len(), auth)
+        .and_then(|_| stream.write_all(&body))
+        .chain_err(|| "Unable to write response")
+}
+
+fn failure(error: String) -> Response {
+    Response { ok: false, result: None, error: Some(error) }
+}
+
+fn serve_client(stream: TcpStream, peer: SocketAddr, token: &str, supervisor: &Supervisor) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+        .chain_err(|| "Unable to set client timeouts")?;
+
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err((status, error)) => return respond(&stream, status, &failure(error)),
+    };
+
+    if !request.token.as_ref().map(|given| token_matches(given, token)).unwrap_or(false) {
+        warn!("Rejected REST request {} {} from {} without a valid token", request.method, request.path, peer);
+        return respond(&stream, 401, &failure("Missing or invalid bearer token".to_owned()));
+    }
+
+    let (parsed, line) = match route(&request) {
+        Ok(routed) => routed,
+        Err((status, error)) => return respond(&stream, status, &failure(error)),
+    };
+
+    let initiator = format!("rest client {}", peer);
+
+    debug!("Received control request from {}: {}", initiator, line);
+    supervisor.record_control(&line, &initiator);
+
+    match control::handle(parsed, supervisor, &initiator) {
+        Ok(result) => respond(&stream, 200, &Response { ok: true, result: Some(result), error: None }),
+        Err(e) => {
+            warn!("Control request [{}] failed: {}", line, e);
+            respond(&stream, 400, &failure(e.to_string()))
+        },
+    }
+}
+
+// serves the control requests as a json api on localhost, one thread per client
+pub fn serve(port: u16, token: String, supervisor: Arc<Supervisor>) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .chain_err(|| format!("Unable to listen on localhost port {} for the REST API", port))?;
+
+    let token = Arc::new(token);
+
+    let _ = thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Unable to accept REST client: {}", e);
+                    continue;
+                },
+            };
+
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(_) => continue,
+            };
+
+            let token = token.clone();
+            let supervisor = supervisor.clone();
+
+            let _ = thread::spawn(move || {
+                if let Err(e) = serve_client(stream, peer, &token, &supervisor) {
+                    error!("REST client error: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use supervisor::Maintenance;
+
+    fn request(method: &str, path: &str) -> HttpRequest {
+        HttpRequest { method: method.to_owned(), path: path.to_owned(), token: None }
+    }
+
+    #[test]
+    fn matches_only_the_same_token() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3creT", "s3cret"));
+        assert!(!token_matches("s3cre", "s3cret"));
+        assert!(!token_matches("", "s3cret"));
+    }
+
+    #[test]
+    fn routes_path_segments_as_pipe_requests() {
+        let (parsed, line) = route(&request("GET", "/status?pretty")).unwrap();
+        assert!(matches!(parsed, Request::Status));
+        assert_eq!(line, "status");
+
+        let (parsed, line) = route(&request("POST", "/maintenance/1h/")).unwrap();
+        assert!(matches!(parsed, Request::Maintenance(Maintenance::Enter(_))));
+        assert_eq!(line, "maintenance 1h");
+    }
+
+    #[test]
+    fn requires_post_for_changes() {
+        assert_eq!(route(&request("GET", "/restart/web")).err().map(|e| e.0), Some(405));
+        assert_eq!(route(&request("GET", "/tail/20/follow")).err().map(|e| e.0), Some(405));
+        assert_eq!(route(&request("DELETE", "/status")).err().map(|e| e.0), Some(405));
+        assert!(route(&request("POST", "/restart/web")).is_ok());
+        assert!(route(&request("GET", "/log-level")).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_paths() {
+        assert_eq!(route(&request("GET", "/")).err().map(|e| e.0), Some(404));
+        assert_eq!(route(&request("POST", "/format/c")).err().map(|e| e.0), Some(404));
+    }
+
+    #[test]
+    fn reads_method_path_and_token() {
+        let raw = "POST /drain HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer abc \r\nContent-Length: 2\r\n\r\n{}";
+        let request = read_request(raw.as_bytes()).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/drain");
+        assert_eq!(request.token.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn rejects_oversized_heads() {
+        let long = format!("GET /status HTTP/1.1\r\nX-Filler: {}\r\n\r\n", "a".repeat(MAX_HEAD_LEN as usize));
+        assert_eq!(read_request(long.as_bytes()).err().map(|e| e.0), Some(431));
+
+        let many = format!("GET /status HTTP/1.1\r\n{}\r\n", "X-Filler: a\r\n".repeat(MAX_HEADERS + 1));
+        assert_eq!(read_request(many.as_bytes()).err().map(|e| e.0), Some(431));
+    }
+
+    #[test]
+    fn rejects_incomplete_requests() {
+        assert_eq!(read_request("GET /status HTTP/1.1\r\nHost: local".as_bytes()).err().map(|e| e.0), Some(400));
+        assert_eq!(read_request("\r\n".as_bytes()).err().map(|e| e.0), Some(400));
+    }
+}
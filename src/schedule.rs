@@ -0,0 +1,327 @@
+use chrono::{self, DateTime, Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use errors::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use win;
+
+// what happens to a scheduled time that does not exist on the day the clocks are put forward
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DstGap {
+    // runs as soon as the clocks were put forward, e.g. at 03:00 instead of 02:30
+    Shift,
+    // not at all that day
+    Skip,
+}
+
+impl Default for DstGap {
+    fn default() -> DstGap {
+        DstGap::Shift
+    }
+}
+
+// how far the wall clock may drift from the monotonic one between checks before it counts as changed
+const CLOCK_JUMP_TOLERANCE: i64 = 5;
+
+// gaps are at most a few hours long, searched by the minute
+const MAX_GAP_MINUTES: i64 = 3 * 60;
+
+// how far ahead the next occurrence outside of the excluded dates is searched
+const MAX_DAYS_AHEAD: i64 = 400;
+
+// a date on which schedules are skipped, such as a holiday, written as "2024-12-25",
+// a range of them as "2024-12-24..2025-01-01", or every year as "12-25"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExcludedDates {
+    Range(NaiveDate, NaiveDate),
+    Yearly(u32, u32),
+}
+
+impl ExcludedDates {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        match *self {
+            ExcludedDates::Range(first, last) => first <= date && date <= last,
+            ExcludedDates::Yearly(month, day) => date.month() == month && date.day() == day,
+        }
+    }
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .chain_err(|| format!("Invalid date, expected YYYY-MM-DD: {}", s))
+}
+
+impl FromStr for ExcludedDates {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExcludedDates> {
+        let s = s.trim();
+
+        if let Some(pos) = s.find("..") {
+            let (first, last) = (parse_date(&s[..pos])?, parse_date(&s[pos + 2..])?);
+
+            if last < first {
+                bail!("Invalid date range, it ends before it starts: {}", s);
+            }
+
+            return Ok(ExcludedDates::Range(first, last));
+        }
+
+        if s.len() == 5 {
+            // any leap year, so that 02-29 is accepted
+            let date = NaiveDate::parse_from_str(&format!("2000-{}", s), "%Y-%m-%d")
+                .chain_err(|| format!("Invalid yearly date, expected MM-DD: {}", s))?;
+
+            return Ok(ExcludedDates::Yearly(date.month(), date.day()));
+        }
+
+        parse_date(s).map(|date| ExcludedDates::Range(date, date))
+    }
+}
+
+impl fmt::Display for ExcludedDates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExcludedDates::Range(first, last) if first == last => write!(f, "{}", first.format("%Y-%m-%d")),
+            ExcludedDates::Range(first, last) => write!(f, "{}..{}", first.format("%Y-%m-%d"), last.format("%Y-%m-%d")),
+            ExcludedDates::Yearly(month, day) => write!(f, "{:02}-{:02}", month, day),
+        }
+    }
+}
+
+impl Serialize for ExcludedDates {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ExcludedDates {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<ExcludedDates, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|e: Error| de::Error::custom(e.to_string()))
+    }
+}
+
+// one entry per line, blank lines and lines starting with # are left out
+pub fn parse_excluded_dates(text: &str) -> Result<Vec<ExcludedDates>> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse())
+        .collect()
+}
+
+// the instant of a wall clock time in the zone, or the machine's local time zone without one,
+// none if it falls into a daylight saving gap, and the earlier one if it occurs twice as the clocks are put back
+fn resolve(naive: &NaiveDateTime, zone: Option<&win::TimeZone>) -> Option<DateTime<Utc>> {
+    match zone {
+        None => {
+            let local = Local.from_local_datetime(naive);
+            local.earliest().map(|local| local.with_timezone(&Utc))
+        },
+
+        // windows shifts times in a gap instead of rejecting them, which the way back reveals
+        Some(zone) => {
+            let utc = zone.to_utc(naive).ok()?;
+
+            if zone.to_local(&utc).ok()? == *naive {
+                Some(Utc.from_utc_datetime(&utc))
+            } else {
+                None
+            }
+        },
+    }
+}
+
+// timers count on the monotonic clock, which ntp corrections and manual changes of the wall clock do not move,
+// so a schedule, which is in wall clock time, notices such changes by comparing how far both clocks moved
+pub struct ClockWatch {
+    instant: Instant,
+    wall: DateTime<Utc>,
+}
+
+impl ClockWatch {
+    pub fn new() -> ClockWatch {
+        ClockWatch { instant: Instant::now(), wall: Utc::now() }
+    }
+
+    // how far the wall clock was changed since the last call, forward if positive, if it was at all
+    pub fn jumped(&mut self) -> Option<chrono::Duration> {
+        let (instant, wall) = (Instant::now(), Utc::now());
+        let elapsed = chrono::Duration::from_std(instant - self.instant).unwrap_or_else(|_| chrono::Duration::zero());
+        let jump = (wall - self.wall) - elapsed;
+
+        self.instant = instant;
+        self.wall = wall;
+
+        if jump.num_seconds().abs() > CLOCK_JUMP_TOLERANCE {
+            Some(jump)
+        } else {
+            None
+        }
+    }
+}
+
+// local wall clock time of the day, written as "HH:MM"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeOfDay(NaiveTime);
+
+impl TimeOfDay {
+    // time left until the next occurrence, which is tomorrow if it already passed today,
+    // in the named windows time zone or the machine's local one, none if every day ahead is excluded
+    pub fn until_next(&self, timezone: Option<&str>, dst_gap: DstGap, excluded: &[ExcludedDates]) -> Option<Duration> {
+        let zone = timezone.and_then(|name| match win::TimeZone::find(name) {
+            Ok(zone) => Some(zone),
+            Err(e) => {
+                warn!("Scheduling in local time instead: {}", e);
+                None
+            },
+        });
+
+        let now = Utc::now();
+
+        let today = match zone {
+            Some(ref zone) => zone.to_local(&now.naive_utc()).unwrap_or_else(|_| now.naive_utc()).date(),
+            None => now.with_timezone(&Local).naive_local().date(),
+        };
+
+        next_occurrence(self.0, today, now, dst_gap, excluded, |naive| resolve(naive, zone.as_ref()))
+            .and_then(|next| (next - now).to_std().ok())
+    }
+}
+
+// the first instant after now at which the wall clock shows the time, from today on, with resolve giving the instant
+// of a wall clock time as the time zone does
+fn next_occurrence<F>(time: NaiveTime, today: NaiveDate, now: DateTime<Utc>, dst_gap: DstGap, excluded: &[ExcludedDates],
+    resolve: F) -> Option<DateTime<Utc>>
+    where F: Fn(&NaiveDateTime) -> Option<DateTime<Utc>>
+{
+    (0..MAX_DAYS_AHEAD)
+        .map(|days| today + chrono::Duration::days(days))
+        .filter(|date| !excluded.iter().any(|dates| dates.contains(*date)))
+        .map(|date| date.and_time(time))
+        .filter_map(|naive| match resolve(&naive) {
+            Some(next) => Some(next),
+            None if dst_gap == DstGap::Shift => (1..MAX_GAP_MINUTES + 1)
+                .filter_map(|minutes| resolve(&(naive + chrono::Duration::minutes(minutes))))
+                .next(),
+            None => None,
+        })
+        .find(|next| *next > now)
+}
+
+impl FromStr for TimeOfDay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TimeOfDay> {
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .map(TimeOfDay)
+            .chain_err(|| format!("Invalid time of day, expected HH:MM: {}", s))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.format("%H:%M"))
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<TimeOfDay, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|e: Error| de::Error::custom(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn utc(date: &str, time: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&at(date, time))
+    }
+
+    // central european time, put forward from 02:00 to 03:00 on 2024-03-31 and back from 03:00 to 02:00
+    // on 2024-10-27, the earlier instant of a time that occurs twice comes first
+    fn berlin(naive: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        let offset = |instant: &DateTime<Utc>| {
+            if *instant >= utc("2024-03-31", "01:00") && *instant < utc("2024-10-27", "01:00") { 2 } else { 1 }
+        };
+
+        [2, 1].iter()
+            .map(|&hours| (hours, Utc.from_utc_datetime(&(*naive - chrono::Duration::hours(hours)))))
+            .find(|&(hours, instant)| offset(&instant) == hours)
+            .map(|(_, instant)| instant)
+    }
+
+    fn next(time: &str, today: &str, now: DateTime<Utc>, dst_gap: DstGap, excluded: &[ExcludedDates])
+        -> Option<DateTime<Utc>> {
+        let time = time.parse::<TimeOfDay>().unwrap().0;
+        let today = NaiveDate::parse_from_str(today, "%Y-%m-%d").unwrap();
+        next_occurrence(time, today, now, dst_gap, excluded, berlin)
+    }
+
+    #[test]
+    fn runs_later_today_or_tomorrow() {
+        assert_eq!(next("12:00", "2024-06-01", utc("2024-06-01", "08:00"), DstGap::Shift, &[]),
+            Some(utc("2024-06-01", "10:00")));
+        assert_eq!(next("09:00", "2024-06-01", utc("2024-06-01", "08:00"), DstGap::Shift, &[]),
+            Some(utc("2024-06-02", "07:00")));
+    }
+
+    #[test]
+    fn shifts_times_in_the_gap_to_when_the_clocks_were_put_forward() {
+        assert_eq!(next("02:30", "2024-03-31", utc("2024-03-30", "23:30"), DstGap::Shift, &[]),
+            Some(utc("2024-03-31", "01:00")));
+    }
+
+    #[test]
+    fn skips_times_in_the_gap() {
+        assert_eq!(next("02:30", "2024-03-31", utc("2024-03-30", "23:30"), DstGap::Skip, &[]),
+            Some(utc("2024-04-01", "00:30")));
+    }
+
+    #[test]
+    fn runs_once_when_the_clocks_are_put_back() {
+        assert_eq!(next("02:30", "2024-10-27", utc("2024-10-26", "23:30"), DstGap::Shift, &[]),
+            Some(utc("2024-10-27", "00:30")));
+
+        // not again once the time comes around a second time
+        assert_eq!(next("02:30", "2024-10-27", utc("2024-10-27", "00:45"), DstGap::Shift, &[]),
+            Some(utc("2024-10-28", "01:30")));
+    }
+
+    #[test]
+    fn skips_excluded_dates() {
+        let excluded = ["2024-06-01..2024-06-02".parse().unwrap(), "06-03".parse().unwrap()];
+
+        assert_eq!(next("12:00", "2024-06-01", utc("2024-06-01", "08:00"), DstGap::Shift, &excluded),
+            Some(utc("2024-06-04", "10:00")));
+    }
+
+    #[test]
+    fn parses_excluded_dates() {
+        assert_eq!("12-25".parse::<ExcludedDates>().unwrap(), ExcludedDates::Yearly(12, 25));
+        assert_eq!("02-29".parse::<ExcludedDates>().unwrap(), ExcludedDates::Yearly(2, 29));
+        assert!("2024-12-31..2024-12-24".parse::<ExcludedDates>().is_err());
+        assert!("13-01".parse::<ExcludedDates>().is_err());
+
+        let range = "2024-12-24..2025-01-01".parse::<ExcludedDates>().unwrap();
+        assert!(range.contains(NaiveDate::from_ymd_opt(2024, 12, 31).unwrap()));
+        assert!(!range.contains(NaiveDate::from_ymd_opt(2025, 1, 2).unwrap()));
+        assert_eq!(range.to_string(), "2024-12-24..2025-01-01");
+    }
+}
@@ -0,0 +1,588 @@
+use serde_json::Value;
+
+// JSON Schema (draft-07) of the config file, kept in sync with config.rs by hand
+
+fn duration(description: &str, default: &str) -> Value {
+    json!({
+        "type": "string",
+        "description": format!("{}, a humantime duration such as \"1s\", \"5m\" or \"1h 30m\"", description),
+        "default": default,
+    })
+}
+
+fn strings(description: &str) -> Value {
+    json!({
+        "type": "array",
+        "items": { "type": "string" },
+        "default": [],
+        "description": description,
+    })
+}
+
+fn rest_api() -> Value {
+    json!({
+        "type": "object",
+        "description": "The control requests as an HTTP JSON API on localhost, e.g. GET /status or POST /maintenance/1h",
+        "additionalProperties": false,
+        "required": ["port", "token"],
+        "properties": {
+            "port": {
+                "type": "integer",
+                "minimum": 1,
+                "maximum": 65535,
+                "description": "Port on 127.0.0.1 to listen on",
+            },
+            "token": {
+                "type": "string",
+                "minLength": 16,
+                "description": "Bearer token every request must carry in its Authorization header",
+            },
+        },
+    })
+}
+
+fn syslog() -> Value {
+    json!({
+        "type": "object",
+        "description": "Forwards the log as RFC 5424 messages to a syslog collector",
+        "additionalProperties": false,
+        "required": ["address"],
+        "properties": {
+            "address": {
+                "type": "string",
+                "description": "host:port of the collector",
+            },
+            "protocol": {
+                "type": "string",
+                "enum": ["udp", "tcp"],
+                "default": "udp",
+                "description": "tcp uses octet counting framing",
+            },
+            "level": {
+                "type": "string",
+                "enum": ["error", "warn", "info", "debug", "trace"],
+                "default": "info",
+                "description": "Least severe level forwarded",
+            },
+            "child_output": {
+                "type": "boolean",
+                "default": false,
+                "description": "Also forward the logged output of the commands",
+            },
+            "facility": {
+                "type": "integer",
+                "minimum": 0,
+                "maximum": 23,
+                "default": 3,
+                "description": "Syslog facility, 3 is daemon",
+            },
+        },
+    })
+}
+
+fn logger() -> Value {
+    json!({
+        "type": "object",
+        "description": "Level and log file of a log target, cmd::<name> for what is logged about a command, cmd::<name>::output for its output alone",
+        "additionalProperties": false,
+        "properties": {
+            "level": {
+                "type": "string",
+                "enum": ["off", "error", "warn", "info", "debug", "trace"],
+                "description": "Least severe level logged, the log level of the service by default",
+            },
+            "file": {
+                "type": "string",
+                "description": "Log file the target is also logged to",
+            },
+            "additive": {
+                "type": "boolean",
+                "default": true,
+                "description": "Also log the target to the service log and syslog, not only to its file",
+            },
+        },
+    })
+}
+
+fn service() -> Value {
+    json!({
+        "type": "object",
+        "description": "Service wide settings",
+        "additionalProperties": false,
+        "properties": {
+            "redact": strings("Extra regexes of secrets to mask in logged command lines, only the capture groups are masked if present"),
+            "maintenance_duration": duration("How long maintenance mode lasts when toggled on without an explicit duration", "1h"),
+            "log_file": {
+                "type": "string",
+                "description": "Log file path, defaults to the executable name with .log next to the executable",
+            },
+            "log_encoding": {
+                "enum": ["utf8", "utf8_bom"],
+                "default": "utf8",
+                "description": "Log files are UTF-8, utf8_bom starts each new one with a byte order mark for tools that otherwise read it in the ANSI codepage",
+            },
+            "relative_to_config": {
+                "type": "boolean",
+                "default": true,
+                "description": "Resolve relative paths against the config file directory instead of the service working directory",
+            },
+            "history_size": {
+                "type": "integer",
+                "minimum": 0,
+                "default": 200,
+                "description": "Number of recent supervisor events kept for the history control request",
+            },
+            "shutdown_timeout": duration("Upper bound of the per command stop_timeout when the system is shutting down", "5s"),
+            "jobs_stop_timeout": duration("On stop, manual commands stop first, the other commands once they did or after this long, capped at shutdown_timeout on system shutdown", "1m"),
+            "stop_budget": duration("Once a stop takes this long in total, the commands still running are killed and the service reports stopped regardless", "3m"),
+            "network_check_host": {
+                "type": "string",
+                "description": "Host whose name must resolve for the network to count as up, otherwise any route out of the machine will do",
+            },
+            "network_wait_timeout": duration("How long needs_network commands wait for the network before starting anyway", "2m"),
+            "startup_stagger": duration("Delay between the first spawns of successive commands at service start, in config order, 0s starts them all at once", "0s"),
+            "container_mode": {
+                "type": "boolean",
+                "default": false,
+                "description": "Start shell commands directly instead of through cmd.exe and stop them without console ctrl-c, for Windows containers",
+            },
+            "file_acl": {
+                "type": "string",
+                "default": "D:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;OW)",
+                "description": "SDDL DACL applied to the log, lock and audit files, empty keeps the permissions inherited from the directory",
+            },
+            "data_dir": {
+                "type": "string",
+                "description": "Root of the data and logs directories of each command, <data_dir>\\<command>\\data and \\logs, created on its first start and passed to the child as SUPERVISOR_DATA_DIR and SUPERVISOR_LOG_DIR",
+            },
+            "data_dir_acl": {
+                "type": "string",
+                "default": "D:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)(A;OICI;FA;;;OW)",
+                "description": "SDDL DACL applied to the directory of each command under data_dir as it is created, inherited by everything within, empty keeps the permissions inherited from data_dir",
+            },
+            "audit_file": {
+                "type": "string",
+                "description": "Append-only JSON lines log of spawns, kills, restarts, control requests and reloads with their initiator, disabled if unset",
+            },
+            "update_manifest": {
+                "type": "string",
+                "description": "https URL of a JSON manifest with the version, https url and sha256 of the latest binary, self-updates are disabled if unset",
+            },
+            "update_interval": duration("How often the update manifest is checked", "1d"),
+            "update_publisher": {
+                "type": "string",
+                "description": "An update must carry a valid Authenticode signature of this publisher, defaults to the publisher of the running binary, without which updates are refused",
+            },
+            "strict": {
+                "type": "boolean",
+                "default": false,
+                "description": "Reject shell commands whose cmd contains & | < > ^ % ! or newlines after variable expansion, arguments then belong in args",
+            },
+            "max_restarts_per_minute": {
+                "type": "integer",
+                "minimum": 0,
+                "default": 30,
+                "description": "Restarts by restart policy across all commands, in bursts of up to this many and then this many per minute, 0 is unlimited",
+            },
+            "restart_budgets": {
+                "type": "object",
+                "additionalProperties": { "type": "integer", "minimum": 0 },
+                "default": {},
+                "description": "Restart budgets by group name, shared by the commands with that restart_group, in bursts of up to this many and then this many per minute",
+            },
+            "dump_dir": {
+                "type": "string",
+                "description": "Directory of the minidumps of crashing minidump commands, defaults to dumps next to the log file",
+            },
+            "dump_count": {
+                "type": "integer",
+                "minimum": 0,
+                "default": 10,
+                "description": "Most recent minidumps kept per executable",
+            },
+            "metrics_file": {
+                "type": "string",
+                "description": "CSV in the perfmon format that availability and resource usage of every command is appended to, disabled if unset",
+            },
+            "metrics_interval": duration("How often a row is appended to the metrics file", "1m"),
+            "heartbeat_file": {
+                "type": "string",
+                "description": "JSON file with the time, pid and health (healthy, degraded or failed) of the service and the commands waiting out a backoff, rewritten every heartbeat_interval, disabled if unset",
+            },
+            "heartbeat_interval": duration("How often the heartbeat file is rewritten", "30s"),
+            "min_free_space_mb": {
+                "type": "integer",
+                "minimum": 0,
+                "default": 1024,
+                "description": "Free megabytes below which the volumes of the log, audit log, metrics and dumps raise alerts and pause commands with pause_on_low_disk, 0 disables the check",
+            },
+            "disk_check_interval": duration("How often the free space of the volumes is checked, besides once before starting", "5m"),
+            "leak_check_interval": duration("How often handle and thread counts are checked for steady growth and the children of the supervisor for strays", "10m"),
+            "alert_window": {
+                "type": "string",
+                "description": "After a crash alert, in the log and the event log, further crash alerts of the same command are held back for this long, the next one then counts the crashes in between, e.g. 15m",
+            },
+            "event_log": {
+                "type": "boolean",
+                "default": false,
+                "description": "Write service and command lifecycle events to the application event log, each value in its own EventData field for Windows Event Forwarding, crashes are held back by alert_window",
+            },
+            "rest_api": rest_api(),
+            "syslog": syslog(),
+            "loggers": {
+                "type": "object",
+                "description": "Loggers by log target, e.g. cmd::app, changes need a service restart",
+                "additionalProperties": logger(),
+            },
+        },
+    })
+}
+
+fn activation() -> Value {
+    json!({
+        "type": "object",
+        "description": "Listen in place of the manual command, starting it on the first connection and proxying every connection to it, changes need a service restart",
+        "additionalProperties": false,
+        "required": ["listen", "target"],
+        "properties": {
+            "listen": {
+                "type": "string",
+                "description": "Address the service accepts connections on, e.g. 0.0.0.0:8080",
+            },
+            "target": {
+                "type": "string",
+                "description": "Address the child listens on, e.g. 127.0.0.1:18080",
+            },
+            "ready_timeout": duration("How long a connection waits for the child to accept before it is dropped", "30s"),
+            "idle_timeout": {
+                "type": "string",
+                "description": "Gracefully stop the child after this long without connections, e.g. 15m, by default it keeps running",
+            },
+        },
+    })
+}
+
+fn forward() -> Value {
+    json!({
+        "type": "array",
+        "description": "Ports the service forwards for as long as it runs, e.g. to reach a child that binds to localhost only from the network, changes need a service restart",
+        "default": [],
+        "items": {
+            "type": "object",
+            "additionalProperties": false,
+            "required": ["listen", "target"],
+            "properties": {
+                "listen": {
+                    "type": "string",
+                    "description": "Address the service accepts connections on, e.g. 0.0.0.0:8080",
+                },
+                "target": {
+                    "type": "string",
+                    "description": "Address of the child or another host every connection is proxied to, e.g. 127.0.0.1:8080",
+                },
+            },
+        },
+    })
+}
+
+fn command() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["name"],
+        "properties": {
+            "name": {
+                "type": "string",
+                "description": "Unique name of the command, used in logs, status and reload",
+            },
+            "kind": {
+                "type": "string",
+                "enum": ["shell", "powershell_script"],
+                "default": "shell",
+            },
+            "cmd": {
+                "type": "string",
+                "description": "Shell command line run by cmd.exe exactly as typed at a prompt, for the shell kind",
+            },
+            "script": {
+                "type": "string",
+                "description": "Path to the .ps1 file, for the powershell_script kind",
+            },
+            "args": strings("Parameters passed to the script, or appended literally to the shell command line"),
+            "execution_policy": {
+                "type": "string",
+                "default": "Bypass",
+                "description": "PowerShell execution policy, for the powershell_script kind",
+            },
+            "cwd": {
+                "type": "string",
+                "description": "Working directory of the child",
+            },
+            "include": strings("If non-empty, only output lines matching at least one of the regexes are logged"),
+            "exclude": strings("Output lines matching any of the regexes are dropped"),
+            "max_log_lines_per_second": {
+                "type": "integer",
+                "minimum": 0,
+                "description": "Output lines logged per second, stdout and stderr together, the rest is dropped with a count of the suppressed lines, 0 is unlimited",
+                "default": 0,
+            },
+            "max_line_bytes": {
+                "type": "integer",
+                "minimum": 1,
+                "description": "Logged output lines are cut off after this many bytes, the rest of them is dropped with a count of the truncated lines, output piped with pipe_to is passed on whole",
+                "default": 65536,
+            },
+            "success_output": {
+                "type": "string",
+                "description": "Regex a line of the output must match for an exit code of 0 to count as success",
+            },
+            "failure_output": {
+                "type": "string",
+                "description": "Regex no line of the output may match for an exit code of 0 to count as success",
+            },
+            "start": {
+                "type": "string",
+                "enum": ["auto", "manual"],
+                "default": "auto",
+                "description": "Manual commands are not started with the service, only by a start control request or their start_control_code",
+            },
+            "replicas": {
+                "type": "integer",
+                "minimum": 1,
+                "default": 1,
+                "description": "Identical instances supervised independently, named <name>.0, <name>.1 and so on, each with its index in SUPERVISOR_INSTANCE_INDEX, depends_on and anti_affinity naming the command refer to all of them",
+            },
+            "start_control_code": {
+                "type": "integer",
+                "minimum": 129,
+                "maximum": 255,
+                "description": "Custom service control code that starts this manual command, e.g. sc control <service> 129",
+            },
+            "on_success": {
+                "type": "string",
+                "description": "Manual command started whenever this one exits successfully, a failure ends the chain",
+            },
+            "pipe_to": {
+                "type": "string",
+                "description": "Command whose stdin receives the stdout of this one instead of the log",
+            },
+            "activation": activation(),
+            "forward": forward(),
+            "restart": {
+                "type": "string",
+                "enum": ["never", "on-failure", "always"],
+                "default": "never",
+            },
+            "restart_delay": duration("Initial delay before respawning, doubled on every consecutive restart", "1s"),
+            "restart_delay_max": duration("Upper bound of the restart delay", "60s"),
+            "restart_schedule": {
+                "type": "string",
+                "pattern": "^[0-9]{2}:[0-9]{2}$",
+                "description": "Daily local time at which the child is gracefully restarted, as HH:MM",
+            },
+            "schedule_timezone": {
+                "type": "string",
+                "description": "Windows time zone of restart_schedule as listed by tzutil /l, defaults to the machine's",
+            },
+            "schedule_dst_gap": {
+                "type": "string",
+                "enum": ["shift", "skip"],
+                "default": "shift",
+                "description": "Whether a scheduled time skipped as the clocks are put forward runs right after the jump or not at all",
+            },
+            "schedule_exclude": {
+                "type": "array",
+                "items": { "type": "string", "pattern": "^([0-9]{4}-)?[0-9]{2}-[0-9]{2}(\\.\\.[0-9]{4}-[0-9]{2}-[0-9]{2})?$" },
+                "default": [],
+                "description": "Dates on which the schedule is skipped, as YYYY-MM-DD, YYYY-MM-DD..YYYY-MM-DD or yearly MM-DD",
+            },
+            "schedule_exclude_file": {
+                "type": "string",
+                "description": "File with more dates on which the schedule is skipped, one per line",
+            },
+            "stop_timeout": duration("Time given to the child to exit after ctrl-c before it is killed", "10s"),
+            "on_service_stop": {
+                "type": "string",
+                "enum": ["stop", "wait", "kill"],
+                "default": "stop",
+                "description": "How a run still going when the service stops ends: ctrl-c and killed after stop_timeout, left to complete for up to stop_timeout and then killed, or killed right away",
+            },
+            "reload_cmd": {
+                "type": "string",
+                "description": "Command line run through cmd.exe by `reload <name>` to have the running child reload itself, with its pid in SUPERVISOR_CHILD_PID",
+            },
+            "reload_timeout": duration("Time the reload_cmd is given before it is killed and the reload failed", "30s"),
+            "ready_after": duration("A respawned child that is still running after this long is considered ready", "5s"),
+            "watch": strings("Files whose changes make the child gracefully restart"),
+            "refresh_env": {
+                "type": "boolean",
+                "default": false,
+                "description": "Re-read the machine and user environment from the registry before every spawn",
+            },
+            "env": {
+                "type": "object",
+                "additionalProperties": { "type": "string" },
+                "description": "Extra environment variables of the child, besides SUPERVISOR_SERVICE_NAME, SUPERVISOR_COMMAND_NAME, SUPERVISOR_RESTART_COUNT and SUPERVISOR_INSTANCE_INDEX",
+            },
+            "needs_network": {
+                "type": "boolean",
+                "default": false,
+                "description": "Wait for the network to be up before the first spawn",
+            },
+            "requires_drive": {
+                "type": "string",
+                "description": "Drive letter like \"D:\" or mount path, the first spawn waits for it to be mounted",
+            },
+            "creation_flags": {
+                "type": "array",
+                "items": {
+                    "type": "string",
+                    "enum": ["create_no_window", "create_new_process_group", "detached_process", "below_normal_priority"],
+                },
+                "default": [],
+                "description": "Process creation flags, create_new_process_group and detached_process prevent graceful stops with ctrl-c",
+            },
+            "disable_wow64_redirection": {
+                "type": "boolean",
+                "default": false,
+                "description": "For 32-bit builds, spawn with System32 not redirected to SysWOW64",
+            },
+            "session": {
+                "type": "string",
+                "enum": ["service", "console"],
+                "default": "service",
+                "description": "console runs the child as the user of the active console session so that gui apps are visible, requires LocalSystem",
+            },
+            "desktop": {
+                "type": "string",
+                "default": "winsta0\\default",
+                "description": "Window station and desktop of console session children",
+            },
+            "integrity_level": {
+                "type": "string",
+                "enum": ["low", "medium", "high"],
+                "description": "Integrity level the child runs at instead of the one of the service, which can only be lowered",
+            },
+            "privileges": {
+                "type": "array",
+                "items": { "type": "string" },
+                "description": "The only privileges the token of the child keeps, e.g. SeChangeNotifyPrivilege, all of them if unset",
+            },
+            "remove_privileges": strings("Privileges removed from the token of the child, e.g. SeDebugPrivilege"),
+            "utf8": {
+                "type": "boolean",
+                "default": false,
+                "description": "Set PYTHONIOENCODING and PYTHONUTF8, and for shell commands run through cmd.exe also the utf-8 console codepage",
+            },
+            "codepage": {
+                "type": "integer",
+                "minimum": 1,
+                "maximum": 65535,
+                "description": "Console codepage set with chcp for shell commands run through cmd.exe, and of output that is not UTF-8, e.g. 1252 or 932, defaults to the OEM codepage",
+            },
+            "tz": {
+                "type": "string",
+                "description": "TZ environment variable of the child, e.g. EST5EDT, honored by the C runtime, Python and ported Unix tools",
+            },
+            "locale": {
+                "type": "string",
+                "description": "LANG and LC_ALL environment variables of the child, e.g. de_DE.UTF-8",
+            },
+            "sha256": {
+                "type": "string",
+                "pattern": "^[0-9a-fA-F]{64}$",
+                "description": "Expected SHA-256 of the executable, or of the script for the powershell_script kind, a mismatch refuses the spawn",
+            },
+            "publisher": {
+                "type": "string",
+                "description": "The executable, or the script for the powershell_script kind, must carry a valid Authenticode signature of this publisher, otherwise the spawn is refused",
+            },
+            "depends_on": strings("Names of the commands this one relies on, without cycles"),
+            "restart_with_dependencies": {
+                "type": "boolean",
+                "default": false,
+                "description": "Gracefully restart once any command of depends_on was restarted and is ready again",
+            },
+            "restart_group": {
+                "type": "string",
+                "description": "Group in restart_budgets whose budget restarts by restart policy and along with dependencies take from",
+            },
+            "anti_affinity": strings("Names of the commands never to start up at the same time as this one, a start waits until the others have run for their ready_after"),
+            "minidump": {
+                "type": "boolean",
+                "default": false,
+                "description": "Have Windows Error Reporting capture a minidump into dump_dir when the executable crashes, machine wide for every process of its name",
+            },
+            "suppress_error_dialogs": {
+                "type": "boolean",
+                "default": false,
+                "description": "Start with critical error and crash dialogs disabled, so that a crashed child exits instead of hanging on invisible UI in session 0",
+            },
+            "pause_on_low_disk": {
+                "type": "boolean",
+                "default": false,
+                "description": "Gracefully stop the child while a volume of the log or other output is below min_free_space_mb, starting it again once space is freed",
+            },
+            "cpu_limit_percent": {
+                "type": "integer",
+                "minimum": 1,
+                "maximum": 100,
+                "description": "Hard cap on the CPU time of the child and the processes it starts, as a percentage of all processors of the machine",
+            },
+            "hang_timeout": {
+                "type": "string",
+                "description": "A child that wrote no output for this long is considered hung, it is killed and respawned on its own unless in maintenance, e.g. 10m",
+            },
+            "startup_output": {
+                "type": "string",
+                "description": "Regex of the output line after which the child has started up, until then hang_timeout does not apply",
+            },
+            "startup_timeout": {
+                "type": "string",
+                "description": "Time the child gets to start up, killed and respawned if startup_output did not match by then, without startup_output hang_timeout only applies afterwards, e.g. 5m",
+            },
+        },
+        "oneOf": [
+            {
+                "properties": { "kind": { "const": "shell" } },
+                "required": ["cmd"],
+                "not": { "required": ["script"] },
+            },
+            {
+                "properties": { "kind": { "const": "powershell_script" } },
+                "required": ["kind", "script"],
+                "not": { "required": ["cmd"] },
+            },
+        ],
+    })
+}
+
+pub fn schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "windows_service config",
+        "description": "${EXE_DIR}, ${CONFIG_DIR}, ${SERVICE_NAME}, ${PROGRAM_DATA} and ${PROFILE} are substituted in cmd, args, script, cwd, watch, env values, schedule_exclude_file, reload_cmd, log_file, audit_file, dump_dir, data_dir, metrics_file, heartbeat_file, update_manifest and the file of loggers",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "service": service(),
+            "cmds": strings("Legacy form, a bare list of shell command lines"),
+            "commands": {
+                "type": "array",
+                "items": command(),
+                "default": [],
+            },
+            "profile": {
+                "type": "object",
+                "description": "Profiles by name, selected by the --profile start argument or the Profile value under the Parameters key of the service, merged over the rest of the config, commands by name",
+                "additionalProperties": {
+                    "type": "object",
+                    "properties": {
+                        "service": { "type": "object" },
+                        "commands": { "type": "array", "items": { "type": "object" } },
+                    },
+                },
+            },
+        },
+    })
+}
@@ -0,0 +1,171 @@
+use command;
+use config::{CommandConfig, FileConfig, ServiceConfig};
+use errors::*;
+use lint;
+use paths::ServicePaths;
+use profile;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process;
+use vars::Variables;
+use win;
+
+// the outcome of each check, printed as it completes, so that a pipeline log shows how far it got
+#[derive(Default)]
+struct Report {
+    passed: usize,
+    failed: usize,
+}
+
+impl Report {
+    fn pass(&mut self, check: &str, detail: &str) {
+        self.passed += 1;
+        println!("PASS {}: {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, e: &Error) {
+        self.failed += 1;
+        println!("FAIL {}: {}", check, e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": "));
+    }
+
+    fn check<F: FnOnce() -> Result<String>>(&mut self, check: &str, f: F) {
+        match f() {
+            Ok(detail) => self.pass(check, &detail),
+            Err(e) => self.fail(check, &e),
+        }
+    }
+}
+
+// the name LookupAccountNameW knows the account by, the scm shows the local system account and local accounts
+// in forms it does not take
+fn lookup_name(account: &str) -> &str {
+    if account.eq_ignore_ascii_case("LocalSystem") {
+        "NT AUTHORITY\\SYSTEM"
+    } else {
+        account.strip_prefix(".\\").unwrap_or(account)
+    }
+}
+
+// creates the directory like the service would, and a file in it
+fn check_writable(dir: &Path) -> Result<String> {
+    fs::create_dir_all(dir)
+        .chain_err(|| format!("Unable to create directory {:?}", dir))?;
+
+    let probe = dir.join(format!(".self-test-{}.tmp", process::id()));
+
+    OpenOptions::new().write(true).create_new(true).open(&probe)
+        .chain_err(|| format!("Unable to write to directory {:?}", dir))?;
+
+    let _ = fs::remove_file(&probe);
+    Ok(format!("{:?} is writable", dir))
+}
+
+fn parent_dir(file: &str) -> PathBuf {
+    Path::new(file).parent().map(|dir| dir.to_owned()).unwrap_or_else(|| PathBuf::from("."))
+}
+
+// the directories the service writes to, by what it writes there
+fn output_dirs(paths: &ServicePaths, service_config: &ServiceConfig) -> Vec<(String, PathBuf)> {
+    let log_dir = match service_config.log_file {
+        Some(ref log_file) => parent_dir(log_file),
+        None => paths.log_file.parent().map(|dir| dir.to_owned()).unwrap_or_else(|| PathBuf::from(".")),
+    };
+
+    let mut dirs = vec![("log directory".to_owned(), log_dir.clone())];
+
+    let files = [("audit", &service_config.audit_file), ("metrics", &service_config.metrics_file),
+        ("heartbeat", &service_config.heartbeat_file)];
+
+    for &(what, file) in &files {
+        if let Some(ref file) = *file {
+            dirs.push((format!("{} directory", what), parent_dir(file)));
+        }
+    }
+
+    for (target, logger) in &service_config.loggers {
+        if let Some(ref file) = logger.file {
+            dirs.push((format!("logger {} directory", target), parent_dir(file)));
+        }
+    }
+
+    let dump_dir = service_config.dump_dir.as_ref().map(PathBuf::from).unwrap_or_else(|| log_dir.join("dumps"));
+    dirs.push(("dump directory".to_owned(), dump_dir));
+
+    if let Some(ref data_dir) = service_config.data_dir {
+        dirs.push(("data directory".to_owned(), PathBuf::from(data_dir)));
+    }
+
+    dirs
+}
+
+fn check_command(report: &mut Report, cmd: &CommandConfig) {
+    report.check(&format!("[{}] executable", cmd.name), || match command::target_file(cmd) {
+        Some(ref target) if target.is_file() => Ok(format!("{:?} exists", target)),
+        Some(target) => bail!("{:?} does not exist", target),
+        None => bail!("Unable to find the program of {:?} in its cwd or PATH", cmd.cmd),
+    });
+
+    if let Some(ref cwd) = cmd.cwd {
+        report.check(&format!("[{}] cwd", cmd.name), || if Path::new(cwd).is_dir() {
+            Ok(format!("{:?} exists", cwd))
+        } else {
+            bail!("Directory {:?} does not exist", cwd)
+        });
+    }
+}
+
+// checks what the service needs to start and run its commands, without starting anything, for deployment
+// pipelines to run before relying on the service, failing if any check did
+pub fn run(args: &[String]) -> Result<()> {
+    let paths = ServicePaths::from_current_exe()?;
+    let mut report = Report::default();
+
+    report.check("scm", || win::check_service_control(&paths.name)
+        .map(|_| format!("may query, start and stop service {}", paths.name))
+        .chain_err(|| format!("Unable to open service {} to query, start and stop it", paths.name)));
+
+    report.check("account", || {
+        let account = win::service_account(&paths.name)
+            .chain_err(|| format!("Unable to query the account of service {}", paths.name))?;
+
+        let sid = win::account_sid(lookup_name(&account))
+            .chain_err(|| format!("Unable to resolve account {}", account))?;
+
+        Ok(format!("service {} logs on as {} ({})", paths.name, account, sid))
+    });
+
+    let loaded = profile::selected(args, &paths.name)
+        .and_then(|profile| FileConfig::load(&paths.config_file, &Variables::new(&paths).with_profile(profile)));
+
+    match loaded {
+        Ok(config) => {
+            let (service_config, cmds) = config.into_parts();
+            let warnings = lint::lint(&cmds);
+
+            for warning in &warnings {
+                println!("Warning: {}", warning);
+            }
+
+            report.pass("config", &format!("{:?} is valid with {} command(s) and {} warning(s)",
+                paths.config_file, cmds.len(), warnings.len()));
+
+            for (what, dir) in output_dirs(&paths, &service_config) {
+                report.check(&what, || check_writable(&dir));
+            }
+
+            for cmd in &cmds {
+                check_command(&mut report, cmd);
+            }
+        },
+
+        Err(e) => report.fail("config", &e),
+    }
+
+    println!("{} passed, {} failed", report.passed, report.failed);
+
+    if report.failed > 0 {
+        bail!("Self-test failed {} of {} checks", report.failed, report.passed + report.failed);
+    }
+
+    Ok(())
+}
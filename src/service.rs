@@ -0,0 +1,275 @@
+use errors::*;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::shared::winerror::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR};
+use winapi::um::winnt::{LPWSTR, SERVICE_WIN32_OWN_PROCESS};
+use winapi::um::winsvc::{RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+    SERVICE_ACCEPT_PARAMCHANGE, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE,
+    SERVICE_CONTROL_PARAMCHANGE, SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STATUS_HANDLE,
+    SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_STOP_REASON_FLAG_PLANNED, SERVICE_STOP_REASON_FLAG_UNPLANNED,
+    SERVICE_STOP_REASON_MAJOR_APPLICATION, SERVICE_STOP_REASON_MAJOR_NONE, SERVICE_STOP_REASON_MAJOR_OPERATINGSYSTEM,
+    SERVICE_STOP_REASON_MINOR_NONE, SERVICE_STOP_REASON_MINOR_OTHER, SERVICE_STOP_REASON_MINOR_UNSTABLE, SERVICE_TABLE_ENTRYW};
+use win::to_wide;
+
+// user defined control codes, sent with `sc control <service> <code>`
+pub const CONTROL_MAINTENANCE: DWORD = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceControl {
+    Stop,
+    Shutdown,
+    ParamChange,
+    Custom(DWORD),
+}
+
+// why the service stops, the scm only takes a stop reason from the client stopping a service with
+// ControlServiceExW, so a service ending on its own can only log it next to its exit code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    // a stop control, e.g. `sc stop`
+    Requested,
+    Shutdown,
+
+    // every command ended and none is to be restarted
+    Completed,
+
+    // e.g. an invalid config
+    Failure,
+    Panic,
+}
+
+impl StopReason {
+    // as a SERVICE_STOP_REASON code
+    pub fn code(&self) -> DWORD {
+        match *self {
+            StopReason::Requested => SERVICE_STOP_REASON_FLAG_PLANNED | SERVICE_STOP_REASON_MAJOR_NONE | SERVICE_STOP_REASON_MINOR_NONE,
+            StopReason::Shutdown => SERVICE_STOP_REASON_FLAG_PLANNED | SERVICE_STOP_REASON_MAJOR_OPERATINGSYSTEM
+                | SERVICE_STOP_REASON_MINOR_NONE,
+            StopReason::Completed => SERVICE_STOP_REASON_FLAG_PLANNED | SERVICE_STOP_REASON_MAJOR_APPLICATION
+                | SERVICE_STOP_REASON_MINOR_NONE,
+            StopReason::Failure => SERVICE_STOP_REASON_FLAG_UNPLANNED | SERVICE_STOP_REASON_MAJOR_APPLICATION
+                | SERVICE_STOP_REASON_MINOR_OTHER,
+            StopReason::Panic => SERVICE_STOP_REASON_FLAG_UNPLANNED | SERVICE_STOP_REASON_MAJOR_APPLICATION
+                | SERVICE_STOP_REASON_MINOR_UNSTABLE,
+        }
+    }
+
+    pub fn is_planned(&self) -> bool {
+        self.code() & SERVICE_STOP_REASON_FLAG_PLANNED != 0
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{} {:?} ({:#010x})", if self.is_planned() { "planned" } else { "unplanned" }, self, self.code())
+    }
+}
+
+pub type ServiceMain = fn(Vec<String>, Receiver<ServiceControl>) -> u32;
+
+// in ms, how long until the next status report
+const DEFAULT_WAIT_HINT: DWORD = 10000;
+
+// the dispatcher gives no way to pass context into the ServiceMain callback
+static mut SERVICE_MAIN: Option<ServiceMain> = None;
+
+struct StatusHandle {
+    handle: SERVICE_STATUS_HANDLE,
+
+    // increased with every report of a pending state, so that the scm sees the progress
+    checkpoint: DWORD,
+
+    // the last report, repeated on interrogation
+    state: DWORD,
+    exit_code: u32,
+    wait_hint: DWORD,
+}
+
+// the handle may be used from any thread
+unsafe impl Send for StatusHandle {}
+
+// kept for reporting the service as stopped from a panic on any thread
+static STATUS: Mutex<Option<StatusHandle>> = Mutex::new(None);
+
+type HealthCheck = Box<dyn Fn() + Send>;
+
+// evaluates the health of the commands, the scm knows no degraded state and an exit code only means anything
+// once stopped, so the health is reported through the status, the heartbeat and the event log instead
+static HEALTH_CHECK: Mutex<Option<HealthCheck>> = Mutex::new(None);
+
+impl StatusHandle {
+    fn set(&mut self, state: DWORD, exit_code: u32) {
+        self.report(state, exit_code, DEFAULT_WAIT_HINT);
+    }
+
+    fn report(&mut self, state: DWORD, exit_code: u32, wait_hint: DWORD) {
+        self.checkpoint = match state {
+            SERVICE_START_PENDING | SERVICE_STOP_PENDING => self.checkpoint + 1,
+            _ => 0,
+        };
+
+        self.state = state;
+        self.exit_code = exit_code;
+        self.wait_hint = wait_hint;
+        self.resubmit();
+    }
+
+    // repeats the last report as is, a pending state must not seem to progress just because it was asked about
+    fn resubmit(&mut self) {
+        let mut status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: self.state,
+            dwControlsAccepted: match self.state {
+                SERVICE_START_PENDING | SERVICE_STOP_PENDING | SERVICE_STOPPED => 0,
+                _ => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN | SERVICE_ACCEPT_PARAMCHANGE,
+            },
+            dwWin32ExitCode: if self.exit_code == 0 { NO_ERROR } else { ERROR_SERVICE_SPECIFIC_ERROR },
+            dwServiceSpecificExitCode: self.exit_code,
+            dwCheckPoint: self.checkpoint,
+            dwWaitHint: self.wait_hint,
+        };
+
+        unsafe {
+            SetServiceStatus(self.handle, &mut status);
+        }
+    }
+}
+
+fn control_name(control: DWORD) -> &'static str {
+    match control {
+        SERVICE_CONTROL_STOP => "stop",
+        SERVICE_CONTROL_SHUTDOWN => "shutdown",
+        SERVICE_CONTROL_INTERROGATE => "interrogate",
+        SERVICE_CONTROL_PARAMCHANGE => "paramchange",
+        CONTROL_MAINTENANCE => "maintenance",
+        128..=255 => "custom",
+        _ => "unsupported",
+    }
+}
+
+unsafe extern "system" fn control_handler(control: DWORD, _: DWORD, _: LPVOID, context: LPVOID) -> DWORD {
+    let tx = &*(context as *const Mutex<Sender<ServiceControl>>);
+
+    // the log lines carry the timestamp of when each control arrived
+    info!("Received service control {} ({})", control_name(control), control);
+
+    let service_control = match control {
+        SERVICE_CONTROL_STOP => ServiceControl::Stop,
+        SERVICE_CONTROL_SHUTDOWN => ServiceControl::Shutdown,
+        SERVICE_CONTROL_PARAMCHANGE => ServiceControl::ParamChange,
+        SERVICE_CONTROL_INTERROGATE => {
+            check_health();
+            report_current();
+            return NO_ERROR;
+        },
+        128..=255 => ServiceControl::Custom(control),
+        _ => return ERROR_CALL_NOT_IMPLEMENTED,
+    };
+
+    let _ = tx.lock().unwrap().send(service_control);
+    NO_ERROR
+}
+
+unsafe extern "system" fn ffi_service_main(argc: DWORD, argv: *mut LPWSTR) {
+    let args = slice::from_raw_parts(argv, argc as usize).iter()
+        .map(|&arg| {
+            let len = (0..).take_while(|&i| *arg.offset(i) != 0).count();
+            String::from_utf16_lossy(slice::from_raw_parts(arg, len))
+        })
+        .collect::<Vec<_>>();
+
+    let (tx, rx) = mpsc::channel();
+
+    // the context lives for as long as the process does
+    let context = Box::into_raw(Box::new(Mutex::new(tx)));
+
+    // own process services may register under any name
+    let name = to_wide(args.first().map(|name| name.as_str()).unwrap_or(""));
+    let handle = RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), context as LPVOID);
+
+    if handle.is_null() {
+        return;
+    }
+
+    let mut status = StatusHandle { handle: handle, checkpoint: 0, state: SERVICE_START_PENDING, exit_code: 0,
+        wait_hint: DEFAULT_WAIT_HINT };
+    status.set(SERVICE_START_PENDING, 0);
+    status.set(SERVICE_RUNNING, 0);
+    *STATUS.lock().unwrap() = Some(status);
+
+    let exit_code = match SERVICE_MAIN {
+        Some(service_main) => service_main(args, rx),
+        None => 1,
+    };
+
+    if let Some(mut status) = STATUS.lock().unwrap().take() {
+        status.set(SERVICE_STOP_PENDING, 0);
+        status.set(SERVICE_STOPPED, exit_code);
+    }
+}
+
+// for ending the process abruptly, the scm then shows the exit code instead of a vanished service,
+// does nothing outside of a service
+pub fn report_stopped(exit_code: u32) {
+    if let Ok(mut status) = STATUS.try_lock() {
+        if let Some(mut status) = status.take() {
+            status.set(SERVICE_STOPPED, exit_code);
+        }
+    }
+}
+
+// reported as soon as a stop control arrives and then periodically until stopped, so that the scm keeps waiting
+// for as long as the commands take to stop instead of considering the service hung, the wait hint is how long
+// until the next report, does nothing outside of a service or once stopped
+pub fn report_stop_pending(wait_hint: Duration) {
+    if let Some(ref mut status) = *STATUS.lock().unwrap() {
+        status.report(SERVICE_STOP_PENDING, 0, wait_hint.as_millis() as DWORD);
+    }
+}
+
+// answers an interrogation with the current state, the scm knows nothing between running and stopped,
+// so a degraded health only shows in the log, the heartbeat and the event log
+fn report_current() {
+    if let Some(ref mut status) = *STATUS.lock().unwrap() {
+        status.resubmit();
+    }
+}
+
+pub fn set_health_check<F: Fn() + Send + 'static>(check: F) {
+    *HEALTH_CHECK.lock().unwrap() = Some(Box::new(check));
+}
+
+// on interrogation, so that a change of health is logged right away rather than on the next periodic check
+fn check_health() {
+    if let Some(ref check) = *HEALTH_CHECK.lock().unwrap() {
+        check();
+    }
+}
+
+// blocks until the service is stopped
+pub fn dispatch(service_main: ServiceMain) -> Result<()> {
+    unsafe {
+        SERVICE_MAIN = Some(service_main);
+    }
+
+    let name = to_wide("");
+
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: name.as_ptr() as LPWSTR,
+            lpServiceProc: Some(ffi_service_main),
+        },
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: ptr::null_mut(),
+            lpServiceProc: None,
+        },
+    ];
+
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+        bail!("Unable to start service control dispatcher: {}", ::std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
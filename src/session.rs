@@ -0,0 +1,361 @@
+use config::{CommandConfig, IntegrityLevel};
+use command;
+use os_pipe::{PipeReader, PipeWriter};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io;
+use std::mem;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::AsRawHandle;
+use std::os::windows::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::ptr;
+use std::slice;
+use win::{self, to_wide};
+use winapi::shared::minwindef::{DWORD, FALSE, LPVOID, TRUE};
+use winapi::shared::ntdef::LUID;
+use winapi::shared::sddl::ConvertStringSidToSidW;
+use winapi::um::handleapi::{CloseHandle, SetHandleInformation};
+use winapi::um::minwinbase::STILL_ACTIVE;
+use winapi::um::processthreadsapi::{CreateProcessAsUserW, GetCurrentProcess, GetExitCodeProcess, OpenProcessToken,
+    TerminateProcess, PROCESS_INFORMATION, STARTUPINFOW};
+use winapi::um::securitybaseapi::{CreateRestrictedToken, GetLengthSid, GetTokenInformation, SetTokenInformation};
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::{LocalFree, LookupPrivilegeNameW, WTSGetActiveConsoleSessionId, CREATE_UNICODE_ENVIRONMENT,
+    HANDLE_FLAG_INHERIT, INFINITE, STARTF_USESTDHANDLES, WAIT_FAILED};
+use winapi::um::winnt::{TokenIntegrityLevel, TokenPrivileges, HANDLE, LUID_AND_ATTRIBUTES, PSID, SE_GROUP_INTEGRITY,
+    TOKEN_ADJUST_DEFAULT, TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE, TOKEN_MANDATORY_LABEL, TOKEN_PRIVILEGES, TOKEN_QUERY};
+use winapi::um::wtsapi32::WTSQueryUserToken;
+
+// no active console session
+const NO_SESSION: DWORD = 0xFFFF_FFFF;
+
+// a process started in another session or with a restricted token, which std::process is unable to spawn
+pub struct SessionChild {
+    handle: HANDLE,
+    pid: u32,
+}
+
+// the process handle may be waited on and terminated from any thread at the same time
+unsafe impl Send for SessionChild {}
+unsafe impl Sync for SessionChild {}
+
+impl SessionChild {
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn wait(&self) -> io::Result<ExitStatus> {
+        if unsafe { WaitForSingleObject(self.handle, INFINITE) } == WAIT_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.exit_code().map(|code| ExitStatus::from_raw(code.unwrap_or(STILL_ACTIVE)))
+    }
+
+    pub fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        self.exit_code().map(|code| code.map(ExitStatus::from_raw))
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        if unsafe { TerminateProcess(self.handle, 1) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn exit_code(&self) -> io::Result<Option<u32>> {
+        let mut code: DWORD = 0;
+
+        if unsafe { GetExitCodeProcess(self.handle, &mut code) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(if code == STILL_ACTIVE { None } else { Some(code) })
+    }
+}
+
+impl Drop for SessionChild {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+struct Token(HANDLE);
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+fn make_inheritable<H: AsRawHandle>(pipe: &H) -> io::Result<()> {
+    if unsafe { SetHandleInformation(pipe.as_raw_handle() as HANDLE, HANDLE_FLAG_INHERIT, HANDLE_FLAG_INHERIT) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// "name=value" entries, each null terminated, with an empty entry at the end
+fn environment_block(vars: &BTreeMap<OsString, OsString>) -> Vec<u16> {
+    let mut block = Vec::new();
+
+    for (key, value) in vars {
+        block.extend(key.encode_wide());
+        block.push('=' as u16);
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+
+    block.push(0);
+    block
+}
+
+// whether the child gets a restricted copy of the token instead of the one of the service or console user
+pub fn restricts_token(config: &CommandConfig) -> bool {
+    config.integrity_level.is_some() || config.privileges.is_some() || !config.remove_privileges.is_empty()
+}
+
+fn own_token() -> io::Result<Token> {
+    unsafe {
+        let mut token: HANDLE = ptr::null_mut();
+
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_ASSIGN_PRIMARY | TOKEN_DUPLICATE | TOKEN_QUERY | TOKEN_ADJUST_DEFAULT,
+            &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Token(token))
+    }
+}
+
+fn privilege_name(luid: &LUID) -> io::Result<String> {
+    let mut luid = *luid;
+    let mut buf = [0u16; 256];
+    let mut len = buf.len() as DWORD;
+
+    if unsafe { LookupPrivilegeNameW(ptr::null(), &mut luid, buf.as_mut_ptr(), &mut len) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(String::from_utf16_lossy(&buf[..len as usize]))
+}
+
+// the privileges the token has that the command does not keep
+fn privileges_to_delete(token: &Token, config: &CommandConfig) -> io::Result<Vec<LUID_AND_ATTRIBUTES>> {
+    let mut needed: DWORD = 0;
+
+    unsafe {
+        GetTokenInformation(token.0, TokenPrivileges, ptr::null_mut(), 0, &mut needed);
+    }
+
+    // u64 for the alignment of the struct at the start of the buffer
+    let mut buf = vec![0u64; (needed as usize).div_ceil(8)];
+
+    if unsafe { GetTokenInformation(token.0, TokenPrivileges, buf.as_mut_ptr() as LPVOID, (buf.len() * 8) as DWORD,
+        &mut needed) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let privileges = unsafe {
+        let header = &*(buf.as_ptr() as *const TOKEN_PRIVILEGES);
+        slice::from_raw_parts(header.Privileges.as_ptr(), header.PrivilegeCount as usize)
+    };
+
+    let mut deleted = Vec::new();
+
+    for privilege in privileges {
+        let name = privilege_name(&privilege.Luid)?;
+        let kept = config.privileges.as_ref()
+            .map(|kept| kept.iter().any(|kept| kept.eq_ignore_ascii_case(&name)))
+            .unwrap_or(true);
+
+        if !kept || config.remove_privileges.iter().any(|removed| removed.eq_ignore_ascii_case(&name)) {
+            deleted.push(LUID_AND_ATTRIBUTES { Luid: privilege.Luid, Attributes: 0 });
+        }
+    }
+
+    Ok(deleted)
+}
+
+fn set_integrity_level(token: &Token, level: IntegrityLevel) -> io::Result<()> {
+    unsafe {
+        let mut sid: PSID = ptr::null_mut();
+
+        if ConvertStringSidToSidW(to_wide(level.sid()).as_ptr(), &mut sid) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut label: TOKEN_MANDATORY_LABEL = mem::zeroed();
+        label.Label.Sid = sid;
+        label.Label.Attributes = SE_GROUP_INTEGRITY;
+
+        let res = SetTokenInformation(token.0, TokenIntegrityLevel, &mut label as *mut _ as LPVOID,
+            mem::size_of::<TOKEN_MANDATORY_LABEL>() as DWORD + GetLengthSid(sid));
+
+        LocalFree(sid as LPVOID);
+
+        if res == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// a primary token like the given one, without the privileges the command drops and at its integrity level
+fn restrict(token: &Token, config: &CommandConfig) -> io::Result<Token> {
+    let mut deleted = privileges_to_delete(token, config)?;
+
+    let restricted = unsafe {
+        let mut restricted: HANDLE = ptr::null_mut();
+
+        if CreateRestrictedToken(token.0, 0, 0, ptr::null_mut(), deleted.len() as DWORD, deleted.as_mut_ptr(), 0,
+            ptr::null_mut(), &mut restricted) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Token(restricted)
+    };
+
+    if let Some(level) = config.integrity_level {
+        set_integrity_level(&restricted, level)?;
+    }
+
+    Ok(restricted)
+}
+
+// the base environment with the variables of the command, names are case insensitive,
+// so that an override of "PATH" replaces "Path"
+fn merge_environment(base: Vec<(OsString, OsString)>, env: &BTreeMap<String, String>) -> BTreeMap<OsString, OsString> {
+    let mut vars = base.into_iter().collect::<BTreeMap<_, _>>();
+
+    for (key, value) in env {
+        vars.retain(|existing, _| !existing.to_string_lossy().eq_ignore_ascii_case(key));
+        vars.insert(OsString::from(key), OsString::from(value));
+    }
+
+    vars
+}
+
+// what CreateProcessAsUserW gets for the child, which std::process is unable to pass
+struct Spawn<'a> {
+    token: &'a Token,
+    desktop: Option<&'a str>,
+    vars: BTreeMap<OsString, OsString>,
+    stdin: Option<&'a PipeReader>,
+    stdout: &'a PipeWriter,
+    stderr: &'a PipeWriter,
+}
+
+fn spawn_as(config: &CommandConfig, shell: bool, cwd: Option<&str>, spawn: Spawn) -> io::Result<SessionChild> {
+    let mut env_block = environment_block(&spawn.vars);
+
+    let (program, args) = command::command_line(config, shell);
+    let mut cmdline = to_wide(format!("{} {}", command::quote_arg(&program), args));
+    let mut desktop = spawn.desktop.map(to_wide);
+    let cwd = cwd.map(to_wide);
+
+    make_inheritable(spawn.stdout)?;
+    make_inheritable(spawn.stderr)?;
+
+    if let Some(stdin) = spawn.stdin {
+        make_inheritable(stdin)?;
+    }
+
+    let mut startup_info: STARTUPINFOW = unsafe { mem::zeroed() };
+    startup_info.cb = mem::size_of::<STARTUPINFOW>() as DWORD;
+    startup_info.lpDesktop = desktop.as_mut().map(|desktop| desktop.as_mut_ptr()).unwrap_or(ptr::null_mut());
+    startup_info.dwFlags = STARTF_USESTDHANDLES;
+    startup_info.hStdInput = spawn.stdin.map(|stdin| stdin.as_raw_handle() as HANDLE).unwrap_or(ptr::null_mut());
+    startup_info.hStdOutput = spawn.stdout.as_raw_handle() as HANDLE;
+    startup_info.hStdError = spawn.stderr.as_raw_handle() as HANDLE;
+
+    let mut process_info: PROCESS_INFORMATION = unsafe { mem::zeroed() };
+
+    let created = unsafe {
+        CreateProcessAsUserW(
+            spawn.token.0,
+            ptr::null(),
+            cmdline.as_mut_ptr(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            TRUE,
+            command::creation_flags(config) | CREATE_UNICODE_ENVIRONMENT,
+            env_block.as_mut_ptr() as LPVOID,
+            cwd.as_ref().map(|cwd| cwd.as_ptr()).unwrap_or(ptr::null()),
+            &mut startup_info,
+            &mut process_info)
+    };
+
+    if created == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+
+    unsafe {
+        CloseHandle(process_info.hThread);
+    }
+
+    Ok(SessionChild {
+        handle: process_info.hProcess,
+        pid: process_info.dwProcessId,
+    })
+}
+
+// starts the command as the user logged on to the physical console, on its interactive desktop,
+// which requires the service to run as LocalSystem
+pub fn spawn(config: &CommandConfig, shell: bool, cwd: Option<&str>, env: &BTreeMap<String, String>, stdout: &PipeWriter,
+    stderr: &PipeWriter) -> io::Result<SessionChild> {
+    let session_id = unsafe { WTSGetActiveConsoleSessionId() };
+
+    if session_id == NO_SESSION {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No active console session"));
+    }
+
+    let token = unsafe {
+        let mut token: HANDLE = ptr::null_mut();
+
+        if WTSQueryUserToken(session_id, &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Token(token)
+    };
+
+    let token = if restricts_token(config) { restrict(&token, config)? } else { token };
+
+    // the environment of the console user, not the one of the service account
+    let vars = merge_environment(win::environment_of(token.0)?, env);
+
+    spawn_as(config, shell, cwd, Spawn {
+        token: &token,
+        desktop: Some(&config.desktop),
+        vars: vars,
+        stdin: None,
+        stdout: stdout,
+        stderr: stderr,
+    })
+}
+
+// starts the command in the service session like std::process would, but with a restricted copy of the token
+// of the service, e.g. at medium integrity or without SeDebugPrivilege
+pub fn spawn_restricted(config: &CommandConfig, shell: bool, cwd: Option<&str>, base_env: Vec<(OsString, OsString)>,
+    env: &BTreeMap<String, String>, stdin: Option<&PipeReader>, stdout: &PipeWriter, stderr: &PipeWriter)
+    -> io::Result<SessionChild> {
+    let token = restrict(&own_token()?, config)?;
+
+    spawn_as(config, shell, cwd, Spawn {
+        token: &token,
+        desktop: None,
+        vars: merge_environment(base_env, env),
+        stdin: stdin,
+        stdout: stdout,
+        stderr: stderr,
+    })
+}
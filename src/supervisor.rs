@@ -0,0 +1,2141 @@
+use alerts::CrashAlerts;
+use audit::{self, Audit};
+use authenticode;
+use child::Child;
+use chrono::{self, DateTime, Local};
+use command;
+use config::{CommandConfig, EffectiveConfig, FileConfig, OnServiceStop, ServiceConfig, Session, StartMode};
+use counters::{Counters, Saved};
+use errors::*;
+use eventlog::{self, Lifecycle};
+use exitcode;
+use history::{Event, EventKind, History};
+use humantime;
+use lint;
+use logging;
+use network;
+use os_pipe::{self, IntoStdio, PipeWriter};
+use output::{self, OutputFilter, OutputSeen, Stream};
+use ratelimit::TokenBucket;
+use redact::Redactor;
+use schedule::ClockWatch;
+use session;
+use shared_child::SharedChild;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use vars::Variables;
+use version::{self, BuildInfo};
+use watch::{self, FileWatcher};
+use win;
+
+const MAX_MAINTENANCE_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+const PRECONDITION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// how often a child past its hang timeout is checked again while maintenance holds the kill back
+const HANG_CHECK_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+// how often the wall clock is checked for changes while a restart is scheduled
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// how often piped output that is held back checks whether its consumer is back
+const PIPE_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// how often a running reload_cmd is checked for its exit
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// how long the output of an exited child may take to be read to its end, a grandchild may still hold it open
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChildState {
+    Starting,
+    Running,
+    Backoff,
+    Stopped,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CommandStatus {
+    pub name: String,
+    pub cmd: String,
+    pub state: ChildState,
+    pub pid: Option<u32>,
+
+    // kept across service restarts
+    pub last_exit_code: Option<i32>,
+    pub last_crash_at: Option<String>,
+    pub restarts: u64,
+    pub restarts_last_hour: usize,
+
+    // the current run, if any
+    pub started_at: Option<String>,
+    pub uptime_secs: Option<u64>,
+
+    // across all runs since the command was configured, for simple slo reporting
+    pub total_uptime_secs: u64,
+    pub uptime_percent: f64,
+}
+
+// the scm only knows running or stopped
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Healthy,
+
+    // some commands wait out a backoff after failing
+    Degraded,
+
+    // all of them, none is left running
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ServiceStatus {
+    pub supervisor: BuildInfo,
+    pub health: Health,
+    pub draining: bool,
+    pub maintenance_until: Option<String>,
+    pub commands: Vec<CommandStatus>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Maintenance {
+    Toggle,
+    Enter(Duration),
+    Leave,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+enum SlotMsg {
+    Stop,
+    Exited(io::Result<ExitStatus>),
+
+    // graceful restart requested from outside the supervising thread, with the reason
+    Restart(String),
+
+    // start of a manual command
+    Start,
+}
+
+// time spent running since the command was configured, i.e. since the service start or the reload adding it
+struct Uptime {
+    tracked_since: Instant,
+    running_since: Option<(Instant, DateTime<Local>)>,
+    total: Duration,
+}
+
+fn uptime_percent(uptime: &Uptime) -> f64 {
+    let current = uptime.running_since.map(|(since, _)| since.elapsed()).unwrap_or_default();
+    let tracked = uptime.tracked_since.elapsed().as_secs_f64();
+
+    if tracked > 0.0 {
+        100.0 * (uptime.total + current).as_secs_f64() / tracked
+    } else {
+        0.0
+    }
+}
+
+// supervision state of a single configured command
+struct Slot {
+    config: CommandConfig,
+    filter: Arc<OutputFilter>,
+    status: Mutex<CommandStatus>,
+    uptime: Mutex<Uptime>,
+    output: Arc<Mutex<OutputSeen>>,
+
+    // threads reading the output of the current child
+    readers: Mutex<Vec<JoinHandle<()>>>,
+
+    // the stdin of the running child, while another command pipes its output into it
+    stdin: Mutex<Option<PipeWriter>>,
+
+    tx: Mutex<Sender<SlotMsg>>,
+    rx: Mutex<Option<Receiver<SlotMsg>>>,
+
+    // a manual command that ended goes back to waiting for a start unless this is set
+    stop_requested: AtomicBool,
+    done: Mutex<bool>,
+    done_cv: Condvar,
+}
+
+impl Slot {
+    fn new(cmd: CommandConfig, redactor: &Redactor) -> Result<Arc<Slot>> {
+        let filter = Arc::new(OutputFilter::new(&cmd)?);
+        let (tx, rx) = mpsc::channel();
+
+        let status = CommandStatus {
+            name: cmd.name.clone(),
+            cmd: redactor.redact(&command::display(&cmd)),
+            state: ChildState::Stopped,
+            pid: None,
+            last_exit_code: None,
+            last_crash_at: None,
+            restarts: 0,
+            restarts_last_hour: 0,
+            started_at: None,
+            uptime_secs: None,
+            total_uptime_secs: 0,
+            uptime_percent: 0.0,
+        };
+
+        let uptime = Uptime {
+            tracked_since: Instant::now(),
+            running_since: None,
+            total: Duration::from_secs(0),
+        };
+
+        Ok(Arc::new(Slot {
+            config: cmd,
+            filter: filter,
+            status: Mutex::new(status),
+            uptime: Mutex::new(uptime),
+            output: Arc::new(Mutex::new(OutputSeen::new())),
+            readers: Mutex::new(Vec::new()),
+            stdin: Mutex::new(None),
+            tx: Mutex::new(tx),
+            rx: Mutex::new(Some(rx)),
+            stop_requested: AtomicBool::new(false),
+            done: Mutex::new(false),
+            done_cv: Condvar::new(),
+        }))
+    }
+
+    fn update<F: FnOnce(&mut CommandStatus)>(&self, f: F) {
+        f(&mut self.status.lock().unwrap())
+    }
+
+    fn begin_run(&self) {
+        self.uptime.lock().unwrap().running_since = Some((Instant::now(), Local::now()));
+    }
+
+    // logs how long the child ran, if it was running at all
+    fn end_run(&self) {
+        // producers hold back their output until the next child
+        *self.stdin.lock().unwrap() = None;
+
+        let mut uptime = self.uptime.lock().unwrap();
+
+        if let Some((since, started_at)) = uptime.running_since.take() {
+            let ran = since.elapsed();
+            uptime.total += ran;
+
+            info!(target: &logging::target(&self.config.name),
+                "Process [{}] ran for {} since {}, total uptime {} ({:.2}%)", self.config.name,
+                humantime::format_duration(Duration::from_secs(ran.as_secs())), started_at.to_rfc3339(),
+                humantime::format_duration(Duration::from_secs(uptime.total.as_secs())), uptime_percent(&uptime));
+        }
+    }
+
+    // the status along with the uptime as of now
+    fn snapshot(&self) -> CommandStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        let uptime = self.uptime.lock().unwrap();
+
+        let current = uptime.running_since.map(|(since, _)| since.elapsed());
+
+        status.started_at = uptime.running_since.map(|(_, started_at)| started_at.to_rfc3339());
+        status.uptime_secs = current.map(|current| current.as_secs());
+        status.total_uptime_secs = (uptime.total + current.unwrap_or_default()).as_secs();
+        status.uptime_percent = uptime_percent(&uptime);
+        status
+    }
+
+    // writes output piped into this command, holding it back while the child is being respawned,
+    // returns false if it was dropped since the command is stopped
+    fn write_stdin(&self, data: &[u8]) -> bool {
+        loop {
+            {
+                let mut stdin = self.stdin.lock().unwrap();
+
+                if let Some(ref mut writer) = *stdin {
+                    match writer.write_all(data) {
+                        Ok(()) => return true,
+                        Err(e) => debug!(target: &logging::target(&self.config.name),
+                            "Unable to write to the stdin of [{}]: {}", self.config.name, e),
+                    }
+                }
+
+                *stdin = None;
+            }
+
+            if self.status.lock().unwrap().state == ChildState::Stopped {
+                return false;
+            }
+
+            thread::sleep(PIPE_RETRY_INTERVAL);
+        }
+    }
+
+    // why the output says otherwise of a run that exited with 0, once it was read to its end
+    fn output_contradiction(&self) -> Option<&'static str> {
+        if !self.filter.judges_success() {
+            return None;
+        }
+
+        let deadline = Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+
+        while self.readers.lock().unwrap().iter().any(|reader| !reader.is_finished()) && Instant::now() < deadline {
+            thread::sleep(PIPE_RETRY_INTERVAL);
+        }
+
+        self.filter.contradiction(&self.output.lock().unwrap())
+    }
+
+    fn send(&self, msg: SlotMsg) {
+        if let Err(e) = self.tx.lock().unwrap().send(msg) {
+            error!(target: &logging::target(&self.config.name), "Error sending into channel of [{}]: {}",
+                self.config.name, e);
+        }
+    }
+
+    fn stop(&self) {
+        self.stop_requested.store(true, Ordering::SeqCst);
+        self.send(SlotMsg::Stop);
+    }
+
+    fn mark_done(&self) {
+        *self.done.lock().unwrap() = true;
+        self.done_cv.notify_all();
+    }
+
+    fn is_done(&self) -> bool {
+        *self.done.lock().unwrap()
+    }
+
+    // returns whether the supervising thread is done
+    fn wait_done(&self, timeout: Option<Duration>) -> bool {
+        let mut done = self.done.lock().unwrap();
+
+        match timeout {
+            Some(timeout) => {
+                if !*done {
+                    done = self.done_cv.wait_timeout(done, timeout).unwrap().0;
+                }
+            },
+            None => {
+                while !*done {
+                    done = self.done_cv.wait(done).unwrap();
+                }
+            },
+        }
+
+        *done
+    }
+}
+
+pub struct Supervisor {
+    config_path: PathBuf,
+    variables: Variables,
+    service_config: ServiceConfig,
+    redactor: Arc<Redactor>,
+    shared: Arc<Shared>,
+
+    // held for the whole of a reload, so that slots being swapped are never observed as all done
+    reload_lock: Mutex<()>,
+}
+
+// supervisor wide flags that every supervising thread consults
+struct Shared {
+    // every configured command, so that supervising threads can reach each other
+    slots: Mutex<Vec<Arc<Slot>>>,
+
+    stopping: AtomicBool,
+
+    // the os is shutting down, so children get the shorter shutdown timeout to exit
+    shutting_down: AtomicBool,
+    shutdown_timeout: Duration,
+
+    // the first phase of a stop, in which only the manual commands stop
+    jobs_stop_timeout: Duration,
+
+    // no more respawns, running children are left to finish on their own
+    draining: AtomicBool,
+
+    // a volume of the log or other output is short of space, commands with pause_on_low_disk hold off
+    low_disk: AtomicBool,
+
+    // crash alerts and health checks are held back until the deadline
+    maintenance: Mutex<Option<(Instant, DateTime<Local>)>>,
+    maintenance_duration: Duration,
+
+    history: History,
+    audit: Audit,
+    counters: Counters,
+    events: Lifecycle,
+    crash_alerts: CrashAlerts,
+
+    // as last reported
+    health: Mutex<Health>,
+
+    // restarts by restart policy across all commands
+    restarts: TokenBucket,
+
+    // restarts by restart policy and along with dependencies, by restart group
+    restart_budgets: HashMap<String, TokenBucket>,
+
+    // commands with anti affinity that are starting up
+    starting: Mutex<HashSet<String>>,
+
+    dump_dir: PathBuf,
+    dump_count: u32,
+
+    // under which each command has its own data and log directories
+    data_dir: Option<PathBuf>,
+    data_dir_acl: String,
+
+    // told to the children along with their own names
+    service_name: String,
+
+    network_check_host: Option<String>,
+    network_wait_timeout: Duration,
+
+    // no console apis and no cmd.exe, for server core and nano server containers
+    container_mode: bool,
+}
+
+// held while a command with anti affinity starts up, so that the others wait
+struct Starting<'a> {
+    shared: &'a Shared,
+    name: String,
+}
+
+impl<'a> Drop for Starting<'a> {
+    fn drop(&mut self) {
+        self.shared.starting.lock().unwrap().remove(&self.name);
+    }
+}
+
+impl Shared {
+    fn try_begin_start(&self, config: &CommandConfig) -> bool {
+        let mut starting = self.starting.lock().unwrap();
+
+        if config.anti_affinity.iter().any(|other| starting.contains(other)) {
+            return false;
+        }
+
+        starting.insert(config.name.clone());
+        true
+    }
+
+    // takes a restart from the budget of the group of the command, if it is in one
+    fn try_take_budget(&self, config: &CommandConfig) -> bool {
+        config.restart_group.as_ref()
+            .and_then(|group| self.restart_budgets.get(group))
+            .map(|budget| budget.try_take())
+            .unwrap_or(true)
+    }
+
+    // gracefully restarts the commands that asked to follow restarts of the given one,
+    // whose own dependents then follow in turn once they are ready
+    fn restart_dependents(&self, name: &str) {
+        for slot in self.slots.lock().unwrap().iter() {
+            if slot.config.restart_with_dependencies && slot.config.depends_on.iter().any(|dependency| dependency == name) {
+                if self.try_take_budget(&slot.config) {
+                    slot.send(SlotMsg::Restart(format!("restart of dependency [{}]", name)));
+                } else {
+                    warn!(target: &logging::target(&slot.config.name),
+                        "Not restarting [{}] along with [{}], the restart budget of its group {} is used up",
+                        slot.config.name, name, slot.config.restart_group.as_ref().unwrap());
+                }
+            }
+        }
+    }
+
+    fn stop_timeout(&self, config: &CommandConfig) -> Duration {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            cmp::min(config.stop_timeout, self.shutdown_timeout)
+        } else {
+            config.stop_timeout
+        }
+    }
+
+    // starts a manual command that is stopped, on request or as the next step of a chain
+    fn start_command(&self, name: &str, initiator: &str) -> Result<()> {
+        let slots = self.slots.lock().unwrap();
+
+        let slot = match slots.iter().find(|slot| slot.config.name == name) {
+            Some(slot) => slot,
+            None => bail!("Unknown command [{}]", name),
+        };
+
+        if slot.config.start != StartMode::Manual {
+            bail!("Command [{}] is started with the service, only manual commands can be started on request", name);
+        }
+
+        if self.draining.load(Ordering::SeqCst) {
+            bail!("Not starting [{}] since the service is draining", name);
+        }
+
+        if self.stopping.load(Ordering::SeqCst) {
+            bail!("Not starting [{}] since the service is stopping", name);
+        }
+
+        {
+            // taken over right away, so that a second request cannot queue up another start
+            let mut status = slot.status.lock().unwrap();
+
+            if status.state != ChildState::Stopped {
+                bail!("Command [{}] is already {:?}", name, status.state);
+            }
+
+            status.state = ChildState::Starting;
+        }
+
+        info!(target: &logging::target(name), "Starting [{}] on request of {}", name, initiator);
+        slot.send(SlotMsg::Start);
+        Ok(())
+    }
+
+    // health checks must not act on anything while this holds
+    fn in_maintenance(&self) -> bool {
+        let mut maintenance = self.maintenance.lock().unwrap();
+
+        match *maintenance {
+            Some((until, _)) if Instant::now() >= until => {
+                info!("Maintenance mode expired");
+                *maintenance = None;
+                false
+            },
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+impl Supervisor {
+    pub fn new(config_path: &Path, variables: Variables, service_config: ServiceConfig, cmds: Vec<CommandConfig>,
+        redactor: Arc<Redactor>, audit: Audit, dump_dir: PathBuf, counters: Counters,
+        events: Lifecycle) -> Result<Supervisor> {
+        // compile all the output filters upfront so that bad regexes fail the start
+        let slots = cmds.into_iter()
+            .map(|cmd| Slot::new(cmd, &redactor))
+            .collect::<Result<Vec<_>>>()?;
+
+        let maintenance_duration = service_config.maintenance_duration;
+        let history_size = service_config.history_size;
+        let shutdown_timeout = service_config.shutdown_timeout;
+        let jobs_stop_timeout = service_config.jobs_stop_timeout;
+        let network_check_host = service_config.network_check_host.clone();
+        let network_wait_timeout = service_config.network_wait_timeout;
+        let container_mode = service_config.container_mode;
+        let max_restarts_per_minute = service_config.max_restarts_per_minute;
+
+        let restart_budgets = service_config.restart_budgets.iter()
+            .map(|(group, &per_minute)| (group.clone(), TokenBucket::new(per_minute)))
+            .collect();
+        let dump_count = service_config.dump_count;
+        let data_dir = service_config.data_dir.as_ref().map(PathBuf::from);
+        let data_dir_acl = service_config.data_dir_acl.clone();
+        let alert_window = service_config.alert_window;
+        let service_name = variables.get("SERVICE_NAME").unwrap_or_default().to_owned();
+
+        Ok(Supervisor {
+            config_path: config_path.to_owned(),
+            variables: variables,
+            service_config: service_config,
+            redactor: redactor,
+            shared: Arc::new(Shared {
+                slots: Mutex::new(slots),
+                stopping: AtomicBool::new(false),
+                shutting_down: AtomicBool::new(false),
+                shutdown_timeout: shutdown_timeout,
+                jobs_stop_timeout: jobs_stop_timeout,
+                draining: AtomicBool::new(false),
+                low_disk: AtomicBool::new(false),
+                maintenance: Mutex::new(None),
+                maintenance_duration: maintenance_duration,
+                history: History::new(history_size),
+                audit: audit,
+                counters: counters,
+                events: events,
+                crash_alerts: CrashAlerts::new(alert_window),
+                health: Mutex::new(Health::Healthy),
+                restarts: TokenBucket::new(max_restarts_per_minute),
+                restart_budgets: restart_budgets,
+                starting: Mutex::new(HashSet::new()),
+                dump_dir: dump_dir,
+                dump_count: dump_count,
+                data_dir: data_dir,
+                data_dir_acl: data_dir_acl,
+                service_name: service_name,
+                network_check_host: network_check_host,
+                network_wait_timeout: network_wait_timeout,
+                container_mode: container_mode,
+            }),
+            reload_lock: Mutex::new(()),
+        })
+    }
+
+    // launches every command in its own supervising thread, the first spawns of the commands started
+    // with the service one startup_stagger after another in the order of the config
+    pub fn start(&self) {
+        let startup_stagger = self.service_config.startup_stagger;
+        let mut auto_count = 0;
+
+        for slot in self.shared.slots.lock().unwrap().iter() {
+            if slot.config.start == StartMode::Auto {
+                self.launch(slot, startup_stagger * auto_count);
+                auto_count += 1;
+            } else {
+                self.launch(slot, Duration::from_secs(0));
+            }
+        }
+    }
+
+    fn launch(&self, slot: &Arc<Slot>, start_delay: Duration) {
+        if let Some(rx) = slot.rx.lock().unwrap().take() {
+            let slot = slot.clone();
+            let redactor = self.redactor.clone();
+            let shared = self.shared.clone();
+
+            let _ = thread::spawn(move || {
+                let manual = slot.config.start == StartMode::Manual;
+                let saved = shared.counters.saved(&slot.config.name);
+
+                if let Some((pid, created)) = saved.child {
+                    kill_leftover(&slot.config.name, pid, created, &shared);
+                }
+
+                // a manual start is not held back by the backoff of the previous run of the service
+                let mut saved = if manual { None } else { Some(saved) };
+
+                loop {
+                    if manual && !wait_start(&rx) {
+                        break;
+                    }
+
+                    supervise(&slot, &rx, redactor.clone(), shared.clone(), start_delay, saved.take());
+
+                    // a manual command that ended for good may be started again
+                    if !manual || slot.stop_requested.load(Ordering::SeqCst) {
+                        break;
+                    }
+                }
+
+                slot.mark_done();
+            });
+        }
+    }
+
+    // blocks until every supervised command has stopped for good
+    pub fn wait(&self) {
+        loop {
+            let pending = self.shared.slots.lock().unwrap().iter()
+                .find(|slot| !slot.is_done())
+                .cloned();
+
+            match pending {
+                Some(slot) => {
+                    slot.wait_done(Some(Duration::from_secs(1)));
+                },
+                None => {
+                    // a reload in progress may be about to add new slots
+                    let _reload_lock = self.reload_lock.lock().unwrap();
+
+                    if self.shared.slots.lock().unwrap().iter().all(|slot| slot.is_done()) {
+                        break;
+                    }
+                },
+            }
+        }
+    }
+
+    // stops in two phases, first the manual commands, typically one-shot jobs, which may rely on the long running
+    // ones, for up to jobs_stop_timeout, then the rest, so that the order of the stop does not depend on timing
+    pub fn stop_all(&self) {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+
+        let jobs = self.shared.slots.lock().unwrap().iter()
+            .filter(|slot| slot.config.start == StartMode::Manual)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for slot in &jobs {
+            slot.stop();
+        }
+
+        let timeout = if self.shared.shutting_down.load(Ordering::SeqCst) {
+            cmp::min(self.shared.jobs_stop_timeout, self.shared.shutdown_timeout)
+        } else {
+            self.shared.jobs_stop_timeout
+        };
+
+        let deadline = Instant::now() + timeout;
+
+        for slot in &jobs {
+            while !slot.is_done() && Instant::now() < deadline {
+                slot.wait_done(Some(deadline.saturating_duration_since(Instant::now())));
+            }
+
+            if !slot.is_done() {
+                warn!(target: &logging::target(&slot.config.name),
+                    "Manual command [{}] did not stop within {:?}, stopping the other commands regardless",
+                    slot.config.name, timeout);
+                break;
+            }
+        }
+
+        for slot in self.shared.slots.lock().unwrap().iter().filter(|slot| slot.config.start != StartMode::Manual) {
+            slot.stop();
+        }
+    }
+
+    // like stop_all, but within the tight time budget the os gives services on shutdown
+    pub fn shutdown(&self) {
+        self.shared.shutting_down.store(true, Ordering::SeqCst);
+        info!("System is shutting down, giving commands at most {:?} to exit", self.shared.shutdown_timeout);
+        self.stop_all();
+    }
+
+    pub fn stop_budget(&self) -> Duration {
+        self.service_config.stop_budget
+    }
+
+    // kills the children of the commands that did not stop within the stop budget, returning their names,
+    // a supervising thread stuck elsewhere, e.g. in a hook, is left behind as the process is about to exit
+    pub fn force_stop(&self) -> Vec<String> {
+        let pending = self.shared.slots.lock().unwrap().iter()
+            .filter(|slot| !slot.is_done())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for slot in &pending {
+            let name = &slot.config.name;
+
+            let pid = match slot.status.lock().unwrap().pid {
+                Some(pid) => pid,
+                None => {
+                    warn!(target: &logging::target(name), "Command [{}] did not stop within the stop budget", name);
+                    continue;
+                },
+            };
+
+            match win::process_created(pid).and_then(|created| win::terminate_if_created(pid, created, 1)) {
+                Ok(_) => {
+                    warn!(target: &logging::target(name),
+                        "Command [{}] did not stop within the stop budget, killed pid={}", name, pid);
+                    self.shared.audit.record("kill", audit::SUPERVISOR, Some(name), &format!("pid={} stop budget", pid));
+                },
+                Err(e) => error!(target: &logging::target(name),
+                    "Command [{}] did not stop within the stop budget, unable to kill pid={}: {}", name, pid, e),
+            }
+        }
+
+        pending.iter().map(|slot| slot.config.name.clone()).collect()
+    }
+
+    // re-reads the config and only restarts the commands whose settings changed
+    pub fn reload(&self, initiator: &str) -> Result<ReloadSummary> {
+        let _reload_lock = self.reload_lock.lock().unwrap();
+
+        if self.shared.stopping.load(Ordering::SeqCst) {
+            bail!("Unable to reload while the service is stopping");
+        }
+
+        if self.shared.draining.load(Ordering::SeqCst) {
+            bail!("Unable to reload while the service is draining");
+        }
+
+        info!("Reloading config from {:?}", self.config_path);
+
+        let (service_config, cmds) = FileConfig::load(&self.config_path, &self.variables)?.into_parts();
+
+        for warning in lint::lint(&cmds) {
+            warn!("Config warning: {}", warning);
+        }
+
+        if service_config != self.service_config {
+            warn!("Changes to the [service] settings only take effect after a service restart");
+        }
+
+        let mut names = HashSet::new();
+
+        for cmd in &cmds {
+            if !names.insert(cmd.name.clone()) {
+                bail!("Duplicate command name [{}], unable to match commands for reload", cmd.name);
+            }
+        }
+
+        let old_slots = self.shared.slots.lock().unwrap().clone();
+        let mut summary = ReloadSummary::default();
+
+        let retired = old_slots.iter()
+            .filter(|slot| !cmds.iter().any(|cmd| *cmd == slot.config))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // new slots are created before anything is stopped so that a bad config changes nothing
+        let new_slots = cmds.into_iter()
+            .map(|cmd| {
+                match old_slots.iter().find(|slot| slot.config == cmd) {
+                    Some(slot) => {
+                        summary.unchanged.push(cmd.name.clone());
+                        Ok((slot.clone(), false))
+                    },
+                    None => {
+                        if old_slots.iter().any(|slot| slot.config.name == cmd.name) {
+                            summary.changed.push(cmd.name.clone());
+                        } else {
+                            summary.added.push(cmd.name.clone());
+                        }
+
+                        Ok((Slot::new(cmd, &self.redactor)?, true))
+                    },
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for slot in &retired {
+            if !summary.changed.contains(&slot.config.name) {
+                summary.removed.push(slot.config.name.clone());
+            }
+
+            info!(target: &logging::target(&slot.config.name), "Stopping [{}] for reload", slot.config.name);
+            slot.stop();
+        }
+
+        for slot in &retired {
+            slot.wait_done(None);
+        }
+
+        *self.shared.slots.lock().unwrap() = new_slots.iter().map(|&(ref slot, _)| slot.clone()).collect();
+
+        for &(ref slot, _) in new_slots.iter().filter(|&&(_, is_new)| is_new) {
+            self.launch(slot, Duration::from_secs(0));
+        }
+
+        info!("Reloaded config, added: {:?}, removed: {:?}, changed: {:?}",
+            summary.added, summary.removed, summary.changed);
+
+        self.shared.audit.record("reload", initiator, None, &format!("added: {:?}, removed: {:?}, changed: {:?}",
+            summary.added, summary.removed, summary.changed));
+
+        Ok(summary)
+    }
+
+    pub fn drain(&self) {
+        if !self.shared.draining.swap(true, Ordering::SeqCst) {
+            info!("Draining, no more commands will be respawned");
+        }
+    }
+
+    pub fn maintenance(&self, maintenance: Maintenance) {
+        let duration = {
+            let active = self.shared.in_maintenance();
+            let mut current = self.shared.maintenance.lock().unwrap();
+
+            let duration = match maintenance {
+                Maintenance::Toggle if active => None,
+                Maintenance::Toggle => Some(self.shared.maintenance_duration),
+                Maintenance::Enter(duration) => Some(duration),
+                Maintenance::Leave => None,
+            };
+
+            // capped so that the deadline arithmetic cannot overflow
+            let duration = duration.map(|duration| cmp::min(duration, MAX_MAINTENANCE_DURATION));
+
+            *current = duration.map(|duration| {
+                let wall_duration = chrono::Duration::from_std(duration)
+                    .expect("Maintenance duration out of range");
+
+                (Instant::now() + duration, Local::now() + wall_duration)
+            });
+
+            duration
+        };
+
+        match duration {
+            Some(duration) => info!("Entered maintenance mode for {:?}, crash alerts are suppressed", duration),
+            None => info!("Left maintenance mode"),
+        }
+    }
+
+    // gracefully restarts a single command right away, bypassing its restart policy and backoff
+    pub fn restart(&self, name: &str, initiator: &str) -> Result<()> {
+        let slots = self.shared.slots.lock().unwrap();
+
+        let slot = match slots.iter().find(|slot| slot.config.name == name) {
+            Some(slot) => slot,
+            None => bail!("Unknown command [{}]", name),
+        };
+
+        match slot.status.lock().unwrap().state {
+            ChildState::Running | ChildState::Backoff => (),
+            state => bail!("Command [{}] is {:?}, only running commands or commands in backoff can be restarted", name, state),
+        }
+
+        info!(target: &logging::target(name), "Restarting [{}] on request of {}", name, initiator);
+        slot.send(SlotMsg::Restart(format!("requested by {}", initiator)));
+        Ok(())
+    }
+
+    // has the running child reload itself through its reload_cmd, instead of a stop and start
+    pub fn reload_command(&self, name: &str, initiator: &str) -> Result<()> {
+        let slot = match self.shared.slots.lock().unwrap().iter().find(|slot| slot.config.name == name) {
+            Some(slot) => slot.clone(),
+            None => bail!("Unknown command [{}]", name),
+        };
+
+        let reload_cmd = match slot.config.reload_cmd {
+            Some(ref reload_cmd) => reload_cmd,
+            None => bail!("Command [{}] has no reload_cmd, it can only be restarted", name),
+        };
+
+        let pid = {
+            let status = slot.status.lock().unwrap();
+
+            match (status.state, status.pid) {
+                (ChildState::Running, Some(pid)) => pid,
+                (state, _) => bail!("Command [{}] is {:?}, only running commands can be reloaded", name, state),
+            }
+        };
+
+        info!(target: &logging::target(name), "Reloading [{}] pid={} on request of {}", name, pid, initiator);
+        self.shared.history.record(EventKind::Control, Some(name), format!("reload requested by {}", initiator));
+        self.shared.audit.record("reload", initiator, Some(name), &format!("pid={}", pid));
+
+        let exit_status = run_reload(&slot, reload_cmd, pid, &self.shared)
+            .chain_err(|| format!("Unable to reload [{}]", name))?;
+
+        if !exit_status.success() {
+            bail!("reload_cmd of [{}] failed with {}", name, exitcode::describe(&exit_status));
+        }
+
+        info!(target: &logging::target(name), "Reloaded [{}]", name);
+        Ok(())
+    }
+
+    // pauses the commands with pause_on_low_disk until space is freed, gracefully stopping the running ones
+    pub fn set_low_disk(&self, low: bool) {
+        if self.shared.low_disk.swap(low, Ordering::SeqCst) == low {
+            return;
+        }
+
+        let slots = self.shared.slots.lock().unwrap();
+
+        for slot in slots.iter().filter(|slot| slot.config.pause_on_low_disk) {
+            if low && slot.status.lock().unwrap().state == ChildState::Running {
+                info!(target: &logging::target(&slot.config.name), "Pausing [{}] until disk space is freed",
+                    slot.config.name);
+                slot.send(SlotMsg::Restart("low disk space".to_owned()));
+            }
+        }
+    }
+
+    pub fn start_command(&self, name: &str, initiator: &str) -> Result<()> {
+        self.shared.start_command(name, initiator)
+    }
+
+    // gracefully stops a manual command, which then waits for its next start
+    pub fn stop_command(&self, name: &str, initiator: &str) -> Result<()> {
+        let slots = self.shared.slots.lock().unwrap();
+
+        let slot = match slots.iter().find(|slot| slot.config.name == name) {
+            Some(slot) => slot,
+            None => bail!("Unknown command [{}]", name),
+        };
+
+        if slot.config.start != StartMode::Manual {
+            bail!("Command [{}] is started with the service, only manual commands can be stopped on their own", name);
+        }
+
+        if slot.status.lock().unwrap().state == ChildState::Stopped {
+            bail!("Command [{}] is already stopped", name);
+        }
+
+        info!(target: &logging::target(name), "Stopping [{}] on request of {}", name, initiator);
+
+        // unlike a stop of the service, the supervising thread is kept
+        slot.send(SlotMsg::Stop);
+        Ok(())
+    }
+
+    pub fn state_of(&self, name: &str) -> Option<ChildState> {
+        self.shared.slots.lock().unwrap().iter()
+            .find(|slot| slot.config.name == name)
+            .map(|slot| slot.status.lock().unwrap().state)
+    }
+
+    // the manual command started by the custom service control code
+    pub fn command_for_control_code(&self, code: u32) -> Option<String> {
+        self.shared.slots.lock().unwrap().iter()
+            .find(|slot| slot.config.start_control_code == Some(code))
+            .map(|slot| slot.config.name.clone())
+    }
+
+    pub fn record_control(&self, request: &str, initiator: &str) {
+        self.shared.history.record(EventKind::Control, None, request.to_owned());
+        self.shared.audit.record("control", initiator, None, request);
+    }
+
+    // the config the service runs with, the [service] settings as of its start and the commands as of the last reload
+    pub fn effective_config(&self) -> Result<EffectiveConfig> {
+        let commands = self.shared.slots.lock().unwrap().iter()
+            .map(|slot| slot.config.clone())
+            .collect::<Vec<_>>();
+
+        EffectiveConfig::new(&self.config_path, &self.variables, &self.service_config, &commands)
+    }
+
+    pub fn history(&self) -> Vec<Event> {
+        self.shared.history.events()
+    }
+
+    // commands that are meant to run but wait out a backoff after failing
+    pub fn unhealthy(&self) -> Vec<String> {
+        self.shared.slots.lock().unwrap().iter()
+            .filter(|slot| slot.status.lock().unwrap().state == ChildState::Backoff)
+            .map(|slot| slot.config.name.clone())
+            .collect()
+    }
+
+    pub fn health(&self) -> Health {
+        let states = self.shared.slots.lock().unwrap().iter()
+            .map(|slot| slot.status.lock().unwrap().state)
+            .collect::<Vec<_>>();
+
+        if !states.contains(&ChildState::Backoff) {
+            Health::Healthy
+        } else if states.contains(&ChildState::Running) {
+            Health::Degraded
+        } else {
+            Health::Failed
+        }
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.shared.stopping.load(Ordering::SeqCst)
+    }
+
+    // logs a change of health and reports it to the event log, called periodically,
+    // the commands leaving during a stop are no change of health
+    pub fn check_health(&self) {
+        if self.is_stopping() {
+            return;
+        }
+
+        let health = self.health();
+
+        if ::std::mem::replace(&mut *self.shared.health.lock().unwrap(), health) == health {
+            return;
+        }
+
+        let unhealthy = self.unhealthy().join(", ");
+        let fields = [&format!("{:?}", health).to_lowercase(), unhealthy.as_str()];
+
+        match health {
+            Health::Healthy => {
+                info!("Service is healthy again");
+                self.shared.events.info(eventlog::EVENT_HEALTH, &fields);
+            },
+            Health::Degraded => {
+                warn!("Service is degraded, waiting out a backoff: {}", unhealthy);
+                self.shared.events.warn(eventlog::EVENT_HEALTH, &fields);
+            },
+            Health::Failed => {
+                error!("Service has failed, no command is left running, waiting out a backoff: {}", unhealthy);
+                self.shared.events.error(eventlog::EVENT_HEALTH, &fields);
+            },
+        }
+    }
+
+    pub fn status(&self) -> ServiceStatus {
+        let maintenance_until = if self.shared.in_maintenance() {
+            self.shared.maintenance.lock().unwrap().map(|(_, until)| until.to_rfc3339())
+        } else {
+            None
+        };
+
+        ServiceStatus {
+            supervisor: version::build_info(),
+            health: self.health(),
+            draining: self.shared.draining.load(Ordering::SeqCst),
+            maintenance_until: maintenance_until,
+            commands: self.shared.slots.lock().unwrap().iter()
+                .map(|slot| {
+                    let mut status = slot.snapshot();
+                    let counters = self.shared.counters.get(&slot.config.name);
+
+                    status.last_exit_code = counters.last_exit_code;
+                    status.last_crash_at = counters.last_crash_at;
+                    status.restarts = counters.restarts;
+                    status.restarts_last_hour = counters.restarts_last_hour;
+                    status
+                })
+                .collect(),
+        }
+    }
+}
+
+// refuses to run a binary that differs from the pinned one or is not signed by the expected publisher,
+// e.g. after tampering
+fn verify(config: &CommandConfig, shared: &Shared) -> Result<()> {
+    if config.sha256.is_none() && config.publisher.is_none() {
+        return Ok(());
+    }
+
+    let target = match command::target_file(config) {
+        Some(target) => target,
+        None => bail!("Unable to find the executable of [{}] to verify it", config.name),
+    };
+
+    let refuse = |detail: String| -> Result<()> {
+        error!(target: &logging::target(&config.name), "Refusing to spawn [{}], {}", config.name, detail);
+        shared.audit.record("refuse", audit::SUPERVISOR, Some(&config.name), &detail);
+        bail!("Refused to spawn, {}", detail);
+    };
+
+    if let Some(ref expected) = config.sha256 {
+        let data = fs::read(&target)
+            .chain_err(|| format!("Unable to read {:?} to verify its sha256", target))?;
+
+        let actual = win::sha256(&data)
+            .chain_err(|| format!("Unable to hash {:?}", target))?;
+
+        if !actual.eq_ignore_ascii_case(expected) {
+            refuse(format!("{:?} has sha256 {}, expected {}", target, actual, expected))?;
+        }
+    }
+
+    if let Some(ref expected) = config.publisher {
+        match authenticode::publisher(&target) {
+            Ok(ref actual) if actual.eq_ignore_ascii_case(expected) => (),
+            Ok(actual) => refuse(format!("{:?} is signed by {:?}, expected {:?}", target, actual, expected))?,
+            Err(e) => refuse(format!("{:?} has no valid signature: {}", target, e))?,
+        }
+    }
+
+    Ok(())
+}
+
+// set up again before every spawn, as the executable may differ after a reload
+fn enable_minidumps(config: &CommandConfig, shared: &Shared) {
+    let image_name = match command::image_name(config) {
+        Some(image_name) => image_name,
+        None => {
+            warn!(target: &logging::target(&config.name),
+                "Unable to find the executable of [{}] to capture minidumps of", config.name);
+            return;
+        },
+    };
+
+    let res = fs::create_dir_all(&shared.dump_dir)
+        .and_then(|_| win::enable_local_dumps(&image_name, &shared.dump_dir, shared.dump_count));
+
+    if let Err(e) = res {
+        warn!(target: &logging::target(&config.name), "Unable to enable minidumps of [{}] for {} in {:?}: {}",
+            config.name, image_name, shared.dump_dir, e);
+    }
+}
+
+// the directory of the command under data_dir is restricted only as it is created on the first start,
+// so that access granted to it later on is kept
+fn create_data_dirs(name: &str, shared: &Shared) -> Result<()> {
+    let command_dir = match shared.data_dir {
+        Some(ref data_dir) => data_dir.join(name),
+        None => return Ok(()),
+    };
+
+    if !command_dir.exists() {
+        fs::create_dir_all(&command_dir)
+            .chain_err(|| format!("Unable to create the directory of [{}] at {:?}", name, command_dir))?;
+
+        info!(target: &logging::target(name), "Created the directory of [{}] at {:?}", name, command_dir);
+
+        if !shared.data_dir_acl.is_empty() {
+            if let Err(e) = win::set_file_dacl(&command_dir, &shared.data_dir_acl) {
+                warn!(target: &logging::target(name), "Unable to restrict access to {:?}: {}", command_dir, e);
+            }
+        }
+    }
+
+    // created within the restricted directory, so that they inherit its permissions
+    for dir in &[command_dir.join("data"), command_dir.join("logs")] {
+        fs::create_dir_all(dir).chain_err(|| format!("Unable to create the directory of [{}] at {:?}", name, dir))?;
+    }
+
+    Ok(())
+}
+
+// the current slot of the command, which a reload may have replaced
+fn find_slot(shared: &Shared, name: &str) -> Option<Arc<Slot>> {
+    shared.slots.lock().unwrap().iter()
+        .find(|slot| slot.config.name == name)
+        .cloned()
+}
+
+// passes the stdout of a child on to the stdin of the consumer, across restarts of either
+fn pipe_output(reader: os_pipe::PipeReader, slot: &Slot, consumer: String, shared: Arc<Shared>) -> JoinHandle<()> {
+    let name = slot.config.name.clone();
+    let mut target = None::<Arc<Slot>>;
+    let mut dropping = false;
+
+    output::relay(reader, name.clone(), slot.filter.clone(), slot.output.clone(), move |data| {
+        if target.as_ref().map(|target| target.is_done()).unwrap_or(true) {
+            target = find_slot(&shared, &consumer);
+        }
+
+        let written = target.as_ref().map(|target| target.write_stdin(data)).unwrap_or(false);
+
+        if !written && !dropping {
+            warn!(target: &logging::target(&name), "Dropping the output of [{}] piped to [{}] while it is stopped",
+                name, consumer);
+        } else if written && dropping {
+            info!(target: &logging::target(&name), "Piping the output of [{}] to [{}] again", name, consumer);
+        }
+
+        dropping = !written;
+    })
+}
+
+// who runs the child, so that it can tell in its own log, overridden by the env of the command
+fn child_env(slot: &Slot, shared: &Shared) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::new();
+
+    env.insert("SUPERVISOR_SERVICE_NAME".to_owned(), shared.service_name.clone());
+    env.insert("SUPERVISOR_COMMAND_NAME".to_owned(), slot.config.name.clone());
+    env.insert("SUPERVISOR_RESTART_COUNT".to_owned(), shared.counters.get(&slot.config.name).restarts.to_string());
+    env.insert("SUPERVISOR_INSTANCE_INDEX".to_owned(), slot.config.instance.to_string());
+
+    if let Some(ref data_dir) = shared.data_dir {
+        let command_dir = data_dir.join(&slot.config.name);
+        env.insert("SUPERVISOR_DATA_DIR".to_owned(), command_dir.join("data").to_string_lossy().into_owned());
+        env.insert("SUPERVISOR_LOG_DIR".to_owned(), command_dir.join("logs").to_string_lossy().into_owned());
+    }
+
+    env.extend(slot.config.child_env());
+    env
+}
+
+fn spawn(slot: &Slot, redactor: &Redactor, shared: &Arc<Shared>) -> Result<Child> {
+    let name = &slot.config.name;
+
+    let cwd = match slot.config.cwd {
+        Some(ref cwd) => cwd.clone(),
+        None => env::current_dir()
+            .map(|cwd| cwd.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "<unknown>".to_owned()),
+    };
+
+    // pipe the child output into the log
+    let (stdout_reader, stdout_writer) = os_pipe::pipe()
+        .chain_err(|| "Unable to create stdout pipe")?;
+
+    let (stderr_reader, stderr_writer) = os_pipe::pipe()
+        .chain_err(|| "Unable to create stderr pipe")?;
+
+    // kept open by the supervisor across restarts of the commands piping into this one
+    let piped_into = shared.slots.lock().unwrap().iter()
+        .any(|other| other.config.pipe_to.as_ref() == Some(name));
+
+    let (stdin_reader, stdin_writer) = if piped_into {
+        let (reader, writer) = os_pipe::pipe().chain_err(|| "Unable to create stdin pipe")?;
+        (Some(reader), Some(writer))
+    } else {
+        (None, None)
+    };
+
+    let _redirection = if slot.config.disable_wow64_redirection {
+        Some(win::disable_wow64_redirection())
+    } else {
+        None
+    };
+
+    verify(&slot.config, shared)?;
+    create_data_dirs(name, shared)?;
+
+    if slot.config.minidump {
+        enable_minidumps(&slot.config, shared);
+    }
+
+    let (child, cmdline) = match slot.config.session {
+        Session::Service if session::restricts_token(&slot.config) => {
+            // picks up system variables set after the service started, like an unrestricted child
+            let fresh_env = if slot.config.refresh_env && !shared.container_mode {
+                win::fresh_environment()
+                    .map_err(|e| warn!(target: &logging::target(name),
+                        "Unable to refresh environment of [{}], using the inherited one: {}", name, e))
+                    .ok()
+            } else {
+                None
+            };
+
+            let base_env = fresh_env.unwrap_or_else(|| env::vars_os().collect());
+
+            let child = session::spawn_restricted(&slot.config, !shared.container_mode, slot.config.cwd.as_deref(),
+                base_env, &child_env(slot, shared), stdin_reader.as_ref(), &stdout_writer, &stderr_writer)
+                .chain_err(|| "Unable to spawn with a restricted token")?;
+
+            let (program, args) = command::command_line(&slot.config, !shared.container_mode);
+            (Child::Session(child), format!("{} {}", program, args))
+        },
+
+        Session::Service => {
+            let mut process = command::build(&slot.config, !shared.container_mode);
+
+            if let Some(ref cwd) = slot.config.cwd {
+                process.current_dir(cwd);
+            }
+
+            // picks up system variables set after the service started
+            if slot.config.refresh_env && shared.container_mode {
+                warn!(target: &logging::target(name), "Not refreshing environment of [{}] in container mode", name);
+            } else if slot.config.refresh_env {
+                match win::fresh_environment() {
+                    Ok(vars) => {
+                        process.env_clear().envs(vars);
+                    },
+                    Err(e) => warn!(target: &logging::target(name),
+                        "Unable to refresh environment of [{}], using the inherited one: {}", name, e),
+                }
+            }
+
+            process.envs(&child_env(slot, shared));
+            process.stdout(stdout_writer.into_stdio()).stderr(stderr_writer.into_stdio());
+
+            if let Some(stdin_reader) = stdin_reader {
+                process.stdin(stdin_reader.into_stdio());
+            }
+
+            let child = SharedChild::spawn(&mut process)
+                .chain_err(|| "Unable to spawn shared child")?;
+
+            // the writer ends must be dropped along with the process so that the readers see EOF on exit
+            (Child::Local(child), format!("{:?}", process))
+        },
+
+        Session::Console => {
+            let child = session::spawn(&slot.config, !shared.container_mode, slot.config.cwd.as_ref().map(|cwd| cwd.as_str()),
+                &child_env(slot, shared), &stdout_writer, &stderr_writer)
+                .chain_err(|| "Unable to spawn into the active console session")?;
+
+            let (program, args) = command::command_line(&slot.config, !shared.container_mode);
+            (Child::Session(child), format!("{} {}", program, args))
+        },
+    };
+
+    info!(target: &logging::target(name), "Spawned [{}] pid={} cwd={:?} started_at={} cmdline={:?}",
+        name, child.id(), cwd, Local::now().to_rfc3339(), redactor.redact(&cmdline));
+
+    // only takes hold right after the spawn, processes the child started before escape the cap
+    if let Some(percent) = slot.config.cpu_limit_percent {
+        match win::limit_cpu_rate(child.id(), percent) {
+            Ok(_) => debug!(target: &logging::target(name), "Capped the cpu of [{}] at {}%", name, percent),
+            Err(e) => warn!(target: &logging::target(name), "Unable to cap the cpu of [{}] at {}%: {}", name, percent, e),
+        }
+    }
+
+    // the hang timeout counts from the spawn until the first line
+    *slot.output.lock().unwrap() = OutputSeen::new();
+
+    if stdin_writer.is_some() {
+        *slot.stdin.lock().unwrap() = stdin_writer;
+    }
+
+    let stdout = match slot.config.pipe_to {
+        Some(ref consumer) => pipe_output(stdout_reader, slot, consumer.clone(), shared.clone()),
+        None => output::forward(stdout_reader, name.clone(), Stream::Stdout, slot.filter.clone(), slot.output.clone()),
+    };
+
+    let stderr = output::forward(stderr_reader, name.clone(), Stream::Stderr, slot.filter.clone(), slot.output.clone());
+    *slot.readers.lock().unwrap() = vec![stdout, stderr];
+
+    Ok(child)
+}
+
+// runs the reload_cmd with the output logged as the command's own, killing it after reload_timeout
+fn run_reload(slot: &Slot, reload_cmd: &str, pid: u32, shared: &Shared) -> Result<ExitStatus> {
+    let name = &slot.config.name;
+    let mut process = command::build_line(reload_cmd, !shared.container_mode);
+
+    if let Some(ref cwd) = slot.config.cwd {
+        process.current_dir(cwd);
+    }
+
+    let (reader, writer) = os_pipe::pipe()
+        .chain_err(|| "Unable to create output pipe")?;
+
+    let stderr_writer = writer.try_clone()
+        .chain_err(|| "Unable to create output pipe")?;
+
+    process
+        .envs(&child_env(slot, shared))
+        .env("SUPERVISOR_CHILD_PID", pid.to_string())
+        .stdin(Stdio::null())
+        .stdout(writer.into_stdio())
+        .stderr(stderr_writer.into_stdio());
+
+    let mut child = process.spawn()
+        .chain_err(|| format!("Unable to spawn reload_cmd {:?}", reload_cmd))?;
+
+    // the write ends held by the builder would keep the pipe open past the exit
+    drop(process);
+
+    let output_name = name.clone();
+    let filter = slot.filter.clone();
+
+    thread::spawn(move || {
+        for line in BufReader::new(reader).split(b'\n') {
+            match line {
+                Ok(line) => info!(target: &logging::target(&output_name), "[{}] reload: {}",
+                    output_name, filter.decode(&line).trim_end_matches('\r')),
+                Err(_) => break,
+            }
+        }
+    });
+
+    let deadline = Instant::now() + slot.config.reload_timeout;
+
+    loop {
+        if let Some(exit_status) = child.try_wait().chain_err(|| "Unable to wait for reload_cmd")? {
+            return Ok(exit_status);
+        }
+
+        if Instant::now() >= deadline {
+            warn!(target: &logging::target(name), "Killing reload_cmd of [{}] still running after {:?}",
+                name, slot.config.reload_timeout);
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("reload_cmd timed out after {:?}", slot.config.reload_timeout);
+        }
+
+        thread::sleep(RELOAD_POLL_INTERVAL);
+    }
+}
+
+fn kill(name: &str, child: &Child, shared: &Shared) {
+    // terminate the process
+    if let Ok(None) = child.try_wait() {
+        debug!(target: &logging::target(name), "Killing process [{}]", name);
+        shared.audit.record("kill", audit::SUPERVISOR, Some(name), &format!("pid={}", child.id()));
+
+        match child.kill() {
+            Ok(_) => info!(target: &logging::target(name), "Killed process [{}]", name),
+            Err(e) => error!(target: &logging::target(name), "Error killing process [{}]: {}", name, e),
+        }
+    }
+}
+
+// waits for the waiter to report the exit, returning None on timeout
+// and whether a stop was requested in the meantime
+fn wait_exited(rx: &Receiver<SlotMsg>, timeout: Option<Duration>) -> (Option<io::Result<ExitStatus>>, bool) {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut stop_requested = false;
+
+    loop {
+        let msg = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+
+                if now >= deadline {
+                    return (None, stop_requested);
+                }
+
+                rx.recv_timeout(deadline - now)
+            },
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match msg {
+            Ok(SlotMsg::Exited(exit_res)) => return (Some(exit_res), stop_requested),
+            Ok(SlotMsg::Stop) => stop_requested = true,
+            Ok(SlotMsg::Restart(_)) | Ok(SlotMsg::Start) => (),
+            Err(RecvTimeoutError::Timeout) => return (None, stop_requested),
+            Err(RecvTimeoutError::Disconnected) => {
+                let e = io::Error::new(io::ErrorKind::Other, "Supervisor channel disconnected");
+                return (Some(Err(e)), stop_requested);
+            },
+        }
+    }
+}
+
+// ctrl-c first, then kill once the stop timeout runs out
+fn graceful_stop(slot: &Slot, child: &Child, rx: &Receiver<SlotMsg>, shared: &Shared) -> (io::Result<ExitStatus>, bool) {
+    let name = &slot.config.name;
+    let stop_timeout = shared.stop_timeout(&slot.config);
+
+    // containers may lack the console apis needed to deliver ctrl-c
+    let (exit_res, stop_requested) = if shared.container_mode {
+        (None, false)
+    } else {
+        debug!(target: &logging::target(name), "Sending ctrl-c to process [{}]", name);
+
+        match win::send_ctrl_c(child.id()) {
+            Ok(_) => wait_exited(rx, Some(stop_timeout)),
+            Err(e) => {
+                warn!(target: &logging::target(name), "Unable to send ctrl-c to process [{}]: {}", name, e);
+                (None, false)
+            },
+        }
+    };
+
+    match exit_res {
+        Some(exit_res) => (exit_res, stop_requested),
+        None => {
+            if !shared.container_mode {
+                warn!(target: &logging::target(name), "Process [{}] did not exit within {:?}", name, stop_timeout);
+            }
+
+            kill(name, child, shared);
+
+            let (exit_res, also_stop_requested) = wait_exited(rx, None);
+            (exit_res.expect("Exit must be reported without timeout"), stop_requested || also_stop_requested)
+        },
+    }
+}
+
+// lets the run complete on service stop, killing it once the stop timeout runs out
+fn wait_completed(slot: &Slot, child: &Child, rx: &Receiver<SlotMsg>, shared: &Shared) -> io::Result<ExitStatus> {
+    let name = &slot.config.name;
+    let stop_timeout = shared.stop_timeout(&slot.config);
+
+    info!(target: &logging::target(name), "Waiting up to {:?} for [{}] to complete before the service stops",
+        stop_timeout, name);
+
+    match wait_exited(rx, Some(stop_timeout)).0 {
+        Some(exit_res) => exit_res,
+        None => {
+            warn!(target: &logging::target(name), "Process [{}] did not complete within {:?}", name, stop_timeout);
+            kill(name, child, shared);
+            wait_exited(rx, None).0.expect("Exit must be reported without timeout")
+        },
+    }
+}
+
+// a child of the previous run of the service that is still running, which it lost track of when it was killed,
+// would otherwise run twice, it is killed rather than adopted since its output pipes went with the previous run,
+// so nothing could log its output or pipe it on, and it is not a child of this process to wait on or stop with ctrl-c
+fn kill_leftover(name: &str, pid: u32, created: u64, shared: &Shared) {
+    match win::terminate_if_created(pid, created, 1) {
+        Ok(true) => {
+            warn!(target: &logging::target(name),
+                "Killed pid={} of [{}] left running by the previous run of the service", pid, name);
+            shared.audit.record("kill", audit::SUPERVISOR, Some(name), &format!("leftover pid={}", pid));
+        },
+        Ok(false) => (),
+        Err(e) => warn!(target: &logging::target(name),
+            "Unable to kill pid={} of [{}] left running by the previous run of the service: {}", pid, name, e),
+    }
+}
+
+fn backoff_delay(config: &CommandConfig, failures: u32) -> Duration {
+    let factor = 1u32.checked_shl(cmp::min(failures, 31)).unwrap_or(u32::max_value());
+
+    config.restart_delay.checked_mul(factor)
+        .map(|delay| cmp::min(delay, config.restart_delay_max))
+        .unwrap_or(config.restart_delay_max)
+}
+
+enum RunOutcome {
+    Exited(io::Result<ExitStatus>),
+    Stopped(io::Result<ExitStatus>),
+    Restart(String),
+}
+
+// kills a child that is unlikely to handle ctrl-c, so it is not given the stop timeout
+fn kill_for_restart(name: &str, child: &Child, rx: &Receiver<SlotMsg>, shared: &Shared, reason: String) -> RunOutcome {
+    kill(name, child, shared);
+
+    let (exit_res, stop_requested) = wait_exited(rx, None);
+    let exit_res = exit_res.expect("Exit must be reported without timeout");
+    info!(target: &logging::target(name), "Process [{}] stopped for restart, exit status: {:?}", name, exit_res);
+
+    if stop_requested {
+        RunOutcome::Stopped(exit_res)
+    } else {
+        RunOutcome::Restart(reason)
+    }
+}
+
+// watches over a running child until it exits, is stopped,
+// or is due for a restart by its schedule or watched files
+fn run_child(slot: &Slot, child: &Child, rx: &Receiver<SlotMsg>, shared: &Shared, check_ready: bool,
+    mut starting: Option<Starting>) -> RunOutcome {
+    let name = &slot.config.name;
+    let started = Instant::now();
+    let mut ready_deadline = if check_ready { Some(started + slot.config.ready_after) } else { None };
+
+    let mut starting_deadline = starting.as_ref().map(|_| started + slot.config.ready_after);
+
+    let mut restart_deadline = slot.config.until_scheduled_restart()
+        .map(|until| Instant::now() + until);
+
+    let mut clock = ClockWatch::new();
+    let mut clock_deadline = restart_deadline.map(|_| Instant::now() + CLOCK_CHECK_INTERVAL);
+
+    let mut watcher = FileWatcher::new(&slot.config.watch);
+    let mut watch_deadline = watcher.as_ref().map(|_| Instant::now() + watch::POLL_INTERVAL);
+
+    // the hang timeout, i.e. the liveness check, only applies once the child started up
+    let startup_probe = slot.filter.has_startup_probe();
+    let mut in_startup = startup_probe || slot.config.startup_timeout.is_some();
+    let startup_deadline = slot.config.startup_timeout.map(|startup_timeout| started + startup_timeout);
+    let mut live_since = started;
+
+    loop {
+        let hang_deadline = slot.config.hang_timeout.filter(|_| !in_startup)
+            .map(|hang_timeout| cmp::max(cmp::max(slot.output.lock().unwrap().last_line, live_since) + hang_timeout,
+                Instant::now() + HANG_CHECK_MIN_INTERVAL));
+
+        // the output is checked for the startup line every so often
+        let startup_check = if in_startup && startup_probe {
+            Some(Instant::now() + HANG_CHECK_MIN_INTERVAL)
+        } else if in_startup {
+            startup_deadline
+        } else {
+            None
+        };
+
+        let timeout = ready_deadline.into_iter()
+            .chain(starting_deadline)
+            .chain(restart_deadline)
+            .chain(clock_deadline)
+            .chain(watch_deadline)
+            .chain(hang_deadline)
+            .chain(startup_check)
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+        let msg = match timeout {
+            Some(timeout) => rx.recv_timeout(timeout),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        let mut restart_reason = None;
+
+        match msg {
+            Ok(SlotMsg::Stop) => {
+                debug!(target: &logging::target(name), "Received stop for [{}]", name);
+
+                // a stop of just this command is always graceful
+                let action = if shared.stopping.load(Ordering::SeqCst) {
+                    slot.config.on_service_stop
+                } else {
+                    OnServiceStop::Stop
+                };
+
+                return RunOutcome::Stopped(match action {
+                    OnServiceStop::Stop => graceful_stop(slot, child, rx, shared).0,
+                    OnServiceStop::Wait => wait_completed(slot, child, rx, shared),
+                    OnServiceStop::Kill => {
+                        kill(name, child, shared);
+                        wait_exited(rx, None).0.expect("Exit must be reported without timeout")
+                    },
+                });
+            },
+
+            Ok(SlotMsg::Exited(exit_res)) => {
+                if ready_deadline.is_some() {
+                    error!(target: &logging::target(name), "Process [{}] exited before becoming ready", name);
+                }
+
+                return RunOutcome::Exited(exit_res);
+            },
+
+            Ok(SlotMsg::Restart(reason)) => restart_reason = Some(reason),
+            Ok(SlotMsg::Start) | Err(RecvTimeoutError::Timeout) => (),
+
+            Err(RecvTimeoutError::Disconnected) => {
+                let e = io::Error::new(io::ErrorKind::Other, "Supervisor channel disconnected");
+                return RunOutcome::Exited(Err(e));
+            },
+        }
+
+        let now = Instant::now();
+
+        if ready_deadline.map(|deadline| now >= deadline).unwrap_or(false) {
+            info!(target: &logging::target(name), "Process [{}] is ready after running for {:?}",
+                name, started.elapsed());
+            ready_deadline = None;
+            shared.restart_dependents(name);
+        }
+
+        if starting_deadline.map(|deadline| now >= deadline).unwrap_or(false) {
+            debug!(target: &logging::target(name),
+                "Process [{}] is done starting up, commands with anti affinity may start", name);
+            starting_deadline = None;
+            drop(starting.take());
+        }
+
+        // the scheduled restart is due once if the clock was put forward past it, otherwise it is scheduled anew
+        if clock_deadline.map(|deadline| now >= deadline).unwrap_or(false) {
+            if let Some(jump) = clock.jumped() {
+                let forward = jump.to_std().ok();
+                let passed = restart_deadline.zip(forward)
+                    .map(|(deadline, forward)| deadline.saturating_duration_since(now) <= forward)
+                    .unwrap_or(false);
+
+                info!(target: &logging::target(name), "The wall clock was changed by {}s, {} the restart of [{}]",
+                    jump.num_seconds(), if passed { "now due for" } else { "rescheduling" }, name);
+
+                restart_deadline = if passed {
+                    Some(now)
+                } else {
+                    slot.config.until_scheduled_restart().map(|until| now + until)
+                };
+            }
+
+            clock_deadline = restart_deadline.map(|_| now + CLOCK_CHECK_INTERVAL);
+        }
+
+        if restart_deadline.map(|deadline| now >= deadline).unwrap_or(false) {
+            restart_reason = Some("scheduled restart".to_owned());
+        }
+
+        if watch_deadline.map(|deadline| now >= deadline).unwrap_or(false) {
+            if let Some(changed) = watcher.as_mut().and_then(|watcher| watcher.poll()) {
+                restart_reason = Some(format!("change of watched files {:?}", changed));
+            }
+
+            watch_deadline = Some(now + watch::POLL_INTERVAL);
+        }
+
+        if in_startup {
+            let past_deadline = startup_deadline.map(|deadline| now >= deadline).unwrap_or(false);
+
+            if slot.output.lock().unwrap().started {
+                info!(target: &logging::target(name), "Process [{}] started up after {:?}", name, started.elapsed());
+                in_startup = false;
+                live_since = now;
+            } else if past_deadline && !startup_probe {
+                debug!(target: &logging::target(name),
+                    "Process [{}] is past its startup_timeout, checking it for hangs", name);
+                in_startup = false;
+                live_since = now;
+            } else if past_deadline && !shared.in_maintenance() {
+                let startup_timeout = slot.config.startup_timeout.unwrap();
+                warn!(target: &logging::target(name), "Process [{}] did not start up within {:?}, killing it",
+                    name, startup_timeout);
+
+                return kill_for_restart(name, child, rx, shared,
+                    format!("no startup_output line within {}", humantime::format_duration(startup_timeout)));
+            }
+        }
+
+        if let Some(hang_timeout) = slot.config.hang_timeout.filter(|_| !in_startup) {
+            let silent_for = cmp::min(slot.output.lock().unwrap().last_line.elapsed(), live_since.elapsed());
+
+            if silent_for >= hang_timeout && !shared.in_maintenance() {
+                warn!(target: &logging::target(name), "Process [{}] wrote no output for {:?}, killing it as hung",
+                    name, silent_for);
+
+                return kill_for_restart(name, child, rx, shared,
+                    format!("hang, no output for {}", humantime::format_duration(hang_timeout)));
+            }
+        }
+
+        if let Some(restart_reason) = restart_reason {
+            info!(target: &logging::target(name), "Restarting [{}] due to {}", name, restart_reason);
+
+            let (exit_res, stop_requested) = graceful_stop(slot, child, rx, shared);
+            info!(target: &logging::target(name), "Process [{}] stopped for restart, exit status: {:?}",
+                name, exit_res);
+
+            return if stop_requested {
+                RunOutcome::Stopped(exit_res)
+            } else {
+                RunOutcome::Restart(restart_reason)
+            };
+        }
+
+        // a clock change may have moved the next occurrence
+        restart_deadline = restart_deadline.and_then(|deadline| if deadline <= now {
+            slot.config.until_scheduled_restart().map(|until| now + until)
+        } else {
+            Some(deadline)
+        });
+    }
+}
+
+// a bare drive letter like "D:" refers to the current directory on that drive, not its root
+fn drive_root(drive: &str) -> PathBuf {
+    if drive.len() == 2 && drive.ends_with(':') {
+        PathBuf::from(format!("{}\\", drive))
+    } else {
+        PathBuf::from(drive)
+    }
+}
+
+// polls until the precondition holds before the first spawn, giving up on it after the timeout if any,
+// returns false if a stop was requested in the meantime
+fn wait_until<F: Fn() -> bool>(slot: &Slot, rx: &Receiver<SlotMsg>, what: &str, timeout: Option<Duration>, ready: F) -> bool {
+    let name = &slot.config.name;
+    let started = Instant::now();
+    let mut waiting = false;
+
+    while !ready() {
+        if let Some(timeout) = timeout.filter(|&timeout| started.elapsed() >= timeout) {
+            warn!(target: &logging::target(name), "Gave up waiting for {} after {:?}, starting [{}] anyway",
+                what, timeout, name);
+            return true;
+        }
+
+        if !waiting {
+            info!(target: &logging::target(name), "Waiting for {} before starting [{}]", what, name);
+            waiting = true;
+        }
+
+        match rx.recv_timeout(PRECONDITION_POLL_INTERVAL) {
+            Ok(SlotMsg::Stop) | Err(RecvTimeoutError::Disconnected) => return false,
+            Ok(SlotMsg::Exited(_)) | Ok(SlotMsg::Restart(_)) | Ok(SlotMsg::Start) | Err(RecvTimeoutError::Timeout) => (),
+        }
+    }
+
+    if waiting {
+        info!(target: &logging::target(name), "Done waiting for {}, starting [{}]", what, name);
+    }
+
+    true
+}
+
+// returns false if a stop was requested before the delay passed
+fn wait_delay(rx: &Receiver<SlotMsg>, delay: Duration) -> bool {
+    let deadline = Instant::now() + delay;
+
+    loop {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(SlotMsg::Stop) | Err(RecvTimeoutError::Disconnected) => return false,
+            Err(RecvTimeoutError::Timeout) => return true,
+            Ok(SlotMsg::Exited(_)) | Ok(SlotMsg::Restart(_)) | Ok(SlotMsg::Start) => (),
+        }
+    }
+}
+
+// blocks a manual command until it is started, returns false if a stop was requested instead
+fn wait_start(rx: &Receiver<SlotMsg>) -> bool {
+    loop {
+        match rx.recv() {
+            Ok(SlotMsg::Start) => return true,
+            Ok(SlotMsg::Stop) | Err(_) => return false,
+            Ok(SlotMsg::Exited(_)) | Ok(SlotMsg::Restart(_)) => (),
+        }
+    }
+}
+
+// spawns the command and respawns it according to its restart policy until told to stop
+fn supervise(slot: &Arc<Slot>, rx: &Receiver<SlotMsg>, redactor: Arc<Redactor>, shared: Arc<Shared>, start_delay: Duration,
+    saved: Option<Saved>) {
+    let name = slot.config.name.clone();
+    let cmd_str = redactor.redact(&command::display(&slot.config));
+    let mut failures = saved.as_ref().map(|saved| saved.failures).unwrap_or(0);
+    let mut check_ready = false;
+
+    let stopped = |exit_res: io::Result<ExitStatus>| {
+        info!(target: &logging::target(&name), "Process [{}] stopped, exit status: {:?}", name, exit_res);
+        slot.end_run();
+        shared.history.record(EventKind::Stop, Some(&name), format!("{:?}", exit_res));
+        shared.audit.record("stop", audit::SUPERVISOR, Some(&name), &format!("{:?}", exit_res));
+
+        shared.events.info(eventlog::EVENT_STOP, &[&name, &match exit_res {
+            Ok(ref exit_status) => exitcode::raw(exit_status),
+            Err(ref e) => format!("{}", e),
+        }]);
+
+        shared.counters.record_exit(&name, exit_res.ok().and_then(|exit_status| exit_status.code()), false);
+
+        slot.update(|status| {
+            status.state = ChildState::Stopped;
+            status.pid = None;
+        });
+    };
+
+    slot.update(|status| status.state = ChildState::Starting);
+
+    if start_delay > Duration::from_secs(0) {
+        debug!(target: &logging::target(&name), "Staggering the start of [{}] by {:?}", name, start_delay);
+
+        if !wait_delay(rx, start_delay) {
+            slot.update(|status| status.state = ChildState::Stopped);
+            return;
+        }
+    }
+
+    // a crash looping child is not given a fresh start by restarting the service
+    if let Some(left) = saved.and_then(|saved| saved.backoff_left) {
+        // the backoff is saved in wall clock time, which may have been set back while the service was down
+        let left = cmp::min(left, slot.config.restart_delay_max);
+
+        info!(target: &logging::target(&name),
+            "Resuming the backoff of [{}] from before the service restarted, starting in {:?}", name, left);
+        slot.update(|status| status.state = ChildState::Backoff);
+
+        if !wait_delay(rx, left) {
+            slot.update(|status| status.state = ChildState::Stopped);
+            return;
+        }
+    }
+
+    if let Some(ref drive) = slot.config.requires_drive {
+        let root = drive_root(drive);
+
+        if !wait_until(slot, rx, &format!("drive {}", drive), None, || fs::read_dir(&root).is_ok()) {
+            slot.update(|status| status.state = ChildState::Stopped);
+            return;
+        }
+    }
+
+    if slot.config.needs_network {
+        let check_host = shared.network_check_host.as_ref().map(|host| host.as_str());
+
+        if !wait_until(slot, rx, "the network", Some(shared.network_wait_timeout), || network::is_up(check_host)) {
+            slot.update(|status| status.state = ChildState::Stopped);
+            return;
+        }
+    }
+
+    loop {
+        slot.update(|status| {
+            status.state = ChildState::Starting;
+            status.pid = None;
+        });
+
+        if slot.config.pause_on_low_disk
+            && !wait_until(slot, rx, "free disk space", None, || !shared.low_disk.load(Ordering::SeqCst)) {
+            slot.update(|status| status.state = ChildState::Stopped);
+            return;
+        }
+
+        let starting = if slot.config.anti_affinity.is_empty() {
+            None
+        } else {
+            let what = format!("{:?} to finish starting up", slot.config.anti_affinity);
+
+            if !wait_until(slot, rx, &what, None, || shared.try_begin_start(&slot.config)) {
+                slot.update(|status| status.state = ChildState::Stopped);
+                return;
+            }
+
+            Some(Starting { shared: &shared, name: name.clone() })
+        };
+
+        let started = Instant::now();
+
+        let exit_res = match spawn(slot, &redactor, &shared) {
+            Ok(child) => {
+                let child = Arc::new(child);
+                let child_wait = child.clone();
+                let tx = slot.tx.lock().unwrap().clone();
+
+                slot.update(|status| {
+                    status.state = ChildState::Running;
+                    status.pid = Some(child.id());
+                });
+
+                slot.begin_run();
+                shared.counters.record_spawn(&name, child.id(), win::process_created(child.id()).ok());
+
+                shared.history.record(EventKind::Spawn, Some(&name), format!("pid={}", child.id()));
+                shared.audit.record("spawn", audit::SUPERVISOR, Some(&name), &format!("pid={}", child.id()));
+                shared.events.info(eventlog::EVENT_SPAWN, &[&name, &child.id().to_string(), &cmd_str]);
+
+                // the exit is reported from the thread pool, so that idle children hold no thread each,
+                // only if that cannot be set up a thread is parked in wait()
+                let exit_tx = tx.clone();
+                let exit_child = child_wait.clone();
+
+                if let Err(e) = win::notify_exit(child.id(), move || {
+                    let _ = exit_tx.send(SlotMsg::Exited(exit_child.wait()));
+                }) {
+                    warn!(target: &logging::target(&name),
+                        "Unable to get notified of the exit of [{}], waiting on a thread instead: {}", name, e);
+
+                    let _ = thread::spawn(move || {
+                        let _ = tx.send(SlotMsg::Exited(child_wait.wait()));
+                    });
+                }
+
+                let outcome = run_child(slot, &child, rx, &shared, check_ready, starting);
+
+                // dependents restart once a respawn is ready, whether requested or by the restart policy
+                check_ready = true;
+
+                match outcome {
+                    RunOutcome::Exited(exit_res) => exit_res.chain_err(|| "Unable to join shell process"),
+
+                    RunOutcome::Stopped(exit_res) => {
+                        if started.elapsed() >= slot.config.restart_delay_max {
+                            shared.counters.reset_backoff(&name);
+                        }
+
+                        stopped(exit_res);
+                        return;
+                    },
+
+                    // requested restarts bypass the restart policy and backoff
+                    RunOutcome::Restart(reason) => {
+                        slot.end_run();
+                        shared.counters.record_restart(&name);
+                        shared.audit.record("restart", audit::SUPERVISOR, Some(&name), &reason);
+                        shared.events.info(eventlog::EVENT_RESTART, &[&name, &reason]);
+                        shared.history.record(EventKind::Restart, Some(&name), reason);
+
+                        if shared.draining.load(Ordering::SeqCst) {
+                            info!(target: &logging::target(&name), "Not respawning [{}] since the service is draining",
+                                name);
+                            slot.update(|status| status.state = ChildState::Stopped);
+                            return;
+                        }
+
+                        continue;
+                    },
+                }
+            },
+
+            Err(e) => {
+                // others must not wait out the backoff
+                drop(starting);
+                Err(e)
+            },
+        };
+        match exit_res {
+            Ok(ref exit_status) => info!(target: &logging::target(&name), "Shell terminated [{}], {}",
+                cmd_str, exitcode::describe(exit_status)),
+            Err(ref e) => error!(target: &logging::target(&name), "Shell error [{}]: {}", cmd_str, e),
+        }
+
+        slot.end_run();
+
+        shared.history.record(EventKind::Exit, Some(&name), match exit_res {
+            Ok(ref exit_status) => exitcode::describe(exit_status),
+            Err(ref e) => format!("{}", e),
+        });
+
+        let mut success = exit_res.as_ref().map(|exit_status| exit_status.success()).unwrap_or(false);
+
+        let (code, mut meaning) = match exit_res {
+            Ok(ref exit_status) => (exitcode::raw(exit_status), exitcode::meaning_of(exit_status).unwrap_or_default()),
+            Err(ref e) => (format!("{}", e), String::new()),
+        };
+
+        // many tools exit with 0 even when they failed
+        if success {
+            if let Some(reason) = slot.output_contradiction() {
+                warn!(target: &logging::target(&name), "Process [{}] exited with 0, but {}, counting it as failed",
+                    name, reason);
+                meaning = reason.to_owned();
+                success = false;
+            }
+        }
+
+        if success {
+            shared.events.info(eventlog::EVENT_EXIT, &[&name, &code, &meaning]);
+        } else if shared.in_maintenance() {
+            info!(target: &logging::target(&name), "Process [{}] failed during maintenance, alert suppressed", name);
+            shared.events.info(eventlog::EVENT_MAINTENANCE_EXIT, &[&name, &code, &meaning]);
+        } else {
+            let described = match exit_res {
+                Ok(ref exit_status) => exitcode::describe(exit_status),
+                Err(ref e) => format!("{}", e),
+            };
+
+            let crashes = shared.crash_alerts.crashed(&name);
+
+            match crashes {
+                Some(1) => error!(target: &logging::target(&name), "Process [{}] crashed: {}", name, described),
+                Some(crashes) => error!(target: &logging::target(&name),
+                    "Process [{}] crashed: {}, {} crashes since the last alert", name, described, crashes),
+
+                // below the levels syslog forwards by default
+                None => debug!(target: &logging::target(&name),
+                    "Process [{}] crashed: {}, alert held back within alert_window", name, described),
+            }
+
+            if let Some(crashes) = crashes {
+                shared.events.error(eventlog::EVENT_CRASH, &[&name, &code, &meaning, &crashes.to_string()]);
+            }
+        }
+
+        shared.counters.record_exit(&name, exit_res.as_ref().ok().and_then(|exit_status| exit_status.code()), !success);
+        slot.update(|status| status.pid = None);
+
+        // a child that ran long enough is considered healthy again
+        if started.elapsed() >= slot.config.restart_delay_max {
+            failures = 0;
+            shared.counters.reset_backoff(&name);
+        }
+
+        if let Some(ref next) = slot.config.on_success {
+            if success {
+                if let Err(e) = shared.start_command(next, &format!("the successful exit of [{}]", name)) {
+                    warn!(target: &logging::target(next), "Unable to start [{}] after [{}]: {}", next, name, e);
+                }
+            } else {
+                info!(target: &logging::target(next), "Not starting [{}] since [{}] failed", next, name);
+            }
+        }
+
+        if !slot.config.restart.should_restart(success) {
+            slot.update(|status| status.state = ChildState::Stopped);
+            return;
+        }
+
+        if shared.draining.load(Ordering::SeqCst) {
+            info!(target: &logging::target(&name), "Not respawning [{}] since the service is draining", name);
+            slot.update(|status| status.state = ChildState::Stopped);
+            return;
+        }
+
+        let delay = backoff_delay(&slot.config, failures);
+        failures += 1;
+
+        slot.update(|status| status.state = ChildState::Backoff);
+        info!(target: &logging::target(&name), "Restarting [{}] in {:?}", name, delay);
+        shared.counters.record_restart(&name);
+        shared.counters.record_backoff(&name, failures, delay);
+        shared.history.record(EventKind::Restart, Some(&name), format!("restart policy, in {:?}", delay));
+        shared.audit.record("restart", audit::SUPERVISOR, Some(&name), &format!("restart policy, in {:?}", delay));
+        shared.events.info(eventlog::EVENT_RESTART, &[&name, &format!("restart policy, in {:?}", delay)]);
+
+        // a restart of a dependency cuts the backoff short, it is likely what the child was missing
+        match rx.recv_timeout(delay) {
+            Err(RecvTimeoutError::Timeout) | Ok(SlotMsg::Exited(_)) | Ok(SlotMsg::Restart(_)) | Ok(SlotMsg::Start) => {
+                if let Some(ref group) = slot.config.restart_group {
+                    let what = format!("the restart budget of group {}", group);
+
+                    if !wait_until(slot, rx, &what, None, || shared.try_take_budget(&slot.config)) {
+                        debug!(target: &logging::target(&name), "Received stop for [{}] during backoff", name);
+                        slot.update(|status| status.state = ChildState::Stopped);
+                        return;
+                    }
+                }
+
+                if !wait_until(slot, rx, "the service wide restart rate limit", None, || shared.restarts.try_take()) {
+                    debug!(target: &logging::target(&name), "Received stop for [{}] during backoff", name);
+                    slot.update(|status| status.state = ChildState::Stopped);
+                    return;
+                }
+
+                // drain may have been requested during the backoff
+                if shared.draining.load(Ordering::SeqCst) {
+                    info!(target: &logging::target(&name), "Not respawning [{}] since the service is draining", name);
+                    slot.update(|status| status.state = ChildState::Stopped);
+                    return;
+                }
+            },
+
+            Ok(SlotMsg::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                debug!(target: &logging::target(&name), "Received stop for [{}] during backoff", name);
+                slot.update(|status| status.state = ChildState::Stopped);
+                return;
+            },
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use chrono::Local;
+use config::{SyslogConfig, SyslogProtocol};
+use log::{LogLevel, LogRecord};
+use log4rs::append::Append;
+use output;
+use std::env;
+use std::error::Error as StdError;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// an unreachable collector must not hold up the logging for long
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+enum Connection {
+    Disconnected,
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+// sends every record as an rfc 5424 message, connecting on first use and reconnecting after a failure,
+// e.g. when the collector was restarted
+#[derive(Debug)]
+pub struct SyslogAppender {
+    config: SyslogConfig,
+    host: String,
+    app_name: String,
+    connection: Mutex<Connection>,
+}
+
+fn severity(level: LogLevel) -> u8 {
+    match level {
+        LogLevel::Error => 3,
+        LogLevel::Warn => 4,
+        LogLevel::Info => 6,
+        LogLevel::Debug | LogLevel::Trace => 7,
+    }
+}
+
+impl SyslogAppender {
+    pub fn new(config: SyslogConfig, app_name: &str) -> SyslogAppender {
+        SyslogAppender {
+            config: config,
+            host: env::var("COMPUTERNAME").unwrap_or_else(|_| "-".to_owned()),
+            app_name: app_name.to_owned(),
+            connection: Mutex::new(Connection::Disconnected),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let addr = self.config.address.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, format!("{} resolves to no address", self.config.address)))?;
+
+        match self.config.protocol {
+            SyslogProtocol::Udp => {
+                let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+                socket.connect(addr)?;
+                Ok(Connection::Udp(socket))
+            },
+
+            SyslogProtocol::Tcp => {
+                let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                Ok(Connection::Tcp(stream))
+            },
+        }
+    }
+
+    // without structured data and message id
+    fn format(&self, record: &LogRecord) -> String {
+        format!("<{}>1 {} {} {} {} - - {}", self.config.facility * 8 + severity(record.level()),
+            Local::now().to_rfc3339(), self.host, self.app_name, process::id(), record.args())
+    }
+}
+
+impl Append for SyslogAppender {
+    fn append(&self, record: &LogRecord) -> ::std::result::Result<(), Box<dyn StdError + Sync + Send>> {
+        if !self.config.child_output && output::is_output(record.target()) {
+            return Ok(());
+        }
+
+        let message = self.format(record);
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Connection::Disconnected = *connection {
+            *connection = self.connect()?;
+        }
+
+        let res = match *connection {
+            Connection::Disconnected => Ok(()),
+            Connection::Udp(ref socket) => socket.send(message.as_bytes()).map(|_| ()),
+
+            // octet counting framing
+            Connection::Tcp(ref mut stream) => write!(stream, "{} {}", message.len(), message),
+        };
+
+        if res.is_err() {
+            *connection = Connection::Disconnected;
+        }
+
+        Ok(res?)
+    }
+}
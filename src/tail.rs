@@ -0,0 +1,117 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+// which a log file written with log_encoding = "utf8_bom" starts with
+const BOM: char = '\u{feff}';
+
+// how far back the log is read for the lines of a command that rarely writes any
+const MAX_SCAN: u64 = 16 * 1024 * 1024;
+
+// child output is logged as `[name] line`, so the lines of a command are picked from the service log
+fn matches(line: &str, command: Option<&str>) -> bool {
+    match command {
+        Some(name) => line.contains(&format!(" - [{}] ", name)),
+        None => true,
+    }
+}
+
+// the last count lines of the log, of a single command if given, and the offset they end at
+pub fn last_lines(path: &Path, count: usize, command: Option<&str>) -> io::Result<(Vec<String>, u64)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut start = len;
+    let mut carry = Vec::new();
+    let mut lines = Vec::new();
+
+    // reads backwards by chunks, the first line of a chunk is carried over until its start is read
+    while lines.len() < count && start > 0 && len - start < MAX_SCAN {
+        let size = CHUNK_SIZE.min(start);
+        start -= size;
+
+        let mut chunk = vec![0; size as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&carry);
+
+        let mut segments = chunk.split(|&b| b == b'\n').collect::<Vec<_>>();
+        let first = if start > 0 { segments.remove(0).to_vec() } else { Vec::new() };
+
+        for segment in segments.into_iter().rev() {
+            let line = String::from_utf8_lossy(segment);
+            let line = line.trim_start_matches(BOM).trim_end_matches('\r');
+
+            if !line.is_empty() && matches(line, command) {
+                lines.push(line.to_owned());
+
+                if lines.len() == count {
+                    break;
+                }
+            }
+        }
+
+        carry = first;
+    }
+
+    lines.reverse();
+    Ok((lines, len))
+}
+
+// follows the log from an offset, like tail -f
+pub struct Follower {
+    path: PathBuf,
+    command: Option<String>,
+    offset: u64,
+    partial: Vec<u8>,
+}
+
+impl Follower {
+    pub fn new(path: &Path, command: Option<&str>, offset: u64) -> Follower {
+        Follower {
+            path: path.to_owned(),
+            command: command.map(|name| name.to_owned()),
+            offset: offset,
+            partial: Vec::new(),
+        }
+    }
+
+    // the complete lines written since the last call
+    pub fn next_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut file = File::open(&self.path)?;
+        let len = file.metadata()?.len();
+
+        // truncated or replaced, start over
+        if len < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+
+        if len == self.offset {
+            return Ok(Vec::new());
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        file.by_ref().take(len - self.offset).read_to_end(&mut self.partial)?;
+        self.offset = len;
+
+        let end = match self.partial.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None => return Ok(Vec::new()),
+        };
+
+        let rest = self.partial.split_off(end);
+        let complete = ::std::mem::replace(&mut self.partial, rest);
+
+        let lines = String::from_utf8_lossy(&complete)
+            .lines()
+            .map(|line| line.trim_start_matches(BOM).trim_end_matches('\r'))
+            .filter(|line| !line.is_empty() && matches(line, self.command.as_deref()))
+            .map(|line| line.to_owned())
+            .collect();
+
+        Ok(lines)
+    }
+}
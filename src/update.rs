@@ -0,0 +1,203 @@
+use authenticode;
+use errors::*;
+use http;
+use serde_json;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use win;
+
+// published next to the release binaries, e.g.
+// {"version": "0.2.0", "url": "https://example.com/windows_service.exe", "sha256": "..."}
+#[derive(Deserialize, Debug)]
+struct Manifest {
+    version: String,
+    url: String,
+    sha256: String,
+}
+
+// dotted numeric versions, so that 0.10.0 is newer than 0.9.0 and 1.0 the same as 1.0.0,
+// anything unparsable is never considered newer
+fn is_newer(candidate: &str, current: &str) -> bool {
+    let parse = |version: &str| version.trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse::<u64>())
+        .collect::<::std::result::Result<Vec<_>, _>>()
+        .map(|mut parts| {
+            while parts.last() == Some(&0) {
+                parts.pop();
+            }
+
+            parts
+        });
+
+    match (parse(candidate), parse(current)) {
+        (Ok(candidate), Ok(current)) => candidate > current,
+        _ => false,
+    }
+}
+
+// the manifest and the binary travel over https, so that nobody on the way can swap them
+fn require_https(url: &str) -> Result<()> {
+    if !url.to_ascii_lowercase().starts_with("https://") {
+        bail!("Update url {} is not https", url);
+    }
+
+    Ok(())
+}
+
+// the publisher an update has to be signed by, the configured one or that of the running binary
+fn expected_publisher(exe: &Path, publisher: Option<&str>) -> Result<String> {
+    match publisher {
+        Some(publisher) => Ok(publisher.to_owned()),
+        None => authenticode::publisher(exe)
+            .chain_err(|| format!("Unable to verify updates, the running binary {:?} has no valid signature \
+                and no update_publisher is configured", exe)),
+    }
+}
+
+fn replaced_exe(exe: &Path) -> PathBuf {
+    exe.with_extension("exe.old")
+}
+
+// the previous binary is still running while an update is staged, so it is only removed on the next start
+pub fn remove_replaced() {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(_) => return,
+    };
+
+    let replaced = replaced_exe(&exe);
+
+    if replaced.exists() {
+        match fs::remove_file(&replaced) {
+            Ok(_) => info!("Removed the binary replaced by the last update {:?}", replaced),
+            Err(e) => warn!("Unable to remove the binary replaced by the last update {:?}: {}", replaced, e),
+        }
+    }
+}
+
+// downloads a newer binary listed in the manifest and swaps it in for the next start, if it is signed by the
+// expected publisher, returning the staged version, if any
+pub fn check(manifest_url: &str, publisher: Option<&str>) -> Result<Option<String>> {
+    let current = env!("CARGO_PKG_VERSION");
+
+    let exe = env::current_exe()
+        .chain_err(|| "Unable to get current executable path")?;
+
+    let replaced = replaced_exe(&exe);
+
+    if replaced.exists() {
+        debug!("An update is already staged and waits for a restart");
+        return Ok(None);
+    }
+
+    require_https(manifest_url)?;
+    let publisher = expected_publisher(&exe, publisher)?;
+
+    let manifest = http::get(manifest_url)
+        .chain_err(|| format!("Unable to fetch update manifest {}", manifest_url))?;
+
+    let manifest: Manifest = serde_json::from_slice(&manifest)
+        .chain_err(|| format!("Unable to parse update manifest {}", manifest_url))?;
+
+    if !is_newer(&manifest.version, current) {
+        debug!("No update, manifest version {} and current version {}", manifest.version, current);
+        return Ok(None);
+    }
+
+    require_https(&manifest.url)?;
+    info!("Downloading update to version {} from {}", manifest.version, manifest.url);
+
+    let binary = http::get(&manifest.url)
+        .chain_err(|| format!("Unable to download update {}", manifest.url))?;
+
+    let sha256 = win::sha256(&binary)
+        .chain_err(|| "Unable to hash the downloaded update")?;
+
+    if !sha256.eq_ignore_ascii_case(manifest.sha256.trim()) {
+        bail!("Downloaded update {} has sha256 {}, but the manifest expects {}", manifest.url, sha256, manifest.sha256);
+    }
+
+    let staged = exe.with_extension("exe.new");
+
+    fs::write(&staged, &binary)
+        .chain_err(|| format!("Unable to write update to {:?}", staged))?;
+
+    // the hash only shows the download matches the manifest, the signature shows who built it
+    match authenticode::publisher(&staged) {
+        Ok(ref actual) if actual.eq_ignore_ascii_case(&publisher) => (),
+        Ok(actual) => {
+            let _ = fs::remove_file(&staged);
+            bail!("Downloaded update {} is signed by {:?}, expected {:?}", manifest.url, actual, publisher);
+        },
+        Err(e) => {
+            let _ = fs::remove_file(&staged);
+            return Err(e).chain_err(|| format!("Downloaded update {} has no valid signature", manifest.url));
+        },
+    }
+
+    // a running executable can be renamed but not overwritten
+    fs::rename(&exe, &replaced)
+        .chain_err(|| format!("Unable to move the running binary {:?} aside", exe))?;
+
+    if let Err(e) = fs::rename(&staged, &exe) {
+        let _ = fs::rename(&replaced, &exe);
+        return Err(e).chain_err(|| format!("Unable to move update {:?} into place", staged));
+    }
+
+    info!("Update to version {} is staged and takes effect on the next service restart", manifest.version);
+    Ok(Some(manifest.version))
+}
+
+// checks right away and then every interval, for as long as the service runs
+pub fn spawn_checker(manifest_url: String, publisher: Option<String>, interval: Duration) {
+    let _ = thread::spawn(move || {
+        loop {
+            if let Err(e) = check(&manifest_url, publisher.as_deref()) {
+                warn!("Update check failed: {}", e);
+            }
+
+            thread::sleep(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(is_newer("0.10.0", "0.9.0"));
+        assert!(is_newer("1.0.1", "1.0.0"));
+        assert!(!is_newer("0.9.0", "0.10.0"));
+        assert!(!is_newer("1.2.3", "1.2.3"));
+    }
+
+    #[test]
+    fn ignores_leading_v_and_trailing_zeros() {
+        assert!(is_newer("v1.1", "1.0.9"));
+        assert!(!is_newer("v1.0.0", "1.0"));
+        assert!(!is_newer("1.0", "1.0.0"));
+        assert!(is_newer("1.0.0.1", "1.0"));
+    }
+
+    #[test]
+    fn never_considers_unparsable_versions_newer() {
+        assert!(!is_newer("2.0.0-beta", "1.0.0"));
+        assert!(!is_newer("latest", "1.0.0"));
+        assert!(!is_newer("", "1.0.0"));
+        assert!(!is_newer("2.0.0", "dev"));
+    }
+
+    #[test]
+    fn requires_https_urls() {
+        assert!(require_https("https://example.com/manifest.json").is_ok());
+        assert!(require_https("HTTPS://example.com/manifest.json").is_ok());
+        assert!(require_https("http://example.com/manifest.json").is_err());
+        assert!(require_https("file:///C:/manifest.json").is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use errors::*;
+use paths::ServicePaths;
+use regex::{Captures, Regex};
+use std::env;
+
+// built-in ${NAME} variables usable in config values,
+// so that configs need not hardcode machine specific absolute paths
+#[derive(Debug, Clone)]
+pub struct Variables {
+    values: Vec<(&'static str, String)>,
+
+    // the config profile every load of the config applies
+    profile: Option<String>,
+}
+
+impl Variables {
+    pub fn new(paths: &ServicePaths) -> Variables {
+        let program_data = env::var("ProgramData").unwrap_or_else(|_| r"C:\ProgramData".to_owned());
+
+        Variables {
+            values: vec![
+                ("EXE_DIR", paths.exe_dir.to_string_lossy().into_owned()),
+                ("CONFIG_DIR", paths.config_dir().to_string_lossy().into_owned()),
+                ("SERVICE_NAME", paths.name.clone()),
+                ("PROGRAM_DATA", program_data),
+                ("PROFILE", String::new()),
+            ],
+            profile: None,
+        }
+    }
+
+    // ${PROFILE} is the name of the profile, empty without one
+    pub fn with_profile(mut self, profile: Option<String>) -> Variables {
+        if let Some(value) = self.values.iter_mut().find(|&&mut (var, _)| var == "PROFILE") {
+            value.1 = profile.clone().unwrap_or_default();
+        }
+
+        self.profile = profile;
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter().find(|&&(var, _)| var == name).map(|&(_, ref value)| value.as_str())
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub fn expand(&self, s: &str) -> Result<String> {
+        let re = Regex::new(r"\$\{([^}]*)\}").expect("Invalid variable regex");
+        let mut unknown = None;
+
+        let expanded = re.replace_all(s, |caps: &Captures| {
+            let name = &caps[1];
+
+            match self.values.iter().find(|&&(var, _)| var == name) {
+                Some(&(_, ref value)) => value.clone(),
+                None => {
+                    unknown = Some(name.to_owned());
+                    caps[0].to_owned()
+                },
+            }
+        }).into_owned();
+
+        match unknown {
+            Some(name) => bail!("Unknown variable ${{{}}} in {:?}, known variables are {:?}",
+                name, s, self.values.iter().map(|&(var, _)| var).collect::<Vec<_>>()),
+            None => Ok(expanded),
+        }
+    }
+}
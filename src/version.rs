@@ -0,0 +1,35 @@
+use chrono::{TimeZone, Utc};
+
+// which build of the supervisor runs, so that fleet tooling can audit the hosts, embedded by build.rs
+#[derive(Serialize, Clone, Debug)]
+pub struct BuildInfo {
+    pub version: &'static str,
+
+    // short git commit, unknown if not built from a checkout
+    pub commit: &'static str,
+    pub built_at: String,
+    pub target: &'static str,
+    pub profile: &'static str,
+}
+
+pub fn build_info() -> BuildInfo {
+    let built_at = env!("BUILD_TIMESTAMP").parse::<i64>().ok()
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .map(|built_at| built_at.to_rfc3339())
+        .unwrap_or_default();
+
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("BUILD_COMMIT"),
+        built_at: built_at,
+        target: env!("BUILD_TARGET"),
+        profile: env!("BUILD_PROFILE"),
+    }
+}
+
+// e.g. 0.1.0 (commit 1a2b3c4d5e6f, built 2024-05-01T12:00:00+00:00 for x86_64-pc-windows-msvc, release)
+pub fn describe() -> String {
+    let info = build_info();
+
+    format!("{} (commit {}, built {} for {}, {})", info.version, info.commit, info.built_at, info.target, info.profile)
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &str) -> Stamp {
+    fs::metadata(path).ok()
+        .and_then(|metadata| metadata.modified().ok().map(|modified| (modified, metadata.len())))
+}
+
+// polls the modification time and size of the watched files,
+// a change only counts once the files stop changing between two polls
+// so that a copy still in progress does not trigger anything
+pub struct FileWatcher {
+    paths: Vec<String>,
+    baseline: Vec<Stamp>,
+    pending: Option<Vec<Stamp>>,
+}
+
+impl FileWatcher {
+    pub fn new(paths: &[String]) -> Option<FileWatcher> {
+        if paths.is_empty() {
+            return None;
+        }
+
+        Some(FileWatcher {
+            paths: paths.to_vec(),
+            baseline: paths.iter().map(|path| stamp(path)).collect(),
+            pending: None,
+        })
+    }
+
+    // returns the changed paths once the change has settled
+    pub fn poll(&mut self) -> Option<Vec<String>> {
+        let current = self.paths.iter().map(|path| stamp(path)).collect::<Vec<_>>();
+
+        if current == self.baseline {
+            self.pending = None;
+            return None;
+        }
+
+        if self.pending.as_ref() != Some(&current) {
+            self.pending = Some(current);
+            return None;
+        }
+
+        let changed = self.paths.iter()
+            .zip(self.baseline.iter().zip(current.iter()))
+            .filter(|&(_, (before, after))| before != after)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        self.baseline = current;
+        self.pending = None;
+        Some(changed)
+    }
+}
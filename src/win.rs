@@ -0,0 +1,926 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::mem;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::AsRawHandle;
+use std::path::Path;
+use std::ptr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use winapi::shared::bcrypt::{BCryptCloseAlgorithmProvider, BCryptCreateHash, BCryptDestroyHash, BCryptFinishHash,
+    BCryptHashData, BCryptOpenAlgorithmProvider, BCRYPT_ALG_HANDLE, BCRYPT_HASH_HANDLE, BCRYPT_SHA256_ALGORITHM};
+use winapi::shared::minwindef::{BOOL, BYTE, DWORD, FALSE, FILETIME, HKEY, LPVOID, TRUE, ULONG};
+use winapi::shared::ntdef::{BOOLEAN, NTSTATUS, ULARGE_INTEGER};
+use winapi::shared::winerror::{ERROR_FILE_NOT_FOUND, ERROR_INSUFFICIENT_BUFFER, ERROR_INVALID_PARAMETER, ERROR_SUCCESS};
+use winapi::shared::sddl::{ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+use winapi::um::consoleapi::{GetConsoleMode, SetConsoleCtrlHandler, SetConsoleMode};
+use winapi::um::errhandlingapi::SetErrorMode;
+use winapi::um::fileapi::{GetDiskFreeSpaceExW, GetVolumePathNameW};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject};
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::minwinbase::{SECURITY_ATTRIBUTES, SYSTEMTIME};
+use winapi::um::processthreadsapi::{GetCurrentProcess, GetProcessHandleCount, GetProcessTimes, OpenProcess,
+    OpenProcessToken, TerminateProcess};
+use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+use winapi::um::securitybaseapi::{CheckTokenMembership, CreateWellKnownSid, SetFileSecurityW};
+use winapi::um::stringapiset::MultiByteToWideChar;
+use winapi::um::timezoneapi::{EnumDynamicTimeZoneInformation, SystemTimeToTzSpecificLocalTimeEx,
+    TzSpecificLocalTimeToSystemTimeEx, DYNAMIC_TIME_ZONE_INFORMATION};
+use winapi::um::tlhelp32::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+use winapi::um::userenv::{CreateEnvironmentBlock, DestroyEnvironmentBlock};
+use winapi::um::winbase::{FormatMessageW, LocalFree, LogonUserW, LookupAccountNameW, RegisterWaitForSingleObject, UnregisterWait,
+    LOGON32_LOGON_SERVICE, LOGON32_PROVIDER_DEFAULT, FORMAT_MESSAGE_FROM_HMODULE, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS, INFINITE, SEM_FAILCRITICALERRORS, SEM_NOGPFAULTERRORBOX, SEM_NOOPENFILEERRORBOX};
+use winapi::um::wincon::{AttachConsole, FreeConsole, GenerateConsoleCtrlEvent, CTRL_C_EVENT, ENABLE_ECHO_INPUT};
+use winapi::um::winnt::{DACL_SECURITY_INFORMATION, HANDLE, KEY_SET_VALUE, PROTECTED_DACL_SECURITY_INFORMATION,
+    JobObjectCpuRateControlInformation, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION, JOB_OBJECT_CPU_RATE_CONTROL_ENABLE,
+    JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, LPWSTR, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SET_QUOTA, PROCESS_TERMINATE, PSECURITY_DESCRIPTOR, PSID, PVOID, REG_DWORD, SECURITY_MAX_SID_SIZE, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE, SID_NAME_USE, SYNCHRONIZE,
+    TOKEN_DUPLICATE, TOKEN_IMPERSONATE, TOKEN_QUERY, WELL_KNOWN_SID_TYPE, WT_EXECUTEONLYONCE};
+use winapi::um::winsvc::{ChangeServiceConfigW, CloseServiceHandle, OpenSCManagerW, OpenServiceW, QueryServiceConfigW,
+    LPQUERY_SERVICE_CONFIGW, SC_HANDLE, SC_MANAGER_CONNECT, SERVICE_CHANGE_CONFIG, SERVICE_NO_CHANGE, SERVICE_QUERY_CONFIG,
+    SERVICE_QUERY_STATUS, SERVICE_START, SERVICE_STOP};
+use winapi::um::winnls::GetOEMCP;
+use winapi::um::winreg::{RegCloseKey, RegCreateKeyExW, RegGetValueW, RegSetValueExW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ};
+use winapi::um::wow64apiset::{Wow64DisableWow64FsRedirection, Wow64RevertWow64FsRedirection};
+
+// a process can only be attached to one console at a time
+static CONSOLE_LOCK: Mutex<()> = Mutex::new(());
+
+// the codepage console programs write in unless told otherwise, e.g. 866 on a russian system
+pub fn oem_codepage() -> u32 {
+    unsafe { GetOEMCP() }
+}
+
+// text in the given codepage, invalid sequences replaced like from_utf8_lossy does
+pub fn decode(bytes: &[u8], codepage: u32) -> io::Result<String> {
+    if bytes.is_empty() {
+        return Ok(String::new());
+    }
+
+    unsafe {
+        let len = MultiByteToWideChar(codepage, 0, bytes.as_ptr() as *const i8, bytes.len() as i32, ptr::null_mut(), 0);
+
+        if len == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut wide = vec![0u16; len as usize];
+
+        if MultiByteToWideChar(codepage, 0, bytes.as_ptr() as *const i8, bytes.len() as i32, wide.as_mut_ptr(), len) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(String::from_utf16_lossy(&wide))
+    }
+}
+
+// null terminated utf-16 string for the wide winapi functions
+pub fn to_wide<S: AsRef<OsStr>>(s: S) -> Vec<u16> {
+    s.as_ref().encode_wide().chain(Some(0)).collect()
+}
+
+// asks a console process to exit by sending ctrl-c to every process on its console,
+// which requires temporarily attaching to that console while ignoring the event ourselves
+pub fn send_ctrl_c(pid: u32) -> io::Result<()> {
+    let _lock = CONSOLE_LOCK.lock().unwrap();
+
+    unsafe {
+        if AttachConsole(pid) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        SetConsoleCtrlHandler(None, TRUE);
+
+        let res = if GenerateConsoleCtrlEvent(CTRL_C_EVENT, 0) == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+
+        FreeConsole();
+
+        // the event is delivered asynchronously, and children spawned while
+        // ctrl-c is ignored would inherit that, so only ignore it briefly
+        thread::sleep(Duration::from_millis(100));
+        SetConsoleCtrlHandler(None, FALSE);
+
+        res
+    }
+}
+
+// the environment as freshly defined in the registry for the service account,
+// rather than the one inherited when the service was started
+pub fn fresh_environment() -> io::Result<Vec<(OsString, OsString)>> {
+    unsafe {
+        let mut token: HANDLE = ptr::null_mut();
+
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY | TOKEN_DUPLICATE | TOKEN_IMPERSONATE, &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let vars = environment_of(token);
+        CloseHandle(token);
+        vars
+    }
+}
+
+// the environment as defined in the registry for the user of the token
+pub fn environment_of(token: HANDLE) -> io::Result<Vec<(OsString, OsString)>> {
+    unsafe {
+        let mut block: LPVOID = ptr::null_mut();
+
+        if CreateEnvironmentBlock(&mut block, token, FALSE) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // "name=value" entries, each null terminated, with an empty entry at the end
+        let mut vars = Vec::new();
+        let mut entry = block as *const u16;
+
+        loop {
+            let len = (0..).take_while(|&i| *entry.offset(i) != 0).count();
+
+            if len == 0 {
+                break;
+            }
+
+            let wide = ::std::slice::from_raw_parts(entry, len);
+
+            // skip the hidden per drive entries like "=C:=C:\"
+            if let Some(pos) = wide.iter().skip(1).position(|&c| c == '=' as u16).map(|pos| pos + 1) {
+                vars.push((OsString::from_wide(&wide[..pos]), OsString::from_wide(&wide[pos + 1..])));
+            }
+
+            entry = entry.offset(len as isize + 1);
+        }
+
+        DestroyEnvironmentBlock(block);
+        Ok(vars)
+    }
+}
+
+// reverts the redirection when dropped, on the thread that disabled it
+pub struct Wow64Redirection {
+    old_value: Option<PVOID>,
+}
+
+impl Drop for Wow64Redirection {
+    fn drop(&mut self) {
+        if let Some(old_value) = self.old_value {
+            unsafe {
+                Wow64RevertWow64FsRedirection(old_value);
+            }
+        }
+    }
+}
+
+// lets a 32-bit build reach the real System32 rather than SysWOW64, for the calling thread only,
+// on a 64-bit build there is no redirection and this does nothing
+pub fn disable_wow64_redirection() -> Wow64Redirection {
+    let mut old_value: PVOID = ptr::null_mut();
+
+    let disabled = unsafe { Wow64DisableWow64FsRedirection(&mut old_value) } != 0;
+
+    Wow64Redirection {
+        old_value: if disabled { Some(old_value) } else { None },
+    }
+}
+
+// a security descriptor of an sddl string for creating an object with, freed along with it
+pub struct SecurityAttributes(SECURITY_ATTRIBUTES);
+
+impl SecurityAttributes {
+    pub fn from_sddl(sddl: &str) -> io::Result<SecurityAttributes> {
+        let wide_sddl = to_wide(sddl);
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+
+        unsafe {
+            if ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                wide_sddl.as_ptr(), SDDL_REVISION_1 as u32, &mut descriptor, ptr::null_mut()) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(SecurityAttributes(SECURITY_ATTRIBUTES {
+            nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+            lpSecurityDescriptor: descriptor,
+            bInheritHandle: FALSE,
+        }))
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut SECURITY_ATTRIBUTES {
+        &mut self.0
+    }
+}
+
+impl Drop for SecurityAttributes {
+    fn drop(&mut self) {
+        unsafe {
+            LocalFree(self.0.lpSecurityDescriptor);
+        }
+    }
+}
+
+// whether the token the thread impersonates, or else the process token, has the well known sid enabled,
+// a group that a filtered token only keeps for deny entries does not count
+pub fn is_member(kind: WELL_KNOWN_SID_TYPE) -> bool {
+    // u32 elements keep the sid aligned
+    let mut sid = [0u32; SECURITY_MAX_SID_SIZE / 4];
+    let mut len = SECURITY_MAX_SID_SIZE as DWORD;
+    let mut member: BOOL = FALSE;
+
+    unsafe {
+        CreateWellKnownSid(kind, ptr::null_mut(), sid.as_mut_ptr() as PSID, &mut len) != 0
+            && CheckTokenMembership(ptr::null_mut(), sid.as_mut_ptr() as PSID, &mut member) != 0
+            && member != FALSE
+    }
+}
+
+// replaces the dacl of the file with the one of the sddl string, without inheriting from the directory
+pub fn set_file_dacl(path: &Path, sddl: &str) -> io::Result<()> {
+    let wide_sddl = to_wide(sddl);
+    let wide_path = to_wide(path);
+
+    unsafe {
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+
+        if ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            wide_sddl.as_ptr(), SDDL_REVISION_1 as u32, &mut descriptor, ptr::null_mut()) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let set = SetFileSecurityW(
+            wide_path.as_ptr(), DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION, descriptor);
+
+        let e = io::Error::last_os_error();
+        LocalFree(descriptor);
+
+        if set == 0 {
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+fn check_status(status: NTSTATUS) -> io::Result<()> {
+    if status < 0 {
+        Err(io::Error::new(io::ErrorKind::Other, format!("NTSTATUS {:#010x}", status)))
+    } else {
+        Ok(())
+    }
+}
+
+// lowercase hex sha-256 of the data, using the cng provider of the os
+pub fn sha256(data: &[u8]) -> io::Result<String> {
+    let mut digest = [0u8; 32];
+
+    unsafe {
+        let mut algorithm: BCRYPT_ALG_HANDLE = ptr::null_mut();
+        check_status(BCryptOpenAlgorithmProvider(
+            &mut algorithm, to_wide(BCRYPT_SHA256_ALGORITHM).as_ptr(), ptr::null(), 0))?;
+
+        let mut hash: BCRYPT_HASH_HANDLE = ptr::null_mut();
+
+        let res = check_status(BCryptCreateHash(algorithm, &mut hash, ptr::null_mut(), 0, ptr::null_mut(), 0, 0))
+            .and_then(|_| {
+                let res = check_status(BCryptHashData(hash, data.as_ptr() as *mut u8, data.len() as ULONG, 0))
+                    .and_then(|_| check_status(BCryptFinishHash(hash, digest.as_mut_ptr(), digest.len() as ULONG, 0)));
+
+                BCryptDestroyHash(hash);
+                res
+            });
+
+        BCryptCloseAlgorithmProvider(algorithm, 0);
+        res?;
+    }
+
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+const LOCAL_DUMPS_KEY: &str = r"SOFTWARE\Microsoft\Windows\Windows Error Reporting\LocalDumps";
+
+// minidump, rather than a full dump of the whole process memory
+const DUMP_TYPE_MINI: DWORD = 1;
+
+fn check_reg(status: i32) -> io::Result<()> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(status))
+    }
+}
+
+fn set_reg_value(key: HKEY, name: &str, kind: DWORD, data: &[u8]) -> io::Result<()> {
+    unsafe {
+        check_reg(RegSetValueExW(key, to_wide(name).as_ptr(), 0, kind, data.as_ptr() as *const BYTE, data.len() as DWORD))
+    }
+}
+
+// a string value under HKLM, none if the key or the value does not exist
+pub fn registry_string(subkey: &str, name: &str) -> io::Result<Option<String>> {
+    let wide_subkey = to_wide(subkey);
+    let wide_name = to_wide(name);
+    let mut buf = [0u16; 1024];
+    let mut len = (buf.len() * 2) as DWORD;
+
+    let status = unsafe {
+        RegGetValueW(HKEY_LOCAL_MACHINE, wide_subkey.as_ptr(), wide_name.as_ptr(), RRF_RT_REG_SZ, ptr::null_mut(),
+            buf.as_mut_ptr() as LPVOID, &mut len)
+    };
+
+    if status == ERROR_FILE_NOT_FOUND as i32 {
+        return Ok(None);
+    }
+
+    check_reg(status)?;
+
+    // the length is in bytes and includes the terminating null
+    let chars = (len as usize / 2).min(buf.len());
+    let end = buf[..chars].iter().position(|&c| c == 0).unwrap_or(chars);
+    Ok(Some(String::from_utf16_lossy(&buf[..end])))
+}
+
+// has windows error reporting write a minidump of any crashing process of the executable name into the folder,
+// keeping the most recent `count`, which needs write access to HKLM
+pub fn enable_local_dumps(exe_name: &str, folder: &Path, count: u32) -> io::Result<()> {
+    let subkey = to_wide(format!(r"{}\{}", LOCAL_DUMPS_KEY, exe_name));
+
+    let folder = to_wide(folder).iter()
+        .flat_map(|c| vec![*c as u8, (*c >> 8) as u8])
+        .collect::<Vec<_>>();
+
+    unsafe {
+        let mut key: HKEY = ptr::null_mut();
+
+        check_reg(RegCreateKeyExW(HKEY_LOCAL_MACHINE, subkey.as_ptr(), 0, ptr::null_mut(), REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE, ptr::null_mut(), &mut key, ptr::null_mut()))?;
+
+        let res = set_reg_value(key, "DumpFolder", REG_EXPAND_SZ, &folder)
+            .and_then(|_| set_reg_value(key, "DumpType", REG_DWORD, &DUMP_TYPE_MINI.to_le_bytes()))
+            .and_then(|_| set_reg_value(key, "DumpCount", REG_DWORD, &count.to_le_bytes()));
+
+        RegCloseKey(key);
+        res
+    }
+}
+
+// the system message text of a win32 error, or of an ntstatus, whose messages live in ntdll
+pub fn error_message(code: u32, ntstatus: bool) -> Option<String> {
+    let mut buf = [0u16; 512];
+
+    let len = unsafe {
+        let (flags, module) = if ntstatus {
+            (FORMAT_MESSAGE_FROM_HMODULE, GetModuleHandleW(to_wide("ntdll.dll").as_ptr()))
+        } else {
+            (FORMAT_MESSAGE_FROM_SYSTEM, ptr::null_mut())
+        };
+
+        FormatMessageW(flags | FORMAT_MESSAGE_IGNORE_INSERTS, module as *const _, code, 0,
+            buf.as_mut_ptr(), buf.len() as DWORD, ptr::null_mut())
+    };
+
+    if len == 0 {
+        return None;
+    }
+
+    // messages span several lines at times and end with a line break
+    let message = String::from_utf16_lossy(&buf[..len as usize]);
+    let message = message.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if message.is_empty() {
+        None
+    } else {
+        Some(message)
+    }
+}
+
+// no critical error, crash or missing file dialogs for this process, inherited by children created without
+// CREATE_DEFAULT_ERROR_MODE, the crash is still reported to windows error reporting
+pub fn suppress_error_dialogs() {
+    unsafe {
+        SetErrorMode(SEM_FAILCRITICALERRORS | SEM_NOGPFAULTERRORBOX | SEM_NOOPENFILEERRORBOX);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessUsage {
+    // user and kernel time
+    pub cpu_time: Duration,
+    pub working_set: usize,
+    pub handles: u32,
+}
+
+fn filetime_duration(time: &FILETIME) -> Duration {
+    let ticks = (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+
+    // in 100 ns ticks
+    Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100)
+}
+
+pub fn process_usage(pid: u32) -> io::Result<ProcessUsage> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let usage = (|| {
+            let mut creation: FILETIME = mem::zeroed();
+            let mut exit: FILETIME = mem::zeroed();
+            let mut kernel: FILETIME = mem::zeroed();
+            let mut user: FILETIME = mem::zeroed();
+
+            if GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut memory: PROCESS_MEMORY_COUNTERS = mem::zeroed();
+            memory.cb = mem::size_of::<PROCESS_MEMORY_COUNTERS>() as DWORD;
+
+            if GetProcessMemoryInfo(process, &mut memory, memory.cb) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut handles: DWORD = 0;
+
+            if GetProcessHandleCount(process, &mut handles) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(ProcessUsage {
+                cpu_time: filetime_duration(&kernel) + filetime_duration(&user),
+                working_set: memory.WorkingSetSize,
+                handles: handles,
+            })
+        })();
+
+        CloseHandle(process);
+        usage
+    }
+}
+
+fn creation_time(process: HANDLE) -> io::Result<u64> {
+    unsafe {
+        let mut creation: FILETIME = mem::zeroed();
+        let mut exit: FILETIME = mem::zeroed();
+        let mut kernel: FILETIME = mem::zeroed();
+        let mut user: FILETIME = mem::zeroed();
+
+        if GetProcessTimes(process, &mut creation, &mut exit, &mut kernel, &mut user) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((u64::from(creation.dwHighDateTime) << 32) | u64::from(creation.dwLowDateTime))
+    }
+}
+
+// caps the cpu time of the process, and of the processes it starts from then on, at percent of the whole machine,
+// the job lives for as long as a process in it does, so its handle is not kept
+pub fn limit_cpu_rate(pid: u32, percent: u32) -> io::Result<()> {
+    unsafe {
+        let process = OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, FALSE, pid);
+
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let job = CreateJobObjectW(ptr::null_mut(), ptr::null());
+
+        if job.is_null() {
+            let e = io::Error::last_os_error();
+            CloseHandle(process);
+            return Err(e);
+        }
+
+        // in hundredths of a percent
+        let mut info: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = mem::zeroed();
+        info.ControlFlags = JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+        *info.u.CpuRate_mut() = percent * 100;
+
+        let res = if SetInformationJobObject(job, JobObjectCpuRateControlInformation, &mut info as *mut _ as LPVOID,
+            mem::size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as DWORD) == 0
+            || AssignProcessToJobObject(job, process) == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+
+        CloseHandle(job);
+        CloseHandle(process);
+        res
+    }
+}
+
+// when the process was created, which together with the pid tells it apart from a later one reusing the pid
+pub fn process_created(pid: u32) -> io::Result<u64> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let created = creation_time(process);
+        CloseHandle(process);
+        created
+    }
+}
+
+// terminates the process only if it is still the one created at that time, whether it was
+pub fn terminate_if_created(pid: u32, created: u64, exit_code: u32) -> io::Result<bool> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_TERMINATE, FALSE, pid);
+
+        if process.is_null() {
+            let e = io::Error::last_os_error();
+
+            // long gone
+            return match e.raw_os_error() {
+                Some(code) if code as u32 == ERROR_INVALID_PARAMETER => Ok(false),
+                _ => Err(e),
+            };
+        }
+
+        let res = creation_time(process).and_then(|actual| {
+            if actual != created {
+                Ok(false)
+            } else if TerminateProcess(process, exit_code) == 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(true)
+            }
+        });
+
+        CloseHandle(process);
+        res
+    }
+}
+
+pub struct ProcessEntry {
+    pub pid: u32,
+    pub parent_pid: u32,
+    pub threads: u32,
+    pub exe: String,
+}
+
+// every process running on the machine, the parent pid may belong to an exited process whose pid got reused
+pub fn processes() -> io::Result<Vec<ProcessEntry>> {
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+
+        if snapshot == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut entries = Vec::new();
+        let mut entry: PROCESSENTRY32W = mem::zeroed();
+        entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as DWORD;
+
+        let mut more = Process32FirstW(snapshot, &mut entry) != 0;
+
+        while more {
+            let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+
+            entries.push(ProcessEntry {
+                pid: entry.th32ProcessID,
+                parent_pid: entry.th32ParentProcessID,
+                threads: entry.cntThreads,
+                exe: String::from_utf16_lossy(&entry.szExeFile[..len]),
+            });
+
+            more = Process32NextW(snapshot, &mut entry) != 0;
+        }
+
+        CloseHandle(snapshot);
+        Ok(entries)
+    }
+}
+
+// root of the volume holding the path, e.g. C:\ or the folder a volume is mounted at,
+// which works for paths that do not exist yet
+pub fn volume_of(path: &Path) -> io::Result<String> {
+    let wide_path = to_wide(path);
+    let mut buf = [0u16; 261];
+
+    if unsafe { GetVolumePathNameW(wide_path.as_ptr(), buf.as_mut_ptr(), buf.len() as DWORD) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Ok(String::from_utf16_lossy(&buf[..len]))
+}
+
+// bytes available to the service account on the volume of the directory, honoring disk quotas
+pub fn free_space(dir: &str) -> io::Result<u64> {
+    let wide_dir = to_wide(dir);
+
+    unsafe {
+        let mut available: ULARGE_INTEGER = mem::zeroed();
+
+        if GetDiskFreeSpaceExW(wide_dir.as_ptr(), &mut available, ptr::null_mut(), ptr::null_mut()) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(*available.QuadPart())
+    }
+}
+
+// a windows time zone with its daylight saving rules, as listed by `tzutil /l`
+pub struct TimeZone(DYNAMIC_TIME_ZONE_INFORMATION);
+
+fn to_system_time(time: &NaiveDateTime) -> SYSTEMTIME {
+    SYSTEMTIME {
+        wYear: time.year() as u16,
+        wMonth: time.month() as u16,
+        wDayOfWeek: time.weekday().num_days_from_sunday() as u16,
+        wDay: time.day() as u16,
+        wHour: time.hour() as u16,
+        wMinute: time.minute() as u16,
+        wSecond: time.second() as u16,
+        wMilliseconds: 0,
+    }
+}
+
+fn from_system_time(time: &SYSTEMTIME) -> io::Result<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(time.wYear as i32, time.wMonth as u32, time.wDay as u32)
+        .and_then(|date| date.and_hms_opt(time.wHour as u32, time.wMinute as u32, time.wSecond as u32))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid system time"))
+}
+
+impl TimeZone {
+    // by its key name, e.g. "W. Europe Standard Time"
+    pub fn find(name: &str) -> io::Result<TimeZone> {
+        let mut index = 0;
+
+        loop {
+            let mut zone: DYNAMIC_TIME_ZONE_INFORMATION = unsafe { mem::zeroed() };
+
+            if unsafe { EnumDynamicTimeZoneInformation(index, &mut zone) } != ERROR_SUCCESS {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("Unknown time zone: {}", name)));
+            }
+
+            let len = zone.TimeZoneKeyName.iter().position(|&c| c == 0).unwrap_or(zone.TimeZoneKeyName.len());
+
+            if String::from_utf16_lossy(&zone.TimeZoneKeyName[..len]).eq_ignore_ascii_case(name) {
+                return Ok(TimeZone(zone));
+            }
+
+            index += 1;
+        }
+    }
+
+    // wall clock times in a daylight saving gap are shifted by the gap
+    pub fn to_utc(&self, local: &NaiveDateTime) -> io::Result<NaiveDateTime> {
+        let mut utc: SYSTEMTIME = unsafe { mem::zeroed() };
+
+        if unsafe { TzSpecificLocalTimeToSystemTimeEx(&self.0, &to_system_time(local), &mut utc) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        from_system_time(&utc)
+    }
+
+    pub fn to_local(&self, utc: &NaiveDateTime) -> io::Result<NaiveDateTime> {
+        let mut local: SYSTEMTIME = unsafe { mem::zeroed() };
+
+        if unsafe { SystemTimeToTzSpecificLocalTimeEx(&self.0, &to_system_time(utc), &mut local) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        from_system_time(&local)
+    }
+}
+
+struct ExitWait {
+    process: HANDLE,
+    wait: HANDLE,
+    notify: Option<Box<dyn FnOnce() + Send>>,
+}
+
+unsafe extern "system" fn process_exited(context: PVOID, _: BOOLEAN) {
+    // the registering thread holds the lock until the wait handle is known
+    let (process, wait, notify) = {
+        let exit_wait = &mut *(*(context as *const Mutex<ExitWait>)).lock().unwrap();
+        (exit_wait.process, exit_wait.wait, exit_wait.notify.take())
+    };
+
+    drop(Box::from_raw(context as *mut Mutex<ExitWait>));
+
+    // a wait registered to execute only once must still be unregistered, which must not block in its own callback
+    UnregisterWait(wait);
+    CloseHandle(process);
+
+    if let Some(notify) = notify {
+        notify();
+    }
+}
+
+// calls back on a thread pool thread once the process exited, instead of blocking a thread of its own in the meantime,
+// the pid must belong to a process handle held elsewhere, so that it cannot have been reused
+pub fn notify_exit<F: FnOnce() + Send + 'static>(pid: u32, notify: F) -> io::Result<()> {
+    unsafe {
+        let process = OpenProcess(SYNCHRONIZE, FALSE, pid);
+
+        if process.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let context = Box::into_raw(Box::new(Mutex::new(ExitWait {
+            process: process,
+            wait: ptr::null_mut(),
+            notify: Some(Box::new(notify)),
+        })));
+
+        let mut exit_wait = (*context).lock().unwrap();
+
+        if RegisterWaitForSingleObject(&mut exit_wait.wait, process, Some(process_exited), context as PVOID, INFINITE,
+            WT_EXECUTEONLYONCE) == 0 {
+            let e = io::Error::last_os_error();
+            drop(exit_wait);
+            drop(Box::from_raw(context));
+            CloseHandle(process);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+struct ServiceHandle(SC_HANDLE);
+
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseServiceHandle(self.0);
+        }
+    }
+}
+
+fn open_service(service_name: &str, access: DWORD) -> io::Result<ServiceHandle> {
+    unsafe {
+        let manager = OpenSCManagerW(ptr::null(), ptr::null(), SC_MANAGER_CONNECT);
+
+        if manager.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let manager = ServiceHandle(manager);
+        let service = OpenServiceW(manager.0, to_wide(service_name).as_ptr(), access);
+
+        if service.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(ServiceHandle(service))
+    }
+}
+
+// the account the service logs on as, e.g. LocalSystem or .\svc_app
+pub fn service_account(service_name: &str) -> io::Result<String> {
+    let service = open_service(service_name, SERVICE_QUERY_CONFIG)?;
+
+    unsafe {
+        let mut needed: DWORD = 0;
+
+        if QueryServiceConfigW(service.0, ptr::null_mut(), 0, &mut needed) == 0 {
+            let e = io::Error::last_os_error();
+
+            if e.raw_os_error() != Some(ERROR_INSUFFICIENT_BUFFER as i32) {
+                return Err(e);
+            }
+        }
+
+        // u64 for the alignment of the struct at the start of the buffer
+        let mut buf = vec![0u64; (needed as usize).div_ceil(8)];
+
+        if QueryServiceConfigW(service.0, buf.as_mut_ptr() as LPQUERY_SERVICE_CONFIGW, (buf.len() * 8) as DWORD,
+            &mut needed) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let config = &*(buf.as_ptr() as LPQUERY_SERVICE_CONFIGW);
+        let start_name = config.lpServiceStartName;
+
+        if start_name.is_null() {
+            return Ok(String::new());
+        }
+
+        let len = (0..).find(|&i| *start_name.offset(i) == 0).unwrap_or(0);
+        Ok(String::from_utf16_lossy(::std::slice::from_raw_parts(start_name, len as usize)))
+    }
+}
+
+// whether the caller may query, start and stop the service, as deployments do
+pub fn check_service_control(service_name: &str) -> io::Result<()> {
+    open_service(service_name, SERVICE_QUERY_CONFIG | SERVICE_QUERY_STATUS | SERVICE_START | SERVICE_STOP).map(|_| ())
+}
+
+// the sid of the account as a string like S-1-5-18, failing for an account that does not exist
+pub fn account_sid(account: &str) -> io::Result<String> {
+    let wide_account = to_wide(account);
+
+    unsafe {
+        let mut sid_len: DWORD = 0;
+        let mut domain_len: DWORD = 0;
+        let mut sid_use: SID_NAME_USE = 0;
+
+        LookupAccountNameW(ptr::null(), wide_account.as_ptr(), ptr::null_mut(), &mut sid_len, ptr::null_mut(),
+            &mut domain_len, &mut sid_use);
+
+        if sid_len == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut sid = vec![0u8; sid_len as usize];
+        let mut domain = vec![0u16; domain_len as usize];
+
+        if LookupAccountNameW(ptr::null(), wide_account.as_ptr(), sid.as_mut_ptr() as PSID, &mut sid_len,
+            domain.as_mut_ptr(), &mut domain_len, &mut sid_use) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut string_sid: LPWSTR = ptr::null_mut();
+
+        if ConvertSidToStringSidW(sid.as_mut_ptr() as PSID, &mut string_sid) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let len = (0..).find(|&i| *string_sid.offset(i) == 0).unwrap_or(0);
+        let res = String::from_utf16_lossy(::std::slice::from_raw_parts(string_sid, len as usize));
+        LocalFree(string_sid as PVOID);
+        Ok(res)
+    }
+}
+
+// sets the account and password the service logs on as, which the scm uses from the next start on
+pub fn set_service_credentials(service_name: &str, account: &str, password: &str) -> io::Result<()> {
+    let service = open_service(service_name, SERVICE_CHANGE_CONFIG)?;
+
+    unsafe {
+        if ChangeServiceConfigW(service.0, SERVICE_NO_CHANGE, SERVICE_NO_CHANGE, SERVICE_NO_CHANGE, ptr::null(),
+            ptr::null(), ptr::null_mut(), ptr::null(), to_wide(account).as_ptr(), to_wide(password).as_ptr(),
+            ptr::null()) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// logs the account on like the scm starts a service as it, which fails for a wrong password as well as
+// for an account without the "log on as a service" right, ERROR_LOGON_TYPE_NOT_GRANTED
+pub fn check_service_logon(account: &str, password: &str) -> io::Result<()> {
+    // .\user is a local account, user@domain names the domain itself
+    let (domain, user) = match account.find('\\') {
+        Some(pos) => (Some(&account[..pos]), &account[pos + 1..]),
+        None => (None, account),
+    };
+
+    let wide_domain = domain.map(to_wide);
+
+    unsafe {
+        let mut token: HANDLE = ptr::null_mut();
+
+        if LogonUserW(to_wide(user).as_ptr(), wide_domain.as_ref().map(|domain| domain.as_ptr()).unwrap_or(ptr::null()),
+            to_wide(password).as_ptr(), LOGON32_LOGON_SERVICE, LOGON32_PROVIDER_DEFAULT, &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        CloseHandle(token);
+    }
+
+    Ok(())
+}
+
+// turns off the echo of the console input until dropped, e.g. while a password is typed
+pub struct HiddenInput {
+    console: HANDLE,
+    mode: DWORD,
+}
+
+impl Drop for HiddenInput {
+    fn drop(&mut self) {
+        unsafe {
+            SetConsoleMode(self.console, self.mode);
+        }
+    }
+}
+
+// none when the input is no console, e.g. piped in by a script
+pub fn hide_console_input() -> Option<HiddenInput> {
+    let console = io::stdin().as_raw_handle() as HANDLE;
+
+    unsafe {
+        let mut mode: DWORD = 0;
+
+        if GetConsoleMode(console, &mut mode) == 0 || SetConsoleMode(console, mode & !ENABLE_ECHO_INPUT) == 0 {
+            return None;
+        }
+
+        Some(HiddenInput { console: console, mode: mode })
+    }
+}